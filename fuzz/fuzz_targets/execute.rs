@@ -0,0 +1,92 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// fuzz/fuzz_targets/execute.rs file
+//
+
+#![no_main]
+
+use chip8::config;
+use chip8::harness::TestMachine;
+use chip8::keypad::VKeys;
+use chip8::quirks::MemoryIncrement;
+use chip8::{Quirks, Variant};
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+/// Most instructions a single run executes.
+const MAX_STEPS: u16 = 4096;
+
+/// A machine in the middle of running something: any registers, any PC
+/// up to the last byte of memory, a stack that may be full, any quirks
+/// and keys, and code both in the ROM and wherever the patch lands.
+#[derive(Debug, Arbitrary)]
+struct Input {
+    variant:    u8,
+    quirks:     u8,
+    v:          [u8; config::CHIP8_REGISTERS_NUM],
+    i:          u16,
+    pc:         u16,
+    stack:      Vec<u16>,
+    dt:         u8,
+    keys:       u16,
+    patch_addr: u16,
+    patch:      Vec<u8>,
+    rom:        Vec<u8>,
+    steps:      u16,
+}
+
+fuzz_target!(|input: Input| {
+    let variant = match input.variant % 3 {
+        0 => Variant::Chip8,
+        1 => Variant::SuperChip,
+        _ => Variant::XoChip,
+    };
+    let Ok(mut machine) = TestMachine::new(&input.rom, variant) else {
+        return;
+    };
+    machine.set_quirks(Quirks {
+        shift_uses_vy:      input.quirks & 0x01 != 0,
+        memory_increment:   match input.quirks >> 1 & 0b11 {
+            0 => MemoryIncrement::None,
+            1 => MemoryIncrement::X,
+            _ => MemoryIncrement::XPlusOne,
+        },
+        jump_uses_vx:       input.quirks & 0x08 != 0,
+        logic_resets_vf:    input.quirks & 0x10 != 0,
+        clip_sprites:       input.quirks & 0x20 != 0,
+        display_wait:       input.quirks & 0x40 != 0,
+    });
+
+    let chip8 = &mut machine.cpu.chip8;
+    let ram = &mut chip8.memory.ram;
+    for (offset, byte) in input.patch.iter().enumerate() {
+        if let Some(slot) = ram.get_mut(input.patch_addr as usize + offset) {
+            *slot = *byte;
+        }
+    }
+    chip8.registers.pc = (input.pc as usize % ram.len()) as u16;
+    chip8.registers.v = input.v;
+    chip8.registers.i = input.i;
+    chip8.registers.dt = input.dt;
+    let depth = input.stack.len().min(config::CHIP8_STACK_SIZE);
+    chip8.stack.stack[..depth].copy_from_slice(&input.stack[..depth]);
+    chip8.registers.sp = depth;
+    for n in 0..16 {
+        if let Some(key) = VKeys::from_num(n) {
+            chip8.keypad.set_key(key, input.keys & (1 << n) != 0);
+        }
+    }
+
+    // every step has to return, Fx0A without a key pressed included
+    for step in 0..input.steps.min(MAX_STEPS) {
+        if step % 16 == 15 {
+            machine.cpu.tick_timers();
+        }
+        if machine.cpu.step().is_err() {
+            break;
+        }
+    }
+});
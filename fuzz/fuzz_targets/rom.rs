@@ -0,0 +1,33 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// fuzz/fuzz_targets/rom.rs file
+//
+
+#![no_main]
+
+use chip8::disasm;
+use chip8::harness::TestMachine;
+use chip8::{Quirks, Variant};
+use libfuzzer_sys::fuzz_target;
+
+/// Frames to run every ROM for, enough for loops and subroutines to get
+/// going while keeping a run well under a second.
+const FRAMES: usize = 30;
+
+// Load arbitrary bytes as a ROM and run them on every variant, the way the
+// frontend runs whatever file it's given. Errors are fine, panics are not,
+// and with the frames bounded every run has to finish.
+fuzz_target!(|rom: &[u8]| {
+    let _ = disasm::disassemble_program(rom, 0x200, Variant::guess(rom));
+    for variant in [Variant::Chip8, Variant::SuperChip, Variant::XoChip] {
+        let Ok(mut machine) = TestMachine::new(rom, variant) else {
+            continue;
+        };
+        machine.set_quirks(Quirks::for_variant(variant));
+        machine.press(rom.first().map_or(0, |byte| byte & 0xF));
+        let _ = machine.run_frames(FRAMES);
+    }
+});
@@ -0,0 +1,444 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// asm.rs file
+//
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// How deep includes may nest, deeper is most likely a file including itself.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// An assembly error and the source line it happened on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// The included file the line is in, `None` for the main source.
+    pub file:       Option<String>,
+    pub line:       usize,
+    pub message:    String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file)  => write!(f, "{}:{}: {}", file, self.line, self.message),
+            None        => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(String),
+    Value(String),
+}
+
+/// A line of source after the includes were pulled in.
+struct SourceLine {
+    file:   Option<String>,
+    line:   usize,
+    text:   String,
+}
+
+struct Statement {
+    file:       Option<String>,
+    line:       usize,
+    mnemonic:   String,
+    operands:   Vec<Operand>,
+}
+
+impl Statement {
+    /// Size in bytes of what the statement assembles to.
+    fn size(&self) -> usize {
+        match self.mnemonic.as_str() {
+            "DB" => self.operands.len(),
+            "DW" => self.operands.len() * 2,
+            _ if self.operands.iter().any(|operand| matches!(operand, Operand::Long(_))) => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Assemble source written in the mnemonics the disassembler emits into
+/// a ROM image loaded at 0x200.
+///
+/// ```text
+/// SPEED = 2                   ; constants, also `SPEED EQU 2`
+/// start:                      ; labels
+///     LD I, ball
+///     ADD V0, SPEED + 1       ; + and - work on numbers, labels and constants
+///     JP start
+/// ball:
+///     DB 0b11000000, 0xC0     ; data bytes, DW for 16 bit words
+/// include "levels.asm"        ; only with assemble_file
+/// ```
+///
+/// Comments start with `;`. Numbers are decimal, hex (0x1F, #1F, $1F) or
+/// binary (0b101, %101).
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    expand(source, None, None, 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+/// Assemble a source file, `include` paths are relative to the file that
+/// includes them.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let source = std::fs::read_to_string(path).map_err(|error| AsmError {
+        file:       Some(path.display().to_string()),
+        line:       0,
+        message:    error.to_string(),
+    })?;
+    let mut lines = Vec::new();
+    expand(&source, Some(path), Some(path.display().to_string()), 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+/// Split the source into lines, replacing `include "file"` lines with the
+/// lines of the file.
+fn expand(source: &str, path: Option<&Path>, file: Option<String>, depth: usize, out: &mut Vec<SourceLine>) -> Result<(), AsmError> {
+    for (index, raw_line) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| AsmError { file: file.clone(), line, message };
+        let text = raw_line.split(';').next().unwrap_or("").trim();
+        let included = match text.split_once(char::is_whitespace) {
+            Some((directive, rest)) if directive.eq_ignore_ascii_case("include") => rest.trim(),
+            _ => {
+                out.push(SourceLine { file: file.clone(), line, text: text.to_string() });
+                continue;
+            },
+        };
+        let name = included.strip_prefix('"').and_then(|name| name.strip_suffix('"'))
+            .ok_or_else(|| error("include needs a file name in quotes".to_string()))?;
+        let Some(path) = path else {
+            return Err(error("include is only possible when assembling a file".to_string()));
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(error(format!("includes nested more than {} deep", MAX_INCLUDE_DEPTH)));
+        }
+        let included_path = path.parent().unwrap_or(Path::new("")).join(name);
+        let source = std::fs::read_to_string(&included_path).map_err(|io_error| error(format!("{}: {}", included_path.display(), io_error)))?;
+        expand(&source, Some(&included_path), Some(included_path.display().to_string()), depth + 1, out)?;
+    }
+    Ok(())
+}
+
+fn assemble_lines(lines: &[SourceLine]) -> Result<Vec<u8>, AsmError> {
+    let mut symbols = Symbols::default();
+    let mut statements = Vec::new();
+    let mut addr: usize = 0x200;
+
+    // first pass: find the labels, the constants and the address of every statement
+    for source_line in lines.iter() {
+        let error = |message: String| AsmError { file: source_line.file.clone(), line: source_line.line, message };
+        let mut text = source_line.text.as_str();
+        while let Some((label, rest)) = split_label(text) {
+            symbols.define(label, Symbol::Label(addr as u16)).map_err(error)?;
+            text = rest;
+        }
+        if text.is_empty() {
+            continue;
+        }
+        if let Some((name, value)) = split_constant(text) {
+            symbols.define(name, Symbol::Constant(value.to_string())).map_err(error)?;
+            continue;
+        }
+        let (mnemonic, operand_text) = match text.split_once(char::is_whitespace) {
+            Some((mnemonic, rest))  => (mnemonic, rest.trim()),
+            None                    => (text, ""),
+        };
+        let operands = if operand_text.is_empty() {
+            Vec::new()
+        } else {
+            operand_text.split(',').map(|operand| parse_operand(operand.trim())).collect::<Result<Vec<_>, _>>()
+                .map_err(error)?
+        };
+        let statement = Statement {
+            file:       source_line.file.clone(),
+            line:       source_line.line,
+            mnemonic:   mnemonic.to_ascii_uppercase(),
+            operands,
+        };
+        addr += statement.size();
+        statements.push(statement);
+    }
+    if addr > 0x10000 {
+        let last = lines.last();
+        return Err(AsmError {
+            file:       last.and_then(|line| line.file.clone()),
+            line:       last.map_or(0, |line| line.line),
+            message:    format!("program doesn't fit into memory, it ends at {:#X}", addr),
+        });
+    }
+
+    // second pass: encode
+    let mut rom = Vec::new();
+    for statement in statements.iter() {
+        encode(statement, &symbols, &mut rom).map_err(|message| AsmError { file: statement.file.clone(), line: statement.line, message })?;
+    }
+    Ok(rom)
+}
+
+enum Symbol {
+    Label(u16),
+    /// The expression is evaluated where the constant is used, so it may
+    /// refer to labels further down.
+    Constant(String),
+}
+
+#[derive(Default)]
+struct Symbols {
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Symbols {
+    fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), String> {
+        if self.symbols.insert(name.to_string(), symbol).is_some() {
+            return Err(format!("{} defined twice", name));
+        }
+        Ok(())
+    }
+
+    /// Evaluate a sum of numbers, labels and constants.
+    fn evaluate(&self, text: &str, depth: usize) -> Result<i64, String> {
+        if depth > self.symbols.len() {
+            return Err(format!("constant defined in terms of itself: {}", text));
+        }
+        let mut total: i64 = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+        for c in text.chars().chain(std::iter::once('+')) {
+            if (c == '+' || c == '-') && !term.trim().is_empty() {
+                total += sign * self.term(term.trim(), depth)?;
+                term.clear();
+                sign = if c == '+' { 1 } else { -1 };
+            } else if c == '-' && term.trim().is_empty() {
+                sign = -sign;
+            } else if c != '+' {
+                term.push(c);
+            }
+        }
+        Ok(total)
+    }
+
+    fn term(&self, text: &str, depth: usize) -> Result<i64, String> {
+        match self.symbols.get(text) {
+            Some(Symbol::Label(addr))       => return Ok(*addr as i64),
+            Some(Symbol::Constant(value))   => return self.evaluate(value, depth + 1),
+            None                            => (),
+        }
+        let lower = text.to_ascii_lowercase();
+        let parsed = if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')).or_else(|| lower.strip_prefix('$')) {
+            i64::from_str_radix(hex, 16)
+        } else if let Some(bin) = lower.strip_prefix("0b").or_else(|| lower.strip_prefix('%')) {
+            i64::from_str_radix(bin, 2)
+        } else {
+            lower.parse::<i64>()
+        };
+        match parsed {
+            Ok(value)                       => Ok(value),
+            Err(_) if is_identifier(text)   => Err(format!("unknown label or constant: {}", text)),
+            Err(_)                          => Err(format!("invalid number: {}", text)),
+        }
+    }
+}
+
+/// Split `name: rest` into the label and the rest of the line.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+    let label = label.trim();
+    if is_identifier(label) {
+        Some((label, rest.trim()))
+    } else {
+        None
+    }
+}
+
+/// Split `NAME = value` or `NAME EQU value` into the name and the value.
+fn split_constant(text: &str) -> Option<(&str, &str)> {
+    if let Some((name, value)) = text.split_once('=') {
+        if is_identifier(name.trim()) {
+            return Some((name.trim(), value.trim()));
+        }
+    }
+    let mut words = text.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some(name), Some(equ)) if equ.eq_ignore_ascii_case("equ") && is_identifier(name) => {
+            let value = text[name.len()..].trim_start()[equ.len()..].trim();
+            Some((name, value))
+        },
+        _ => None,
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.'),
+        _ => false,
+    }
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    let upper = text.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I"     => Operand::I,
+        "[I]"   => Operand::IndirectI,
+        "DT"    => Operand::Dt,
+        "ST"    => Operand::St,
+        "K"     => Operand::K,
+        "F"     => Operand::F,
+        "HF"    => Operand::Hf,
+        "B"     => Operand::B,
+        "R"     => Operand::R,
+        _ => {
+            if upper.len() == 2 && upper.starts_with('V') {
+                if let Ok(reg) = u8::from_str_radix(&upper[1..], 16) {
+                    return Ok(Operand::V(reg));
+                }
+            }
+            if upper.starts_with("LONG ") {
+                return Ok(Operand::Long(text[5..].trim().to_string()));
+            }
+            if text.is_empty() {
+                return Err("missing operand".to_string());
+            }
+            Operand::Value(text.to_string())
+        },
+    };
+    Ok(operand)
+}
+
+/// Evaluate an operand and check it fits into `max`. Negative values down
+/// to half the range are stored as two's complement, so `ADD V0, -1` works.
+fn value(text: &str, symbols: &Symbols, max: u16) -> Result<u16, String> {
+    let value = symbols.evaluate(text, 0)?;
+    if value > max as i64 || value < -(max as i64 + 1) / 2 {
+        return Err(format!("{} doesn't fit, the maximum is {:#X}", text, max));
+    }
+    Ok(value as u16 & max)
+}
+
+fn encode(statement: &Statement, symbols: &Symbols, rom: &mut Vec<u8>) -> Result<(), String> {
+    use Operand::*;
+
+    let addr = |text: &str| value(text, symbols, 0xFFF);
+    let byte = |text: &str| value(text, symbols, 0xFF);
+    let nibble = |text: &str| value(text, symbols, 0xF);
+    let xy = |x: u8, y: u8| ((x as u16) << 8) | ((y as u16) << 4);
+    let vx = |x: u8| (x as u16) << 8;
+
+    if let "DB" | "DW" = statement.mnemonic.as_str() {
+        if statement.operands.is_empty() {
+            return Err(format!("{} needs at least one value", statement.mnemonic));
+        }
+        for operand in statement.operands.iter() {
+            let Value(text) = operand else {
+                return Err(format!("invalid data: {}", describe(statement)));
+            };
+            if statement.mnemonic == "DB" {
+                rom.push(byte(text)? as u8);
+            } else {
+                rom.extend_from_slice(&value(text, symbols, 0xFFFF)?.to_be_bytes());
+            }
+        }
+        return Ok(());
+    }
+
+    let word = match (statement.mnemonic.as_str(), statement.operands.as_slice()) {
+        ("CLS", [])                     => 0x00E0,
+        ("RET", [])                     => 0x00EE,
+        ("SCD", [Value(n)])             => 0x00C0 | nibble(n)?,
+        ("SCU", [Value(n)])             => 0x00D0 | nibble(n)?,
+        ("SCR", [])                     => 0x00FB,
+        ("SCL", [])                     => 0x00FC,
+        ("EXIT", [])                    => 0x00FD,
+        ("LOW", [])                     => 0x00FE,
+        ("HIGH", [])                    => 0x00FF,
+        ("SYS", [Value(a)])             => addr(a)?,
+        ("JP", [Value(a)])              => 0x1000 | addr(a)?,
+        ("JP", [V(0), Value(a)])        => 0xB000 | addr(a)?,
+        ("CALL", [Value(a)])            => 0x2000 | addr(a)?,
+        ("SE", [V(x), Value(k)])        => 0x3000 | vx(*x) | byte(k)?,
+        ("SNE", [V(x), Value(k)])       => 0x4000 | vx(*x) | byte(k)?,
+        ("SE", [V(x), V(y)])            => 0x5000 | xy(*x, *y),
+        ("SAVE", [V(x), V(y)])          => 0x5002 | xy(*x, *y),
+        ("LOAD", [V(x), V(y)])          => 0x5003 | xy(*x, *y),
+        ("LD", [V(x), Value(k)])        => 0x6000 | vx(*x) | byte(k)?,
+        ("ADD", [V(x), Value(k)])       => 0x7000 | vx(*x) | byte(k)?,
+        ("LD", [V(x), V(y)])            => 0x8000 | xy(*x, *y),
+        ("OR", [V(x), V(y)])            => 0x8001 | xy(*x, *y),
+        ("AND", [V(x), V(y)])           => 0x8002 | xy(*x, *y),
+        ("XOR", [V(x), V(y)])           => 0x8003 | xy(*x, *y),
+        ("ADD", [V(x), V(y)])           => 0x8004 | xy(*x, *y),
+        ("SUB", [V(x), V(y)])           => 0x8005 | xy(*x, *y),
+        ("SHR", [V(x)])                 => 0x8006 | xy(*x, *x),
+        ("SHR", [V(x), V(y)])           => 0x8006 | xy(*x, *y),
+        ("SUBN", [V(x), V(y)])          => 0x8007 | xy(*x, *y),
+        ("SHL", [V(x)])                 => 0x800E | xy(*x, *x),
+        ("SHL", [V(x), V(y)])           => 0x800E | xy(*x, *y),
+        ("SNE", [V(x), V(y)])           => 0x9000 | xy(*x, *y),
+        ("LD", [I, Value(a)])           => 0xA000 | addr(a)?,
+        ("LD", [I, Long(a)])            => {
+            rom.extend_from_slice(&[0xF0, 0x00]);
+            rom.extend_from_slice(&value(a, symbols, 0xFFFF)?.to_be_bytes());
+            return Ok(());
+        },
+        ("RND", [V(x), Value(k)])       => 0xC000 | vx(*x) | byte(k)?,
+        ("DRW", [V(x), V(y), Value(n)]) => 0xD000 | xy(*x, *y) | nibble(n)?,
+        ("SKP", [V(x)])                 => 0xE09E | vx(*x),
+        ("SKNP", [V(x)])                => 0xE0A1 | vx(*x),
+        ("PLANE", [Value(n)])           => 0xF001 | (nibble(n)? << 8),
+        ("AUDIO", [])                   => 0xF002,
+        ("LD", [V(x), Dt])              => 0xF007 | vx(*x),
+        ("LD", [V(x), K])               => 0xF00A | vx(*x),
+        ("LD", [Dt, V(x)])              => 0xF015 | vx(*x),
+        ("LD", [St, V(x)])              => 0xF018 | vx(*x),
+        ("ADD", [I, V(x)])              => 0xF01E | vx(*x),
+        ("LD", [F, V(x)])               => 0xF029 | vx(*x),
+        ("LD", [Hf, V(x)])              => 0xF030 | vx(*x),
+        ("LD", [B, V(x)])               => 0xF033 | vx(*x),
+        ("PITCH", [V(x)])               => 0xF03A | vx(*x),
+        ("LD", [IndirectI, V(x)])       => 0xF055 | vx(*x),
+        ("LD", [V(x), IndirectI])       => 0xF065 | vx(*x),
+        ("LD", [R, V(x)])               => 0xF075 | vx(*x),
+        ("LD", [V(x), R])               => 0xF085 | vx(*x),
+        _ => return Err(format!("invalid instruction: {}", describe(statement))),
+    };
+    rom.extend_from_slice(&word.to_be_bytes());
+    Ok(())
+}
+
+fn describe(statement: &Statement) -> String {
+    let operands: Vec<String> = statement.operands.iter().map(|operand| match operand {
+        Operand::V(x)       => format!("V{:X}", x),
+        Operand::I          => "I".to_string(),
+        Operand::IndirectI  => "[I]".to_string(),
+        Operand::Dt         => "DT".to_string(),
+        Operand::St         => "ST".to_string(),
+        Operand::K          => "K".to_string(),
+        Operand::F          => "F".to_string(),
+        Operand::Hf         => "HF".to_string(),
+        Operand::B          => "B".to_string(),
+        Operand::R          => "R".to_string(),
+        Operand::Long(a)    => format!("LONG {}", a),
+        Operand::Value(a)   => a.clone(),
+    }).collect();
+    format!("{} {}", statement.mnemonic, operands.join(", ")).trim().to_string()
+}
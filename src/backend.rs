@@ -0,0 +1,183 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// backend.rs file
+//
+
+use crate::config;
+use crate::display::Display;
+use crate::keypad::{Keypad, VKeys};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Something that can show the contents of the display.
+pub trait DisplaySink {
+    fn present(&mut self, display: &Display);
+}
+
+/// Frontend keys that control the interpreter rather than the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    SpeedUp,
+    SlowDown,
+    /// Save the machine state to the numbered slot.
+    SaveState(u8),
+    /// Restore the machine state from the numbered slot.
+    LoadState(u8),
+    /// Held down: go back one frame instead of running one.
+    Rewind,
+    /// Stop in the debugger before the next instruction.
+    Break,
+}
+
+/// Something that knows which keys of the hex keypad are held down.
+pub trait KeypadSource {
+    /// Update the keypad with the currently pressed keys.
+    fn poll_keys(&mut self, keypad: &mut Keypad);
+
+    /// Return true once the user asked to stop the machine.
+    fn quit_requested(&mut self) -> bool {
+        false
+    }
+
+    /// Return the hotkeys pressed since the last call.
+    fn poll_hotkeys(&mut self) -> Vec<Hotkey> {
+        Vec::new()
+    }
+}
+
+/// Something that can play the buzzer.
+pub trait AudioSink {
+    /// Called with the sound timer whenever it may have changed, the
+    /// buzzer sounds while it is above zero.
+    fn check_st(&mut self, st: u8);
+
+    /// XO-CHIP programs replace the buzzer tone with a 128 bit sample
+    /// played at `4000 * 2^((pitch - 64) / 48)` bits per second. Backends
+    /// that can't play samples keep their tone.
+    fn set_pattern(&mut self, _pattern: &[u8; config::XOCHIP_AUDIO_PATTERN_LEN], _pitch: u8) {}
+}
+
+/// Display that throws every frame away.
+pub struct NullDisplay;
+
+impl DisplaySink for NullDisplay {
+    fn present(&mut self, _display: &Display) {}
+}
+
+/// Keypad backend that leaves the keypad alone, for hosts that set the
+/// keys on the machine directly.
+pub struct NullKeypad;
+
+impl KeypadSource for NullKeypad {
+    fn poll_keys(&mut self, _keypad: &mut Keypad) {}
+}
+
+/// Buzzer that never makes a sound.
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn check_st(&mut self, _st: u8) {}
+}
+
+/// Display that keeps a copy of the last presented frame. Clones share
+/// the same frame, so keep one around after handing the other to the
+/// machine.
+#[derive(Clone, Default)]
+pub struct MemoryDisplay {
+    state: Rc<RefCell<(Option<Display>, usize)>>,
+}
+
+impl MemoryDisplay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The last presented frame, if any was presented yet.
+    pub fn last_frame(&self) -> Option<Display> {
+        self.state.borrow().0.clone()
+    }
+
+    /// Number of frames presented so far.
+    pub fn frames(&self) -> usize {
+        self.state.borrow().1
+    }
+}
+
+impl DisplaySink for MemoryDisplay {
+    fn present(&mut self, display: &Display) {
+        let mut state = self.state.borrow_mut();
+        state.0 = Some(display.clone());
+        state.1 += 1;
+    }
+}
+
+/// Keypad whose keys are pressed and released by the host. Clones share
+/// the same keys.
+#[derive(Clone, Default)]
+pub struct MemoryKeypad {
+    state: Rc<RefCell<([bool; config::CHIP8_KEYBOARD_SIZE], bool)>>,
+}
+
+impl MemoryKeypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_key(&self, n: u8, pressed: bool) {
+        self.state.borrow_mut().0[(n & 0xF) as usize] = pressed;
+    }
+
+    pub fn request_quit(&self) {
+        self.state.borrow_mut().1 = true;
+    }
+}
+
+impl KeypadSource for MemoryKeypad {
+    fn poll_keys(&mut self, keypad: &mut Keypad) {
+        let keys = self.state.borrow().0;
+        for (n, pressed) in keys.iter().enumerate() {
+            if let Some(key) = VKeys::from_num(n as u8) {
+                keypad.set_key(key, *pressed);
+            }
+        }
+    }
+
+    fn quit_requested(&mut self) -> bool {
+        self.state.borrow().1
+    }
+}
+
+/// Buzzer that only counts how often it started sounding. Clones share
+/// the same counters.
+#[derive(Clone, Default)]
+pub struct MemoryAudio {
+    state: Rc<RefCell<(bool, usize)>>,
+}
+
+impl MemoryAudio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sounding(&self) -> bool {
+        self.state.borrow().0
+    }
+
+    pub fn beeps(&self) -> usize {
+        self.state.borrow().1
+    }
+}
+
+impl AudioSink for MemoryAudio {
+    fn check_st(&mut self, st: u8) {
+        let mut state = self.state.borrow_mut();
+        if st > 0 && !state.0 {
+            state.1 += 1;
+        }
+        state.0 = st > 0;
+    }
+}
@@ -6,51 +6,165 @@
 // chip8.rs file
 //
 
-#[path = "config.rs"]
-pub mod config;
-#[path = "memory.rs"]
-pub mod memory;
-#[path = "stack.rs"]
-pub mod stack;
-#[path = "registers.rs"]
-pub mod registers;
-#[path = "io.rs"]
-pub mod io;
-#[path = "speakers.rs"]
-pub mod speakers;
-#[path = "romhandler.rs"]
-pub mod romhandler;
+use crate::memory;
+use crate::stack;
+use crate::registers;
+use crate::display;
+use crate::keypad;
+use crate::romhandler;
+use crate::config;
+use crate::error::Chip8Error;
+use crate::variant::Variant;
+use crate::quirks::Quirks;
+use crate::rng::Rng;
+use crate::backend::{AudioSink, DisplaySink, Hotkey, KeypadSource, NullAudio, NullDisplay, NullKeypad};
 
+/// A data read or write of a byte of RAM made by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr:   usize,
+    pub write:  bool,
+}
+
+/// The complete machine state together with the backends it talks to.
+/// The state itself knows nothing about windows or sound devices, those
+/// are hidden behind the backend traits.
 pub struct Chip8 {
     pub memory:         memory::Memory,
     pub stack:          stack::Stack,
     pub registers:      registers::Registers,
-    pub io:             io::IO,
-    pub speaker:        speakers::Speaker,
+    pub display:        display::Display,
+    pub keypad:         keypad::Keypad,
     pub romcartridge:   romhandler::RomCartridge,
+    pub variant:        Variant,
+    pub quirks:         Quirks,
+    /// SUPER-CHIP RPL user flags, saved and restored by Fx75/Fx85.
+    pub rpl:            [u8; config::CHIP8_REGISTERS_NUM],
+    /// Set once the program executed the SUPER-CHIP exit instruction.
+    pub exited:         bool,
+    /// Set by a sprite draw with the display wait quirk, the rest of the
+    /// frame is skipped.
+    pub vblank_wait:    bool,
+    /// XO-CHIP 1-bit audio sample buffer, loaded by F002.
+    pub audio_pattern:  [u8; config::XOCHIP_AUDIO_PATTERN_LEN],
+    /// XO-CHIP playback rate of the audio pattern, set by Fx3A.
+    pub pitch:          u8,
+    /// Generator behind Cxkk, seeded from the OS unless set.
+    pub rng:            Rng,
+    /// While set, every `read_byte` and `write_byte` is appended, for the
+    /// debugger's watchpoints. Instruction fetches aren't data accesses.
+    pub access_log:     Option<Vec<MemoryAccess>>,
+    pub display_sink:   Box<dyn DisplaySink>,
+    pub keypad_source:  Box<dyn KeypadSource>,
+    pub audio_sink:     Box<dyn AudioSink>,
 }
 
 impl Chip8 {
-    pub fn new(window: crate::minifb::Window, romfile: String) -> Self {
+    /// Build a headless machine, nothing is shown, heard or pressed.
+    pub fn new(romcartridge: romhandler::RomCartridge) -> Self {
+        Self::with_backends(romcartridge, Box::new(NullDisplay), Box::new(NullKeypad), Box::new(NullAudio))
+    }
+
+    pub fn with_backends(
+        romcartridge:   romhandler::RomCartridge,
+        display_sink:   Box<dyn DisplaySink>,
+        keypad_source:  Box<dyn KeypadSource>,
+        audio_sink:     Box<dyn AudioSink>,
+    ) -> Self {
         Self {
             memory:         memory::Memory::new(),
             stack:          stack::Stack::new(),
             registers:      registers::Registers::new(),
-            io:             io::IO::new(window),
-            speaker:        speakers::Speaker::new(),
-            romcartridge:   romhandler::RomCartridge::new(romfile),
+            display:        display::Display::new(),
+            keypad:         keypad::Keypad::new(),
+            romcartridge,
+            variant:        Variant::default(),
+            quirks:         Quirks::default(),
+            rpl:            [0; config::CHIP8_REGISTERS_NUM],
+            exited:         false,
+            vblank_wait:    false,
+            audio_pattern:  [0; config::XOCHIP_AUDIO_PATTERN_LEN],
+            pitch:          config::XOCHIP_DEFAULT_PITCH,
+            rng:            Rng::default(),
+            access_log:     None,
+            display_sink,
+            keypad_source,
+            audio_sink,
+        }
+    }
+
+    /// Show the display on the display backend.
+    pub fn present(&mut self) {
+        self.display_sink.present(&self.display);
+    }
+
+    /// Refresh the keypad from the keypad backend.
+    pub fn poll_keys(&mut self) {
+        self.keypad_source.poll_keys(&mut self.keypad);
+    }
+
+    pub fn quit_requested(&mut self) -> bool {
+        self.keypad_source.quit_requested()
+    }
+
+    pub fn poll_hotkeys(&mut self) -> Vec<Hotkey> {
+        self.keypad_source.poll_hotkeys()
+    }
+
+    /// Let the audio backend know about the current sound timer, and on
+    /// XO-CHIP about the sample buffer too.
+    pub fn update_audio(&mut self) {
+        if self.variant.has_xochip() {
+            self.audio_sink.set_pattern(&self.audio_pattern, self.pitch);
         }
+        self.audio_sink.check_st(self.registers.st);
     }
 
-    pub fn stack_push(&mut self, value: u16) {
-        self.registers.sp = self.registers.sp + 1;
+    pub fn stack_push(&mut self, value: u16) -> Result<(), Chip8Error> {
+        if self.registers.sp >= config::CHIP8_STACK_SIZE {
+            return Err(Chip8Error::StackOverflow);
+        }
         self.stack.stack[self.registers.sp] = value;
+        self.registers.sp += 1;
+        Ok(())
     }
 
-    pub fn stack_pop(&mut self) -> u16 {
-        let result = self.stack.stack[self.registers.sp];
-        self.registers.sp = self.registers.sp - 1;
-        result
+    pub fn stack_pop(&mut self) -> Result<u16, Chip8Error> {
+        if self.registers.sp == 0 {
+            return Err(Chip8Error::StackUnderflow);
+        }
+        self.registers.sp -= 1;
+        Ok(self.stack.stack[self.registers.sp])
+    }
+
+    /// Read a byte of RAM, failing instead of panicking when the address
+    /// is outside of the memory.
+    pub fn read_byte(&mut self, addr: usize) -> Result<u8, Chip8Error> {
+        if let Some(accesses) = self.access_log.as_mut() {
+            accesses.push(MemoryAccess { addr, write: false });
+        }
+        self.peek_byte(addr)
+    }
+
+    /// Read a byte of RAM without it counting as a data access, for
+    /// reading the instruction stream.
+    pub fn peek_byte(&self, addr: usize) -> Result<u8, Chip8Error> {
+        self.memory.ram.get(addr).copied().ok_or(Chip8Error::MemoryOutOfBounds(addr))
+    }
+
+    /// Write a byte of RAM, failing instead of panicking when the address
+    /// is outside of the memory.
+    pub fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), Chip8Error> {
+        if let Some(accesses) = self.access_log.as_mut() {
+            accesses.push(MemoryAccess { addr, write: true });
+        }
+        match self.memory.ram.get_mut(addr) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            },
+            None => Err(Chip8Error::MemoryOutOfBounds(addr)),
+        }
     }
 
 }
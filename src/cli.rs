@@ -0,0 +1,466 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// cli.rs file
+//
+
+use chip8::quirks::Quirks;
+use chip8::rng::RngKind;
+use chip8::variant::Variant;
+
+pub const USAGE: &str = "\
+Usage: chip8 [run] <rom> [options]
+       chip8 disasm <rom> [--variant V] [--linear] [--range START-END] [-o <file>]
+       chip8 asm <source> [--variant V] [-o <file>|-]
+       chip8 info <rom>
+       chip8 test <rom> [--frames N] [--expect <file>] [--save-state <file>] [options]
+       chip8 help
+
+Options for run and test:
+  --speed N|Nhz       instructions per frame, or per second with the hz suffix
+  --variant V         chip8, schip or xochip (default: from the file extension)
+  --quirks Q          quirks preset and/or overrides, e.g. \"schip1.1,clip=false\"
+  --load-state FILE   start from a save state made with the same ROM
+  --seed N            seed the random number generator for a reproducible run
+  --rng KIND          random number generator for Cxkk: xorshift or fontwalk (default: xorshift)
+  --replay FILE       play back a movie recorded with --record
+  --trace FILE        write every executed instruction and the registers after it to FILE
+  --trace-range R,... only trace instructions at these addresses, e.g. 200-2FF,400-41F
+  --trace-last N      only write the last N instructions, when the machine stops or crashes
+
+Options for run:
+  --scale N           size of a low resolution pixel: 2, 4, 8, 16, 32 or 64 (default: 8)
+  --palette C,C[,C,C] colours as rrggbb for off, on, plane 2 and both planes
+  --mute              don't open an audio device
+  --volume N          buzzer volume from 0 to 100 (default: 50)
+  --keymap KEYS       keyboard keys for the hex keys 0 to F (default: x123qweasdzc4rfv)
+  --rewind SECONDS    how much play Backspace can rewind, 0 to turn it off (default: 10)
+  --record FILE       record the keys of every frame into a movie file
+  --debug             start paused in the terminal debugger, F12 in the window breaks into it
+  --gdb PORT          wait for a GDB remote protocol debugger on localhost:PORT
+
+Options for asm:
+  --variant V         for Octo sources (.8o), reject instructions V doesn't have
+
+A <rom> ending in .8o is Octo source and is compiled before it is run.
+
+Options for disasm:
+  --linear            decode every word in order instead of following the code
+  --range START-END   decode the machine's memory (fonts, ROM at 200) from START to END, in hex
+
+Options for test:
+  --frames N          number of frames to run (default: 60, or the whole movie)
+  --expect FILE       compare the final screen with an ASCII dump, '.' off and '#' on
+  --save-state FILE   save the final machine state
+
+Exit codes: 0 success, 1 runtime error, 2 usage error.";
+
+/// How fast to run, as the user wrote it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    PerFrame(usize),
+    Hz(usize),
+}
+
+/// Options shared by everything that runs a ROM.
+#[derive(Debug, Default)]
+pub struct MachineOptions {
+    pub rom:          String,
+    pub speed:        Option<Speed>,
+    pub variant:      Option<Variant>,
+    pub quirks:       Option<String>,
+    pub load_state:   Option<String>,
+    pub seed:         Option<u64>,
+    pub rng:          Option<RngKind>,
+    pub replay:       Option<String>,
+    pub trace:        Option<String>,
+    pub trace_ranges: Vec<(u16, u16)>,
+    pub trace_last:   Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct RunOptions {
+    pub machine:    MachineOptions,
+    pub scale:      usize,
+    pub palette:    Option<[u32; 4]>,
+    pub mute:       bool,
+    pub volume:     u8,
+    pub keymap:     Option<String>,
+    pub rewind:     usize,
+    pub record:     Option<String>,
+    pub debug:      bool,
+    pub gdb:        Option<u16>,
+}
+
+#[derive(Debug)]
+pub struct TestOptions {
+    pub machine:    MachineOptions,
+    pub frames:     Option<usize>,
+    pub expect:     Option<String>,
+    pub save_state: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct DisasmOptions {
+    pub rom:        String,
+    pub variant:    Option<Variant>,
+    pub output:     Option<String>,
+    pub linear:     bool,
+    pub range:      Option<(u16, u16)>,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Run(RunOptions),
+    Disasm(DisasmOptions),
+    Asm { source: String, variant: Option<Variant>, output: Option<String> },
+    Info { rom: String },
+    Test(TestOptions),
+    Help,
+}
+
+/// Parse the arguments without the program name. Errors are usage errors.
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let (command, rest) = match args.first().map(|arg| arg.as_str()) {
+        None                            => return Err("no ROM given".to_string()),
+        Some("help" | "-h" | "--help")  => return Ok(Command::Help),
+        Some("run")                     => ("run", &args[1..]),
+        Some("disasm")                  => ("disasm", &args[1..]),
+        Some("asm")                     => ("asm", &args[1..]),
+        Some("info")                    => ("info", &args[1..]),
+        Some("test")                    => ("test", &args[1..]),
+        // chip8 <rom> is short for chip8 run <rom>
+        Some(_)                         => ("run", args),
+    };
+
+    let mut file: Option<String> = None;
+    let mut machine = MachineOptions::default();
+    let mut run = RunOptions { machine: MachineOptions::default(), scale: 8, palette: None, mute: false, volume: 50, keymap: None, rewind: 10, record: None, debug: false, gdb: None };
+    let mut test = TestOptions { machine: MachineOptions::default(), frames: None, expect: None, save_state: None };
+    let mut output: Option<String> = None;
+    let mut linear = false;
+    let mut range: Option<(u16, u16)> = None;
+
+    let mut args = rest.iter();
+    while let Some(arg) = args.next() {
+        let option = arg.as_str();
+        if !option.starts_with('-') {
+            if file.is_some() {
+                return Err(format!("unexpected argument: {}", arg));
+            }
+            file = Some(arg.clone());
+            continue;
+        }
+        let allowed = match option {
+            "--speed" | "--quirks" | "--load-state"     => matches!(command, "run" | "test"),
+            "--seed" | "--rng" | "--replay"             => matches!(command, "run" | "test"),
+            "--trace" | "--trace-range" | "--trace-last" => matches!(command, "run" | "test"),
+            "--variant"                                 => matches!(command, "run" | "test" | "disasm" | "asm"),
+            "--scale" | "--palette" | "--mute"          => command == "run",
+            "--volume" | "--keymap" | "--rewind"        => command == "run",
+            "--record" | "--debug" | "--gdb"            => command == "run",
+            "--frames" | "--expect" | "--save-state"    => command == "test",
+            "-o" | "--output"                           => matches!(command, "disasm" | "asm"),
+            "--linear" | "--range"                      => command == "disasm",
+            _                                           => false,
+        };
+        if !allowed {
+            return Err(format!("unknown option for {}: {}", command, arg));
+        }
+        match option {
+            "--mute"    => run.mute = true,
+            "--debug"   => run.debug = true,
+            "--linear"  => linear = true,
+            _           => (),
+        }
+        if matches!(option, "--mute" | "--debug" | "--linear") {
+            continue;
+        }
+        let value = args.next().ok_or(format!("{} needs a value", arg))?;
+        match option {
+            "--speed"           => machine.speed = Some(parse_speed(value)?),
+            "--variant"         => machine.variant = Some(value.parse()?),
+            "--quirks"          => {
+                // catch typos here instead of after the window opened
+                Quirks::default().apply(value)?;
+                machine.quirks = Some(value.clone());
+            },
+            "--load-state"      => machine.load_state = Some(value.clone()),
+            "--seed"            => machine.seed = Some(value.parse().map_err(|_| format!("invalid seed: {}", value))?),
+            "--rng"             => machine.rng = Some(value.parse()?),
+            "--replay"          => machine.replay = Some(value.clone()),
+            "--trace"           => machine.trace = Some(value.clone()),
+            "--trace-range"     => {
+                for range in value.split(',') {
+                    machine.trace_ranges.push(parse_range(range)?);
+                }
+            },
+            "--trace-last"      => machine.trace_last = Some(parse_number(value).filter(|n| *n > 0).ok_or(format!("invalid number of instructions: {}", value))?),
+            "--record"          => run.record = Some(value.clone()),
+            "--gdb"             => run.gdb = Some(value.parse().map_err(|_| format!("invalid port: {}", value))?),
+            "--scale"           => run.scale = parse_scale(value)?,
+            "--palette"         => run.palette = Some(parse_palette(value)?),
+            "--volume"          => run.volume = parse_number(value).filter(|volume| *volume <= 100)
+                                        .ok_or(format!("volume must be 0 to 100: {}", value))? as u8,
+            "--keymap"          => run.keymap = Some(parse_keymap(value)?),
+            "--rewind"          => run.rewind = parse_number(value).ok_or(format!("invalid number of seconds: {}", value))?,
+            "--frames"          => test.frames = Some(parse_number(value).ok_or(format!("invalid number of frames: {}", value))?),
+            "--expect"          => test.expect = Some(value.clone()),
+            "--range"           => range = Some(parse_range(value)?),
+            "--save-state"      => test.save_state = Some(value.clone()),
+            _                   => output = Some(value.clone()),
+        }
+    }
+
+    let file = file.ok_or(match command {
+        "asm" => "no source file given".to_string(),
+        _     => "no ROM given".to_string(),
+    })?;
+    if machine.replay.is_some() && (run.record.is_some() || machine.load_state.is_some()) {
+        return Err("--replay can't be combined with --record or --load-state".to_string());
+    }
+    if machine.trace.is_none() && (!machine.trace_ranges.is_empty() || machine.trace_last.is_some()) {
+        return Err("--trace-range and --trace-last need --trace FILE".to_string());
+    }
+    if run.debug && run.gdb.is_some() {
+        return Err("--debug and --gdb are two different debuggers, pick one".to_string());
+    }
+    if run.record.is_some() && machine.load_state.is_some() {
+        return Err("movies start from power on, --record can't be combined with --load-state".to_string());
+    }
+    machine.rom = file.clone();
+    Ok(match command {
+        "run"       => Command::Run(RunOptions { machine, ..run }),
+        "test"      => Command::Test(TestOptions { machine, ..test }),
+        "disasm"    => Command::Disasm(DisasmOptions { rom: file, variant: machine.variant, output, linear, range }),
+        "asm"       => Command::Asm { source: file, variant: machine.variant, output },
+        _           => Command::Info { rom: file },
+    })
+}
+
+fn parse_number(value: &str) -> Option<usize> {
+    value.parse().ok()
+}
+
+fn parse_speed(value: &str) -> Result<Speed, String> {
+    let lower = value.to_ascii_lowercase();
+    let speed = match lower.strip_suffix("hz") {
+        Some(hz) => parse_number(hz).map(Speed::Hz),
+        None     => parse_number(&lower).map(Speed::PerFrame),
+    };
+    match speed {
+        Some(Speed::PerFrame(0) | Speed::Hz(0)) | None => Err(format!("invalid speed: {}", value)),
+        Some(speed) => Ok(speed),
+    }
+}
+
+fn parse_scale(value: &str) -> Result<usize, String> {
+    match parse_number(value) {
+        Some(scale @ (2 | 4 | 8 | 16 | 32 | 64)) => Ok(scale),
+        _ => Err(format!("scale must be 2, 4, 8, 16, 32 or 64: {}", value)),
+    }
+}
+
+/// Parse `START-END`, both in hex.
+fn parse_range(value: &str) -> Result<(u16, u16), String> {
+    let hex = |text: &str| u16::from_str_radix(text.trim().trim_start_matches("0x"), 16).ok();
+    match value.split_once('-').map(|(start, end)| (hex(start), hex(end))) {
+        Some((Some(start), Some(end))) if start <= end => Ok((start, end)),
+        _ => Err(format!("invalid range, expected START-END in hex: {}", value)),
+    }
+}
+
+fn parse_keymap(value: &str) -> Result<String, String> {
+    if value.len() != 16 || !value.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("keymap needs 16 letters or digits: {}", value));
+    }
+    Ok(value.to_string())
+}
+
+/// Parse two to four rrggbb colours, the missing ones keep their default.
+fn parse_palette(value: &str) -> Result<[u32; 4], String> {
+    let mut palette = chip8::io::DEFAULT_PALETTE;
+    let colours: Vec<&str> = value.split(',').map(|colour| colour.trim().trim_start_matches('#')).collect();
+    if colours.len() < 2 || colours.len() > 4 {
+        return Err(format!("palette needs 2 to 4 colours: {}", value));
+    }
+    for (n, colour) in colours.iter().enumerate() {
+        if colour.len() != 6 {
+            return Err(format!("invalid colour: {}", colour));
+        }
+        palette[n] = u32::from_str_radix(colour, 16).map_err(|_| format!("invalid colour: {}", colour))?;
+    }
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &str) -> Result<Command, String> {
+        parse(&args.split_whitespace().map(String::from).collect::<Vec<_>>())
+    }
+
+    fn run(args: &str) -> RunOptions {
+        match parse_args(args) {
+            Ok(Command::Run(options))   => options,
+            result                      => panic!("{}: {:?}", args, result),
+        }
+    }
+
+    fn test(args: &str) -> TestOptions {
+        match parse_args(args) {
+            Ok(Command::Test(options))  => options,
+            result                      => panic!("{}: {:?}", args, result),
+        }
+    }
+
+    fn error(args: &str) -> String {
+        match parse_args(args) {
+            Err(error)  => error,
+            Ok(command) => panic!("{} parsed as {:?}", args, command),
+        }
+    }
+
+    #[test]
+    fn rom_alone_runs_it_with_the_defaults() {
+        let options = run("game.ch8");
+        assert_eq!(options.machine.rom, "game.ch8");
+        assert_eq!(options.machine.variant, None);
+        assert_eq!(options.machine.seed, None);
+        assert_eq!(options.machine.rng, None);
+        assert_eq!((options.scale, options.volume, options.rewind), (8, 50, 10));
+        assert!(!options.mute && !options.debug);
+        assert_eq!(run("run game.ch8").machine.rom, "game.ch8");
+    }
+
+    #[test]
+    fn run_options() {
+        let options = run("run --seed 42 --rng fontwalk game.ch8 --variant schip --quirks schip1.1,clip=false --speed 600Hz \
+            --scale 4 --palette 000000,ffffff,#ff0000 --mute --volume 0 --keymap 0123456789abcdef --rewind 0 --record game.movie --gdb 9000");
+        let machine = &options.machine;
+        assert_eq!(machine.rom, "game.ch8");
+        assert_eq!(machine.seed, Some(42));
+        assert_eq!(machine.rng, Some(RngKind::FontWalk));
+        assert_eq!(machine.variant, Some(Variant::SuperChip));
+        assert_eq!(machine.quirks.as_deref(), Some("schip1.1,clip=false"));
+        assert_eq!(machine.speed, Some(Speed::Hz(600)));
+        assert_eq!(options.scale, 4);
+        assert_eq!(options.palette, Some([0x000000, 0xFFFFFF, 0xFF0000, chip8::io::DEFAULT_PALETTE[3]]));
+        assert!(options.mute);
+        assert_eq!(options.volume, 0);
+        assert_eq!(options.keymap.as_deref(), Some("0123456789abcdef"));
+        assert_eq!(options.rewind, 0);
+        assert_eq!(options.record.as_deref(), Some("game.movie"));
+        assert_eq!(options.gdb, Some(9000));
+
+        assert_eq!(run("game.ch8 --seed 18446744073709551615").machine.seed, Some(u64::MAX));
+        assert_eq!(run("game.ch8 --speed 12").machine.speed, Some(Speed::PerFrame(12)));
+        assert_eq!(run("game.ch8 --variant XO-CHIP").machine.variant, Some(Variant::XoChip));
+        assert_eq!(run("game.ch8 --rng XORSHIFT").machine.rng, Some(RngKind::Xorshift));
+        assert!(run("game.ch8 --debug").debug);
+    }
+
+    #[test]
+    fn test_options() {
+        let options = test("test game.ch8 --frames 300 --expect screen.txt --save-state end.state --seed 7 --replay game.movie \
+            --trace trace.txt --trace-range 200-2ff,0x400-41F --trace-last 100");
+        assert_eq!(options.machine.rom, "game.ch8");
+        assert_eq!(options.frames, Some(300));
+        assert_eq!(options.expect.as_deref(), Some("screen.txt"));
+        assert_eq!(options.save_state.as_deref(), Some("end.state"));
+        assert_eq!(options.machine.seed, Some(7));
+        assert_eq!(options.machine.replay.as_deref(), Some("game.movie"));
+        assert_eq!(options.machine.trace.as_deref(), Some("trace.txt"));
+        assert_eq!(options.machine.trace_ranges, [(0x200, 0x2FF), (0x400, 0x41F)]);
+        assert_eq!(options.machine.trace_last, Some(100));
+    }
+
+    #[test]
+    fn other_commands() {
+        assert!(matches!(parse_args("help"), Ok(Command::Help)));
+        assert!(matches!(parse_args("--help"), Ok(Command::Help)));
+        assert!(matches!(parse_args("info game.ch8"), Ok(Command::Info { rom }) if rom == "game.ch8"));
+        assert!(matches!(parse_args("asm game.8o --variant chip8 -o -"),
+            Ok(Command::Asm { source, variant: Some(Variant::Chip8), output: Some(output) }) if source == "game.8o" && output == "-"));
+        match parse_args("disasm game.ch8 --linear --range 0-1FF --output game.asm") {
+            Ok(Command::Disasm(options)) => {
+                assert_eq!(options.rom, "game.ch8");
+                assert!(options.linear);
+                assert_eq!(options.range, Some((0, 0x1FF)));
+                assert_eq!(options.output.as_deref(), Some("game.asm"));
+            },
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn invalid_values() {
+        let cases = [
+            ("game.ch8 --seed abc",                 "invalid seed: abc"),
+            ("game.ch8 --seed -1",                  "invalid seed: -1"),
+            ("game.ch8 --seed 18446744073709551616", "invalid seed"),
+            ("game.ch8 --rng vip",                  "unknown random number generator: vip"),
+            ("game.ch8 --variant chip9",            "unknown variant: chip9"),
+            ("game.ch8 --quirks schip9",            ""),
+            ("game.ch8 --quirks clip=maybe",        ""),
+            ("game.ch8 --speed 0",                  "invalid speed: 0"),
+            ("game.ch8 --speed 0hz",                "invalid speed: 0hz"),
+            ("game.ch8 --speed fast",               "invalid speed: fast"),
+            ("game.ch8 --scale 3",                  "scale must be"),
+            ("game.ch8 --volume 101",               "volume must be 0 to 100: 101"),
+            ("game.ch8 --keymap x123",              "keymap needs 16 letters or digits"),
+            ("game.ch8 --palette 000000",           "palette needs 2 to 4 colours"),
+            ("game.ch8 --palette 000000,fffffg",    "invalid colour: fffffg"),
+            ("game.ch8 --rewind -1",                "invalid number of seconds"),
+            ("game.ch8 --gdb 70000",                "invalid port: 70000"),
+            ("test game.ch8 --frames x",            "invalid number of frames"),
+            ("test game.ch8 --trace t --trace-last 0", "invalid number of instructions: 0"),
+            ("test game.ch8 --trace t --trace-range 300-200", "invalid range"),
+            ("disasm game.ch8 --range 200",         "invalid range"),
+        ];
+        for (args, message) in cases {
+            let error = error(args);
+            assert!(error.contains(message), "{}: {}", args, error);
+        }
+    }
+
+    #[test]
+    fn conflicting_options() {
+        let cases = [
+            ("game.ch8 --replay m --record n",          "--replay can't be combined"),
+            ("test game.ch8 --replay m --load-state s", "--replay can't be combined"),
+            ("game.ch8 --record m --load-state s",      "--record can't be combined with --load-state"),
+            ("game.ch8 --trace-range 200-210",          "need --trace FILE"),
+            ("test game.ch8 --trace-last 5",            "need --trace FILE"),
+            ("game.ch8 --debug --gdb 9000",             "pick one"),
+        ];
+        for (args, message) in cases {
+            let error = error(args);
+            assert!(error.contains(message), "{}: {}", args, error);
+        }
+    }
+
+    #[test]
+    fn misplaced_arguments() {
+        let cases = [
+            ("",                                "no ROM given"),
+            ("run",                             "no ROM given"),
+            ("asm",                             "no source file given"),
+            ("game.ch8 other.ch8",              "unexpected argument: other.ch8"),
+            ("game.ch8 --seed",                 "--seed needs a value"),
+            ("game.ch8 --frames 10",            "unknown option for run: --frames"),
+            ("test game.ch8 --record m",        "unknown option for test: --record"),
+            ("disasm game.ch8 --seed 1",        "unknown option for disasm: --seed"),
+            ("asm game.8o --linear",            "unknown option for asm: --linear"),
+            ("info game.ch8 --variant chip8",   "unknown option for info: --variant"),
+            ("game.ch8 --sped 10",              "unknown option for run: --sped"),
+        ];
+        for (args, message) in cases {
+            let error = error(args);
+            assert!(error.contains(message), "{:?}: {}", args, error);
+        }
+    }
+}
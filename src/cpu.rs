@@ -6,15 +6,19 @@
 // cpu.rs file
 //
 
-#[path = "config.rs"]
-mod config;
-#[path = "chip8.rs"]
-pub mod chip8;
-
-use rand::Rng;
-use crate::chip8::io::VKeys;
-use std::time::Duration;
-
+use crate::chip8;
+use crate::config;
+use crate::error::Chip8Error;
+use crate::backend::{AudioSink, DisplaySink, KeypadSource};
+use crate::romhandler::RomCartridge;
+use crate::variant::Variant;
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::rng::Rng;
+use crate::scheduler::Scheduler;
+use crate::state;
+use crate::trace::Tracer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Instruction {
     pub instruction: u16,
     pub nibbles:    [u8; 4],
@@ -32,13 +36,13 @@ impl Instruction {
         new_nibbles[1]  =   ((instruction & 0b0000111100000000) >> 8) as u8;
         new_nibbles[2]  =   ((instruction & 0b0000000011110000) >> 4) as u8;
         new_nibbles[3]  =   (instruction & 0b0000000000001111) as u8;
-        let new_nnn     =   (instruction & 0b0000111111111111);         // Example: 1nnn
+        let new_nnn     =   instruction & 0b0000111111111111;           // Example: 1nnn
         let new_n       =   new_nibbles[3];                             // Example: Dxyn
         let new_x       =   new_nibbles[1];                             // Example: Fx07
         let new_y       =   new_nibbles[2];                             // Example: 5xy0
         let new_kk      =   (new_nnn & 0b0000000011111111) as u8;      // Example: 7xkk
         Self {
-            instruction:    instruction,
+            instruction,
             nibbles:        new_nibbles,
             nnn:            new_nnn,
             n:              new_n,
@@ -49,69 +53,224 @@ impl Instruction {
     }
 }
 
+/// What a single call to `Cpu::step` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// The instruction was executed.
+    Executed(Instruction),
+    /// The instruction was Fx0A and no key was down, so the program
+    /// counter stayed on it.
+    WaitingForKey(Instruction),
+    /// The program executed the SUPER-CHIP exit instruction, nothing
+    /// runs anymore.
+    Exited,
+}
+
+/// Watches `Cpu::run_frame_with` execute instructions, e.g. a debugger.
+pub trait StepHook {
+    /// Called before the instruction at PC runs. Return false to end the
+    /// frame right there, without ticking the timers.
+    fn before_step(&mut self, _cpu: &mut Cpu) -> bool {
+        true
+    }
+
+    /// Called after the instruction that was at `pc` ran or failed. An
+    /// error still stops the frame when this returns.
+    fn after_step(&mut self, _cpu: &mut Cpu, _pc: u16, _result: &Result<Step, Chip8Error>) {}
+}
+
+impl StepHook for () {}
+
 pub struct Cpu {
-    pub chip8: chip8::Chip8,
+    pub chip8:  chip8::Chip8,
+    /// Writes down every executed instruction while set.
+    pub tracer: Option<Tracer>,
 }
 
 impl Cpu {
-    pub fn new(window: crate::minifb::Window, romfile: String) -> Self {
+    pub fn new(romcartridge: RomCartridge) -> Self {
         Self {
-            chip8: chip8::Chip8::new(window, romfile),
+            chip8:  chip8::Chip8::new(romcartridge),
+            tracer: None,
         }
     }
 
-    pub fn start_execution(&mut self) {
-        let mut next_instruction = self.get_instruction();
-        let mut hz_devider_counter = 0;
-        self.chip8.io.render();
-        while (self.chip8.registers.pc >= 0x200) && (self.chip8.io.poll_quit() != false) {
-            if self.chip8.registers.pc > 0xFFD {
-                println!("ERROR: The code you're running tried to write out of memory bounds!");
-                while (self.chip8.io.poll_quit() != false) {}
+    pub fn with_backends(
+        romcartridge:   RomCartridge,
+        display_sink:   Box<dyn DisplaySink>,
+        keypad_source:  Box<dyn KeypadSource>,
+        audio_sink:     Box<dyn AudioSink>,
+    ) -> Self {
+        Self {
+            chip8:  chip8::Chip8::with_backends(romcartridge, display_sink, keypad_source, audio_sink),
+            tracer: None,
+        }
+    }
+
+    /// Run in real time at the variant's default speed until the program
+    /// jumps below 0x200, exits or the keypad backend asks to quit. What
+    /// the hotkeys did isn't shown, run a `Scheduler` for that.
+    pub fn start_execution(&mut self) -> Result<(), Chip8Error> {
+        Scheduler::new(self.chip8.variant.default_speed()).run(self, |_| ())
+    }
+
+    /// Fetch, decode and execute exactly one instruction.
+    pub fn step(&mut self) -> Result<Step, Chip8Error> {
+        if self.chip8.exited {
+            return Ok(Step::Exited);
+        }
+        let pc = self.chip8.registers.pc;
+        let instruction = self.get_instruction()?;
+        let result = self.execute_instruction(instruction);
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.record(&self.chip8, pc, &instruction, result.as_ref().err());
+        }
+        result?;
+        if (instruction.instruction & 0xF0FF) == 0xF00A && self.chip8.registers.pc == pc {
+            return Ok(Step::WaitingForKey(instruction));
+        }
+        Ok(Step::Executed(instruction))
+    }
+
+    /// Run one 60 Hz frame: poll the keypad, execute `cycles` instructions,
+    /// tick the timers once and present the display. With the display wait
+    /// quirk the frame ends early after a sprite was drawn.
+    /// Return the number of instructions executed.
+    pub fn run_frame(&mut self, cycles: usize) -> Result<usize, Chip8Error> {
+        self.run_frame_with(cycles, &mut ())
+    }
+
+    /// Run one frame like `run_frame`, calling `hook` around every
+    /// instruction.
+    pub fn run_frame_with(&mut self, cycles: usize, hook: &mut dyn StepHook) -> Result<usize, Chip8Error> {
+        self.chip8.poll_keys();
+        self.chip8.vblank_wait = false;
+        let mut executed = 0;
+        for _ in 0..cycles {
+            if !hook.before_step(self) {
+                return Ok(executed);
+            }
+            let pc = self.chip8.registers.pc;
+            let result = self.step();
+            hook.after_step(self, pc, &result);
+            if result? == Step::Exited {
                 break;
             }
-            if hz_devider_counter == 8 {
-                if self.chip8.registers.dt > 0 {
-                    self.chip8.registers.dt -= 1;
-                }
-                if self.chip8.registers.st > 0 {
-                    self.chip8.registers.st -= 1;
-                    self.chip8.speaker.check_st(self.chip8.registers.st);
-                }
-                hz_devider_counter = 0;
+            executed += 1;
+            if (self.chip8.registers.pc < 0x200) || self.chip8.vblank_wait {
+                break;
             }
-            hz_devider_counter += 1;
-            self.execute_instruction(next_instruction);
-            next_instruction = self.get_instruction();
         }
+        self.tick_timers();
+        self.chip8.present();
+        Ok(executed)
+    }
+
+    /// Decrement the delay and sound timers, called at 60 Hz.
+    pub fn tick_timers(&mut self) {
+        if self.chip8.registers.dt > 0 {
+            self.chip8.registers.dt -= 1;
+        }
+        if self.chip8.registers.st > 0 {
+            self.chip8.registers.st -= 1;
+        }
+        self.chip8.update_audio();
+    }
+
+    pub fn next_inst(&mut self) -> Result<(), Chip8Error> {
+        self.chip8.registers.pc = self.chip8.registers.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    /// Skip the next instruction. On XO-CHIP the four byte F000 NNNN
+    /// instruction is skipped as a whole.
+    pub fn skip_next_inst(&mut self) -> Result<(), Chip8Error> {
+        let next = self.chip8.registers.pc.wrapping_add(2) as usize;
+        if self.chip8.variant.has_xochip() && self.chip8.peek_byte(next)? == 0xF0 && self.chip8.peek_byte(next + 1)? == 0x00 {
+            self.chip8.registers.pc = self.chip8.registers.pc.wrapping_add(6);
+        } else {
+            self.chip8.registers.pc = self.chip8.registers.pc.wrapping_add(4);
+        }
+        Ok(())
     }
 
-    pub fn next_inst(&mut self) {
-        self.chip8.registers.pc = self.chip8.registers.pc + 2;
+    /// Select the instruction set, XO-CHIP also gets its 64 KiB of memory.
+    /// Call it before `load_instructions`.
+    /// The quirks are reset to the variant's usual preset.
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.chip8.variant = variant;
+        self.chip8.quirks = Quirks::for_variant(variant);
+        self.chip8.memory.resize(variant.memory_size());
     }
 
-    pub fn skip_next_inst(&mut self) {
-        self.chip8.registers.pc = self.chip8.registers.pc + 4;
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.chip8.quirks = quirks;
     }
 
-    pub fn load_instructions(&mut self) {
-        self.chip8.memory.ram[0x200..0x1000].copy_from_slice(&self.chip8.romcartridge.rom);
+    /// Replace the random number generator, e.g. with a seeded one for
+    /// reproducible runs.
+    pub fn set_rng(&mut self, rng: Rng) {
+        self.chip8.rng = rng;
     }
 
-    pub fn get_instruction(&mut self) -> Instruction {
-        let instruction: u16 = (self.chip8.memory.ram[self.chip8.registers.pc as usize] as u16) << 8 | (self.chip8.memory.ram[(self.chip8.registers.pc+1) as usize] as u16);
-        Instruction::new(instruction)
+    /// Trace every instruction from now on.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
     }
 
-    pub fn execute_instruction(&mut self, instruction: Instruction) {
+    /// Snapshot the whole machine into the versioned save state format.
+    pub fn save_state(&self) -> Vec<u8> {
+        state::save(&self.chip8)
+    }
+
+    /// Restore a snapshot made by `save_state` with the same ROM loaded.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), Chip8Error> {
+        state::load(&mut self.chip8, bytes)?;
+        self.chip8.present();
+        Ok(())
+    }
+
+    /// Copy the ROM into memory at 0x200.
+    pub fn load_instructions(&mut self) -> Result<(), Chip8Error> {
+        let rom = &self.chip8.romcartridge.rom;
+        let max = self.chip8.memory.ram.len() - 0x200;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max });
+        }
+        self.chip8.memory.ram[0x200..0x200 + rom.len()].copy_from_slice(rom);
+        Ok(())
+    }
+
+    /// Fetch the instruction at the program counter.
+    pub fn get_instruction(&mut self) -> Result<Instruction, Chip8Error> {
+        let pc = self.chip8.registers.pc as usize;
+        if pc + 1 >= self.chip8.memory.ram.len() {
+            return Err(Chip8Error::PcOutOfBounds(self.chip8.registers.pc));
+        }
+        let instruction: u16 = (self.chip8.memory.ram[pc] as u16) << 8 | (self.chip8.memory.ram[pc + 1] as u16);
+        Ok(Instruction::new(instruction))
+    }
+
+    pub fn execute_instruction(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
+        let schip = self.chip8.variant.has_superchip();
+        let xochip = self.chip8.variant.has_xochip();
         match instruction.nibbles {
+            [0x0, 0x0, 0xC, _] if schip         => self.scd_n(instruction.n),
+            [0x0, 0x0, 0xD, _] if xochip        => self.scu_n(instruction.n),
             [0x0, 0x0, 0xE, 0x0]    => self.cls(),
             [0x0, 0x0, 0xE, 0xE]    => self.ret(),
+            [0x0, 0x0, 0xF, 0xB] if schip       => self.scr(),
+            [0x0, 0x0, 0xF, 0xC] if schip       => self.scl(),
+            [0x0, 0x0, 0xF, 0xD] if schip       => self.exit(),
+            [0x0, 0x0, 0xF, 0xE] if schip       => self.low(),
+            [0x0, 0x0, 0xF, 0xF] if schip       => self.high(),
             [0x1, _, _, _]          => self.jump(instruction.nnn),
             [0x2, _, _, _]          => self.call(instruction.nnn),
             [0x3, _, _, _]          => self.se_vx_byte(instruction.x, instruction.kk),
             [0x4, _, _, _]          => self.sne_vx_byte(instruction.x, instruction.kk),
             [0x5, _, _, 0x0]        => self.se_vx_vy(instruction.x, instruction.y),
+            [0x5, _, _, 0x2] if xochip          => self.ld_i_vx_vy(instruction.x, instruction.y),
+            [0x5, _, _, 0x3] if xochip          => self.ld_vx_vy_i(instruction.x, instruction.y),
             [0x6, _, _, _]          => self.ld_vx_byte(instruction.x, instruction.kk),
             [0x7, _, _, _]          => self.add_vx_byte(instruction.x, instruction.kk),
             [0x8, _, _, 0x0]        => self.ld_vx_vy(instruction.x, instruction.y),
@@ -120,9 +279,9 @@ impl Cpu {
             [0x8, _, _, 0x3]        => self.xor_vx_vy(instruction.x, instruction.y),
             [0x8, _, _, 0x4]        => self.add_vx_vy(instruction.x, instruction.y),
             [0x8, _, _, 0x5]        => self.sub_vx_vy(instruction.x, instruction.y),
-            [0x8, _, _, 0x6]        => self.shr_vx(instruction.x),
+            [0x8, _, _, 0x6]        => self.shr_vx(instruction.x, instruction.y),
             [0x8, _, _, 0x7]        => self.subn_vx_vy(instruction.x, instruction.y),
-            [0x8, _, _, 0xE]        => self.shl_vx(instruction.x),
+            [0x8, _, _, 0xE]        => self.shl_vx(instruction.x, instruction.y),
             [0x9, _, _, 0x0]        => self.sne_vx_vy(instruction.x, instruction.y),
             [0xA, _, _, _]          => self.ld_i_addr(instruction.nnn),
             [0xB, _, _, _]          => self.jp_v0_nnn(instruction.nnn),
@@ -130,15 +289,22 @@ impl Cpu {
             [0xD, _, _, _]          => self.drw_vx_vy_n(instruction.x, instruction.y, instruction.n),
             [0xE, _, 0x9, 0xE]      => self.skp_vx(instruction.x),
             [0xE, _, 0xA, 0x1]      => self.sknp_vx(instruction.x),
+            [0xF, 0x0, 0x0, 0x0] if xochip      => self.ld_i_long(),
+            [0xF, _, 0x0, 0x1] if xochip        => self.plane_n(instruction.x),
+            [0xF, 0x0, 0x0, 0x2] if xochip      => self.audio(),
             [0xF, _, 0x0, 0x7]      => self.ld_vx_dt(instruction.x),
             [0xF, _, 0x0, 0xA]      => self.ld_vx_k(instruction.x),
             [0xF, _, 0x1, 0x5]      => self.ld_dt_vx(instruction.x),
             [0xF, _, 0x1, 0x8]      => self.ld_st_vx(instruction.x),
             [0xF, _, 0x1, 0xE]      => self.add_i_vx(instruction.x),
             [0xF, _, 0x2, 0x9]      => self.ld_f_vx(instruction.x),
+            [0xF, _, 0x3, 0x0] if schip         => self.ld_hf_vx(instruction.x),
             [0xF, _, 0x3, 0x3]      => self.ld_b_vx(instruction.x),
+            [0xF, _, 0x3, 0xA] if xochip        => self.pitch_vx(instruction.x),
             [0xF, _, 0x5, 0x5]      => self.ld_i_vx(instruction.x),
             [0xF, _, 0x6, 0x5]      => self.ld_vx_i(instruction.x),
+            [0xF, _, 0x7, 0x5] if schip         => self.ld_r_vx(instruction.x),
+            [0xF, _, 0x8, 0x5] if schip         => self.ld_vx_r(instruction.x),
             _ => self.next_inst(),
         }
     }
@@ -146,252 +312,430 @@ impl Cpu {
     
     // Start of instruction set implementation
 
-    fn cls(&mut self) {
-        self.chip8.io.clear();
-        self.next_inst();
+    fn cls(&mut self) -> Result<(), Chip8Error> {
+        self.chip8.display.clear();
+        self.next_inst()
     }
 
-    fn ret(&mut self) {
-        self.chip8.registers.pc = self.chip8.stack.stack[self.chip8.registers.sp];
-        self.chip8.registers.sp = self.chip8.registers.sp - 1;
-        self.next_inst();
+    /// Return to the address the matching call pushed, the instruction
+    /// after it.
+    fn ret(&mut self) -> Result<(), Chip8Error> {
+        self.chip8.registers.pc = self.chip8.stack_pop()?;
+        Ok(())
     }
 
-    fn jump(&mut self, nnn: u16) {
+    fn jump(&mut self, nnn: u16) -> Result<(), Chip8Error> {
         self.chip8.registers.pc = nnn;
+        Ok(())
     }
 
-    fn call(&mut self, nnn: u16) {
-        self.chip8.registers.sp = self.chip8.registers.sp + 1;
-        self.chip8.stack.stack[self.chip8.registers.sp] = self.chip8.registers.pc;
+    fn call(&mut self, nnn: u16) -> Result<(), Chip8Error> {
+        self.chip8.stack_push(self.chip8.registers.pc.wrapping_add(2))?;
         self.chip8.registers.pc = nnn;
-    } 
+        Ok(())
+    }
 
-    fn se_vx_byte(&mut self, x: u8, kk: u8) {
+    fn se_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), Chip8Error> {
         if self.chip8.registers.v[x as usize] == kk {
-            self.skip_next_inst();
+            self.skip_next_inst()
         } else {
-            self.next_inst();
+            self.next_inst()
         }
     }
 
-    fn sne_vx_byte(&mut self, x: u8, kk: u8) {
+    fn sne_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), Chip8Error> {
         if self.chip8.registers.v[x as usize] != kk {
-            self.skip_next_inst();
+            self.skip_next_inst()
         } else {
-            self.next_inst();
+            self.next_inst()
         }
     }
 
-    fn se_vx_vy(&mut self, x: u8, y: u8) {
+    fn se_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
         if self.chip8.registers.v[x as usize] == self.chip8.registers.v[y as usize] {
-            self.skip_next_inst();
+            self.skip_next_inst()
         } else {
-            self.next_inst();
+            self.next_inst()
         }
     }
 
-    fn ld_vx_byte(&mut self, x: u8, kk: u8) {
+    fn ld_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), Chip8Error> {
         self.chip8.registers.v[x as usize] = kk;
-        self.next_inst();
+        self.next_inst()
     }
 
-    fn add_vx_byte(&mut self, x: u8, kk: u8) {
+    fn add_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), Chip8Error> {
         self.chip8.registers.v[x as usize] = self.chip8.registers.v[x as usize].overflowing_add(kk).0;
-        self.next_inst();
+        self.next_inst()
     }
 
-    fn ld_vx_vy(&mut self, x: u8, y: u8) {
+    fn ld_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
         self.chip8.registers.v[x as usize] = self.chip8.registers.v[y as usize];
-        self.next_inst();
+        self.next_inst()
     }
 
-    fn or_vx_vy(&mut self, x: u8, y: u8) {
+    fn or_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
         self.chip8.registers.v[x as usize] |= self.chip8.registers.v[y as usize];
-        self.next_inst();
+        self.logic_vf_reset();
+        self.next_inst()
     }
 
-    fn and_vx_vy(&mut self, x: u8, y: u8) {
+    fn and_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
         self.chip8.registers.v[x as usize] &= self.chip8.registers.v[y as usize];
-        self.next_inst();
+        self.logic_vf_reset();
+        self.next_inst()
     }
 
-    fn xor_vx_vy(&mut self, x: u8, y: u8) {
+    fn xor_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
         self.chip8.registers.v[x as usize] ^= self.chip8.registers.v[y as usize];
-        self.next_inst();
+        self.logic_vf_reset();
+        self.next_inst()
     }
 
-    fn add_vx_vy(&mut self, x: u8, y: u8) {
-        let mut sum_wo_filter = (self.chip8.registers.v[x as usize].overflowing_add(self.chip8.registers.v[y as usize]).0) as u16;
-        if (sum_wo_filter > 255) {
-            self.chip8.registers.v[x as usize] = (0b0000000011111111 & sum_wo_filter) as u8;
-            self.chip8.registers.v[0xF] = 1;
-        }
-        else {
-            self.chip8.registers.v[x as usize] = sum_wo_filter as u8;
-        }
-        self.next_inst();
+    /// Vx += Vy, VF = carry.
+    fn add_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let (sum, carry) = self.chip8.registers.v[x as usize].overflowing_add(self.chip8.registers.v[y as usize]);
+        self.set_vx_and_flag(x, sum, carry)
     }
 
-    fn sub_vx_vy(&mut self, x: u8, y: u8) {
-        if self.chip8.registers.v[x as usize] > self.chip8.registers.v[y as usize] {
-            self.chip8.registers.v[0xF] = 1;
-            self.chip8.registers.v[x as usize] = self.chip8.registers.v[x as usize].overflowing_sub(self.chip8.registers.v[y as usize]).0;
-        } else {
-            self.chip8.registers.v[0xF] = 0;
-            self.chip8.registers.v[x as usize] = self.chip8.registers.v[y as usize].overflowing_sub(self.chip8.registers.v[x as usize]).0; 
-        }
-        self.next_inst();
+    /// Vx -= Vy, VF = not borrow.
+    fn sub_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let (difference, borrow) = self.chip8.registers.v[x as usize].overflowing_sub(self.chip8.registers.v[y as usize]);
+        self.set_vx_and_flag(x, difference, !borrow)
     }
 
-    fn shr_vx(&mut self, x: u8) {
-        if (self.chip8.registers.v[x as usize] & 0b00000001) == 1 {
-            self.chip8.registers.v[0xF] = 1;
-        } else {
-            self.chip8.registers.v[0xF] = 0;
-        }
-        self.chip8.registers.v[x as usize] = self.chip8.registers.v[x as usize].overflowing_div(2).0;
-        self.next_inst();
+    /// Vx >>= 1, VF = the bit shifted out.
+    fn shr_vx(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.shift_source(x, y);
+        let vx = self.chip8.registers.v[x as usize];
+        self.set_vx_and_flag(x, vx >> 1, (vx & 0b00000001) != 0)
     }
 
-    fn subn_vx_vy(&mut self, x: u8, y: u8) {
-        if (self.chip8.registers.v[y as usize] > self.chip8.registers.v[x as usize]) {
-            self.chip8.registers.v[0xF] = 1;
-        } else {
-            self.chip8.registers.v[0xF] = 0;
-        }
-        self.chip8.registers.v[x as usize] = self.chip8.registers.v[y as usize].overflowing_sub(self.chip8.registers.v[x as usize]).0;
-        self.next_inst();
+    /// Vx = Vy - Vx, VF = not borrow.
+    fn subn_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let (difference, borrow) = self.chip8.registers.v[y as usize].overflowing_sub(self.chip8.registers.v[x as usize]);
+        self.set_vx_and_flag(x, difference, !borrow)
     }
 
-    fn shl_vx(&mut self, x: u8) {
-        if (self.chip8.registers.v[x as usize] & 0b10000000) > 0 {
-            self.chip8.registers.v[0xF] = 1; 
-        } else {
-            self.chip8.registers.v[0xF] = 0;
-        }
-        self.chip8.registers.v[x as usize] = self.chip8.registers.v[x as usize].overflowing_mul(2).0;
-        self.next_inst();
+    /// Vx <<= 1, VF = the bit shifted out.
+    fn shl_vx(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.shift_source(x, y);
+        let vx = self.chip8.registers.v[x as usize];
+        self.set_vx_and_flag(x, vx << 1, (vx & 0b10000000) != 0)
     }
     
-    fn sne_vx_vy(&mut self, x: u8, y: u8) {
+    fn sne_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
         if self.chip8.registers.v[x as usize] != self.chip8.registers.v[y as usize] {
-           self.skip_next_inst();
+           self.skip_next_inst()
         } else {
-            self.next_inst();
+            self.next_inst()
         }
     }
 
-    fn ld_i_addr(&mut self, nnn: u16) {
+    fn ld_i_addr(&mut self, nnn: u16) -> Result<(), Chip8Error> {
         self.chip8.registers.i = nnn;
-        self.next_inst();
+        self.next_inst()
     }
 
-    fn jp_v0_nnn(&mut self, nnn: u16) {
-        self.chip8.registers.pc = nnn.overflowing_add(self.chip8.registers.v[0x0] as u16).0;
+    /// Jump to nnn + V0, or with the jump quirk to xnn + Vx.
+    fn jp_v0_nnn(&mut self, nnn: u16) -> Result<(), Chip8Error> {
+        let offset_reg = if self.chip8.quirks.jump_uses_vx { (nnn >> 8) as usize } else { 0x0 };
+        self.chip8.registers.pc = nnn.overflowing_add(self.chip8.registers.v[offset_reg] as u16).0;
+        Ok(())
     }
 
-    fn rnd_vx_byte(&mut self, x: u8, byte: u8) {
-        let mut rng = rand::thread_rng();
-        let rand_num: u8 = rng.gen_range(0..255);
+    fn rnd_vx_byte(&mut self, x: u8, byte: u8) -> Result<(), Chip8Error> {
+        let rand_num = self.chip8.rng.next_byte(&self.chip8.memory.ram);
         self.chip8.registers.v[x as usize] = byte & rand_num;
-        self.next_inst();
+        self.next_inst()
     }
 
     /// Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-    fn drw_vx_vy_n(&mut self, x: u8, y: u8, n: u8) {
+    /// On SUPER-CHIP a sprite with n = 0 is 16x16, two bytes per row.
+    /// On XO-CHIP the sprite is drawn on every selected plane, the data for
+    /// the second plane follows right after the data for the first.
+    /// The sprite starts at (Vx, Vy) wrapped onto the screen, whatever sticks
+    /// out over the edges is clipped or wrapped as the clip quirk says.
+    fn drw_vx_vy_n(&mut self, x: u8, y: u8, n: u8) -> Result<(), Chip8Error> {
+        let wide = n == 0 && self.chip8.variant.has_superchip();
+        let (rows, bytes_per_row) = if wide { (16, 2) } else { (n as u16, 1) };
+        let clip = self.chip8.quirks.clip_sprites;
+        // read the coordinates before VF is cleared, either may be VF
+        let vx = self.chip8.registers.v[x as usize] as usize % self.chip8.display.width;
+        let vy = self.chip8.registers.v[y as usize] as usize % self.chip8.display.height;
         self.chip8.registers.v[0xF] = 0;
-        let mut vy_with_mac = 0;
-        let mut i_with_mac = 0;
-        for mem_addr_count in 0..n {
-            vy_with_mac = self.chip8.registers.v[y as usize].overflowing_add(mem_addr_count).0;
-            i_with_mac = self.chip8.registers.i.overflowing_add(mem_addr_count as u16).0;
-            if self.chip8.io.draw_byte((self.chip8.registers.v[x as usize]) as usize, vy_with_mac as usize, self.chip8.memory.ram[i_with_mac as usize]) == true {
-                self.chip8.registers.v[0xF] = 1;
+        let mut sprite_addr = self.chip8.registers.i;
+        for plane in [1u8, 2u8] {
+            if (self.chip8.display.planes & plane) == 0 {
+                continue;
             }
+            for row in 0..rows {
+                let vy_with_row = vy + row as usize;
+                let addr_with_row = sprite_addr.wrapping_add(row * bytes_per_row) as usize;
+                let collision = if wide {
+                    let word = (self.chip8.read_byte(addr_with_row)? as u16) << 8 | self.chip8.read_byte(addr_with_row + 1)? as u16;
+                    self.chip8.display.draw_word(plane, vx, vy_with_row, word, clip)?
+                } else {
+                    let sprite_byte = self.chip8.read_byte(addr_with_row)?;
+                    self.chip8.display.draw_byte(plane, vx, vy_with_row, sprite_byte, clip)?
+                };
+                if collision {
+                    self.chip8.registers.v[0xF] = 1;
+                }
+            }
+            sprite_addr = sprite_addr.wrapping_add(rows * bytes_per_row);
+        }
+        if self.chip8.quirks.display_wait {
+            self.chip8.vblank_wait = true;
         }
-        self.next_inst();
+        self.next_inst()
     }
 
-    fn skp_vx(&mut self, x: u8) {
-        self.chip8.io.check_keys();
-        let key_status = self.chip8.io.get_key_status_from_num(self.chip8.registers.v[x as usize]);
-        if key_status == true {
-            self.skip_next_inst();
+    fn skp_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let key_status = self.chip8.keypad.get_key_status_from_nibble(self.chip8.registers.v[x as usize]);
+        if key_status {
+            self.skip_next_inst()
         } else {
-            self.next_inst();
+            self.next_inst()
         }
     }
 
-    fn sknp_vx(&mut self, x: u8) {
-        self.chip8.io.check_keys();
-        let key_status = self.chip8.io.get_key_status_from_num(self.chip8.registers.v[x as usize]);
-        if key_status == false {
-            self.skip_next_inst();
+    fn sknp_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let key_status = self.chip8.keypad.get_key_status_from_nibble(self.chip8.registers.v[x as usize]);
+        if !key_status {
+            self.skip_next_inst()
         } else {
-            self.next_inst();
+            self.next_inst()
         }
     }
 
-    fn ld_vx_dt(&mut self, x: u8) {
+    fn ld_vx_dt(&mut self, x: u8) -> Result<(), Chip8Error> {
         self.chip8.registers.v[x as usize] = self.chip8.registers.dt;
-        self.next_inst();
+        self.next_inst()
     }
 
-    fn ld_vx_k(&mut self, x: u8) {
-        self.chip8.registers.v[x as usize] = self.chip8.io.wait_for_key();
-        self.next_inst();
+    /// Wait for a key press, store the value of the key in Vx.
+    /// While no key is down the program counter stays put, so the
+    /// instruction is simply executed again on the next cycle.
+    fn ld_vx_k(&mut self, x: u8) -> Result<(), Chip8Error> {
+        if let Some(key) = self.chip8.keypad.first_pressed() {
+            self.chip8.registers.v[x as usize] = key;
+            self.next_inst()?;
+        }
+        Ok(())
     }
 
-    fn ld_dt_vx(&mut self, x: u8) {
+    fn ld_dt_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
         self.chip8.registers.dt = self.chip8.registers.v[x as usize];
-        self.next_inst();
+        self.next_inst()
     }
     
-    fn ld_st_vx(&mut self, x: u8) {
+    fn ld_st_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
         self.chip8.registers.st = self.chip8.registers.v[x as usize];
-        self.next_inst();
+        self.next_inst()
     }
 
-    fn add_i_vx(&mut self, x: u8) {
-        self.chip8.registers.i += self.chip8.registers.v[x as usize] as u16;
-        self.next_inst();
+    fn add_i_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.chip8.registers.i = self.chip8.registers.i.wrapping_add(self.chip8.registers.v[x as usize] as u16);
+        self.next_inst()
     }
 
-    fn ld_f_vx(&mut self, x: u8) {
-        self.chip8.registers.i = (self.chip8.registers.v[x as usize].overflowing_mul(5).0) as u16;
+    /// Point I at the font sprite for the digit in the low nibble of Vx.
+    fn ld_f_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let digit = (self.chip8.registers.v[x as usize] & 0xF) as u16;
+        self.chip8.registers.i = digit * 5;
         self.next_inst()
     }
 
-    fn ld_b_vx(&mut self, x: u8) {
+    fn ld_b_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
         let hundreds = self.chip8.registers.v[x as usize]/100;
         let tens = self.chip8.registers.v[x as usize]/10 - hundreds*10;
         let ones = self.chip8.registers.v[x as usize] - (hundreds*100 + tens*10);
 
-        self.chip8.memory.ram[self.chip8.registers.i as usize] = hundreds;
-        self.chip8.memory.ram[(self.chip8.registers.i+1) as usize] = tens;
-        self.chip8.memory.ram[(self.chip8.registers.i+2) as usize] = ones;
+        let iptr = self.chip8.registers.i as usize;
+        self.chip8.write_byte(iptr, hundreds)?;
+        self.chip8.write_byte(iptr + 1, tens)?;
+        self.chip8.write_byte(iptr + 2, ones)?;
         
-        self.next_inst();
+        self.next_inst()
     }
 
-    fn ld_i_vx(&mut self, x: u8) {
-        let mut iptr = self.chip8.registers.i as usize;
-        for i in 0..x {
-            self.chip8.memory.ram[iptr] = self.chip8.registers.v[i as usize];
-            iptr += 1;
+    fn ld_i_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let iptr = self.chip8.registers.i as usize;
+        for i in 0..=x as usize {
+            self.chip8.write_byte(iptr + i, self.chip8.registers.v[i])?;
         }
+        self.memory_increment(x);
 
-        self.next_inst();
+        self.next_inst()
     }
 
-    fn ld_vx_i(&mut self, x: u8) {
-        let mut iptr = self.chip8.registers.i as usize;
-        for i in 0..(x+1) {
-            self.chip8.registers.v[i as usize] = self.chip8.memory.ram[iptr];
-            iptr += 1;
+    fn ld_vx_i(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let iptr = self.chip8.registers.i as usize;
+        for i in 0..=x as usize {
+            self.chip8.registers.v[i] = self.chip8.read_byte(iptr + i)?;
         }
+        self.memory_increment(x);
+
+        self.next_inst()
+    }
+
+    /// Store the result of an arithmetic instruction, then the flag. VF
+    /// is written last, so with x = F it ends up holding the flag.
+    fn set_vx_and_flag(&mut self, x: u8, value: u8, flag: bool) -> Result<(), Chip8Error> {
+        self.chip8.registers.v[x as usize] = value;
+        self.chip8.registers.v[0xF] = flag as u8;
+        self.next_inst()
+    }
 
-        self.next_inst();
+    // Quirk helpers
+
+    /// With the VF reset quirk the logic instructions clear VF.
+    fn logic_vf_reset(&mut self) {
+        if self.chip8.quirks.logic_resets_vf {
+            self.chip8.registers.v[0xF] = 0;
+        }
+    }
+
+    /// With the shift quirk Vy is copied into Vx before shifting.
+    fn shift_source(&mut self, x: u8, y: u8) {
+        if self.chip8.quirks.shift_uses_vy {
+            self.chip8.registers.v[x as usize] = self.chip8.registers.v[y as usize];
+        }
+    }
+
+    /// Advance I after Fx55/Fx65 as the memory quirk says.
+    fn memory_increment(&mut self, x: u8) {
+        let increment = match self.chip8.quirks.memory_increment {
+            MemoryIncrement::None       => 0,
+            MemoryIncrement::X          => x as u16,
+            MemoryIncrement::XPlusOne   => x as u16 + 1,
+        };
+        self.chip8.registers.i = self.chip8.registers.i.wrapping_add(increment);
+    }
+
+    // SUPER-CHIP instructions
+
+    /// Scroll the display down by n lines.
+    fn scd_n(&mut self, n: u8) -> Result<(), Chip8Error> {
+        self.chip8.display.scroll_down(n as usize);
+        self.next_inst()
+    }
+
+    /// Scroll the display right by 4 pixels.
+    fn scr(&mut self) -> Result<(), Chip8Error> {
+        self.chip8.display.scroll_right(4);
+        self.next_inst()
+    }
+
+    /// Scroll the display left by 4 pixels.
+    fn scl(&mut self) -> Result<(), Chip8Error> {
+        self.chip8.display.scroll_left(4);
+        self.next_inst()
+    }
+
+    /// Exit the interpreter.
+    fn exit(&mut self) -> Result<(), Chip8Error> {
+        self.chip8.exited = true;
+        Ok(())
+    }
+
+    /// Switch to the 64x32 low resolution mode.
+    fn low(&mut self) -> Result<(), Chip8Error> {
+        self.chip8.display.set_hires(false);
+        self.next_inst()
+    }
+
+    /// Switch to the 128x64 high resolution mode.
+    fn high(&mut self) -> Result<(), Chip8Error> {
+        self.chip8.display.set_hires(true);
+        self.next_inst()
+    }
+
+    /// Point I at the big 8x10 font sprite for the digit in Vx.
+    fn ld_hf_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let digit = (self.chip8.registers.v[x as usize] & 0xF) as usize;
+        self.chip8.registers.i = (config::SCHIP_BIG_CHARSET_ADDR + digit * 10) as u16;
+        self.next_inst()
+    }
+
+    /// Store V0 through Vx in the RPL user flags.
+    fn ld_r_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let last = (x as usize).min(self.chip8.variant.rpl_flags_num() - 1);
+        self.chip8.rpl[..=last].copy_from_slice(&self.chip8.registers.v[..=last]);
+        self.next_inst()
+    }
+
+    /// Read V0 through Vx from the RPL user flags.
+    fn ld_vx_r(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let last = (x as usize).min(self.chip8.variant.rpl_flags_num() - 1);
+        self.chip8.registers.v[..=last].copy_from_slice(&self.chip8.rpl[..=last]);
+        self.next_inst()
+    }
+
+    // XO-CHIP instructions
+
+    /// Scroll the selected planes up by n lines.
+    fn scu_n(&mut self, n: u8) -> Result<(), Chip8Error> {
+        self.chip8.display.scroll_up(n as usize);
+        self.next_inst()
+    }
+
+    /// Store Vx through Vy at I, without changing I. Works backwards if
+    /// x is larger than y.
+    fn ld_i_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let iptr = self.chip8.registers.i as usize;
+        for (offset, reg) in Self::register_range(x, y).enumerate() {
+            self.chip8.write_byte(iptr + offset, self.chip8.registers.v[reg])?;
+        }
+        self.next_inst()
+    }
+
+    /// Read Vx through Vy from I, without changing I. Works backwards if
+    /// x is larger than y.
+    fn ld_vx_vy_i(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let iptr = self.chip8.registers.i as usize;
+        for (offset, reg) in Self::register_range(x, y).enumerate() {
+            self.chip8.registers.v[reg] = self.chip8.read_byte(iptr + offset)?;
+        }
+        self.next_inst()
+    }
+
+    fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+        if x <= y {
+            Box::new(x as usize..=y as usize)
+        } else {
+            Box::new((y as usize..=x as usize).rev())
+        }
+    }
+
+    /// Load I with the 16 bit address in the word following the instruction.
+    fn ld_i_long(&mut self) -> Result<(), Chip8Error> {
+        let pc = self.chip8.registers.pc as usize;
+        let addr = (self.chip8.peek_byte(pc + 2)? as u16) << 8 | self.chip8.peek_byte(pc + 3)? as u16;
+        self.chip8.registers.i = addr;
+        self.chip8.registers.pc = self.chip8.registers.pc.wrapping_add(4);
+        Ok(())
+    }
+
+    /// Select the bitplanes drawn to, n is a bitmask from 0 to 3.
+    fn plane_n(&mut self, n: u8) -> Result<(), Chip8Error> {
+        self.chip8.display.planes = n & 0b11;
+        self.next_inst()
+    }
+
+    /// Load the 16 byte audio pattern from I.
+    fn audio(&mut self) -> Result<(), Chip8Error> {
+        let iptr = self.chip8.registers.i as usize;
+        for offset in 0..config::XOCHIP_AUDIO_PATTERN_LEN {
+            self.chip8.audio_pattern[offset] = self.chip8.read_byte(iptr + offset)?;
+        }
+        self.next_inst()
+    }
+
+    /// Set the audio pattern playback rate to Vx.
+    fn pitch_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.chip8.pitch = self.chip8.registers.v[x as usize];
+        self.next_inst()
     }
 }
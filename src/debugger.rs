@@ -0,0 +1,494 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// debugger.rs file
+//
+
+use crate::config;
+use crate::cpu::{Cpu, Instruction, Step, StepHook};
+use crate::disasm;
+use crate::error::Chip8Error;
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+pub const DEBUGGER_HELP: &str = "\
+Numbers are hex, with or without 0x. An empty line repeats the last command.
+  break ADDR (b)        stop before the instruction at ADDR runs
+  delete [ADDR] (d)     remove a breakpoint, or all of them
+  breakpoints           list the breakpoints
+  watch ADDR[-END]      stop after an instruction writes to memory in the range
+  rwatch ADDR[-END]     stop after an instruction reads memory in the range,
+                        including sprite data and Fx65 loads
+  awatch ADDR[-END]     stop after either
+  watch REG [VALUE]     stop once V0-VF or I changes, or changes to VALUE
+  watch dt|st [VALUE]   stop once Fx15/Fx18 sets the timer, or sets it to VALUE
+  watchpoints           list the watchpoints with their numbers
+  unwatch [N]           remove watchpoint N, or all of them
+  step [N] (s)          run N instructions (default 1), into subroutines
+  next (n)              run one instruction, a whole subroutine for a call
+  finish (f)            run until the current subroutine returns
+  continue (c)          run until a breakpoint, or F12 in the window
+  regs (r)              show V0-VF, I, PC, SP, the stack and the timers
+  mem ADDR [LEN] (x)    hexdump LEN bytes of memory (default 40)
+  dis [ADDR] [N] (l)    disassemble N instructions at ADDR (default: around PC)
+  set REG VALUE         change V0-VF, I, PC, DT or ST
+  poke ADDR BYTE...     change memory
+  quit (q)              stop the machine";
+
+/// Something that runs the machine instruction by instruction and can
+/// stop it, the terminal debugger or the GDB stub. Attach one with
+/// `Scheduler::set_debugger`.
+pub trait DebugFrontend {
+    /// Run one 60 Hz frame like `Cpu::run_frame`, stopping in between
+    /// instructions whenever the user wants to.
+    fn run_frame(&mut self, cpu: &mut Cpu, cycles: usize) -> Result<usize, Chip8Error>;
+
+    /// Stop before the next instruction, e.g. on a hotkey.
+    fn pause(&mut self);
+
+    /// Return true once the user asked to stop the machine.
+    fn quit_requested(&self) -> bool;
+}
+
+/// Condition that stops the machine after the instruction that met it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// A data access to RAM between `start` and `end`, both included.
+    Memory { start: u16, end: u16, read: bool, write: bool },
+    /// V register `x` changed, to `value` if given.
+    Register { x: u8, value: Option<u8> },
+    /// I changed, to `value` if given.
+    I(Option<u16>),
+    /// Fx15 set the delay timer, to `value` if given.
+    DelayTimer(Option<u8>),
+    /// Fx18 set the sound timer, to `value` if given.
+    SoundTimer(Option<u8>),
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |value: Option<u16>| value.map_or(String::new(), |value| format!(" = {:02X}", value));
+        match self {
+            Watchpoint::Memory { start, end, read, write } => {
+                let access = match (read, write) {
+                    (true, true)    => "access",
+                    (true, false)   => "read",
+                    _               => "write",
+                };
+                write!(f, "{} {:03X}-{:03X}", access, start, end)
+            },
+            Watchpoint::Register { x, value: v }    => write!(f, "V{:X}{}", x, value(v.map(u16::from))),
+            Watchpoint::I(v)                        => write!(f, "I{}", value(*v)),
+            Watchpoint::DelayTimer(v)               => write!(f, "DT set{}", value(v.map(u16::from))),
+            Watchpoint::SoundTimer(v)               => write!(f, "ST set{}", value(v.map(u16::from))),
+        }
+    }
+}
+
+/// When to give control back to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Before the next instruction.
+    Pause,
+    /// After this many more instructions.
+    Step(usize),
+    /// Once a call made at depth `depth` returned to `addr`.
+    Over { addr: u16, depth: usize },
+    /// Once the call depth drops below `depth`.
+    Finish(usize),
+    /// Only at breakpoints.
+    Continue,
+}
+
+/// Interactive debugger that runs the machine one instruction at a time
+/// and stops at breakpoints to read commands. It starts out paused before
+/// the first instruction.
+pub struct Debugger {
+    breakpoints:    BTreeSet<u16>,
+    watchpoints:    Vec<Watchpoint>,
+    mode:           Mode,
+    /// Don't stop at the breakpoint on this address, execution just
+    /// resumed from it.
+    resumed_at:     Option<u16>,
+    /// V and I from before the instruction that is running, for the
+    /// watchpoints.
+    before:         ([u8; config::CHIP8_REGISTERS_NUM], u16),
+    last_command:   String,
+    quit:           bool,
+    input:          Box<dyn BufRead>,
+    output:         Box<dyn Write>,
+}
+
+/// What a command asks of the machine.
+enum Flow {
+    /// Read another command.
+    Prompt,
+    /// Run in the given mode.
+    Resume(Mode),
+    Quit,
+}
+
+impl Debugger {
+    /// Read commands from stdin and answer on stdout.
+    pub fn new() -> Self {
+        Self::with_io(Box::new(std::io::BufReader::new(std::io::stdin())), Box::new(std::io::stdout()))
+    }
+
+    pub fn with_io(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Self {
+            breakpoints:    BTreeSet::new(),
+            watchpoints:    Vec::new(),
+            mode:           Mode::Pause,
+            resumed_at:     None,
+            before:         ([0; config::CHIP8_REGISTERS_NUM], 0),
+            last_command:   String::new(),
+            quit:           false,
+            input,
+            output,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    fn should_stop(&mut self, cpu: &Cpu) -> bool {
+        let pc = cpu.chip8.registers.pc;
+        let depth = cpu.chip8.registers.sp;
+        if self.resumed_at.take() != Some(pc) && self.breakpoints.contains(&pc) {
+            let _ = writeln!(self.output, "breakpoint at {:03X}", pc);
+            return true;
+        }
+        match self.mode {
+            Mode::Pause                 => true,
+            Mode::Step(n)               => n == 0,
+            Mode::Over { addr, depth: call_depth } => pc == addr && depth == call_depth,
+            Mode::Finish(call_depth)    => depth < call_depth,
+            Mode::Continue              => false,
+        }
+    }
+
+    /// After the instruction at `pc` ran, stop if it met a watchpoint. `v`
+    /// and `i` are the registers from before it ran.
+    fn check_watchpoints(&mut self, cpu: &mut Cpu, pc: u16, instruction: Instruction, v: &[u8], i: u16) {
+        let accesses = cpu.chip8.access_log.take().unwrap_or_default();
+        let registers = &cpu.chip8.registers;
+        let timer_set = |low: u8| instruction.nibbles[0] == 0xF && instruction.kk == low;
+        let mut hits = Vec::new();
+        for (n, watchpoint) in self.watchpoints.iter().enumerate() {
+            let hit = match *watchpoint {
+                Watchpoint::Memory { start, end, read, write } => accesses.iter()
+                    .find(|access| (start as usize..=end as usize).contains(&access.addr) && if access.write { write } else { read })
+                    .map(|access| format!("{} {:03X}", if access.write { "write to" } else { "read of" }, access.addr)),
+                Watchpoint::Register { x, value } => {
+                    let (old, new) = (v[x as usize], registers.v[x as usize]);
+                    (old != new && value.is_none_or(|value| value == new)).then(|| format!("V{:X} = {:02X}, was {:02X}", x, new, old))
+                },
+                Watchpoint::I(value) => (i != registers.i && value.is_none_or(|value| value == registers.i))
+                    .then(|| format!("I = {:04X}, was {:04X}", registers.i, i)),
+                Watchpoint::DelayTimer(value) => (timer_set(0x15) && value.is_none_or(|value| value == registers.dt))
+                    .then(|| format!("DT set to {:02X}", registers.dt)),
+                Watchpoint::SoundTimer(value) => (timer_set(0x18) && value.is_none_or(|value| value == registers.st))
+                    .then(|| format!("ST set to {:02X}", registers.st)),
+            };
+            if let Some(hit) = hit {
+                hits.push(format!("watchpoint {}: {} by {:03X}", n + 1, hit, pc));
+            }
+        }
+        for hit in hits.iter() {
+            let _ = writeln!(self.output, "{}", hit);
+        }
+        if !hits.is_empty() {
+            self.mode = Mode::Pause;
+        }
+    }
+
+    /// Show where the machine stopped and read commands until one of them
+    /// resumes it.
+    fn prompt(&mut self, cpu: &mut Cpu) {
+        self.mode = Mode::Pause;
+        self.show_current(cpu);
+        loop {
+            let _ = write!(self.output, "(chip8) ");
+            let _ = self.output.flush();
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                // end of input, nobody is left to resume the machine
+                Ok(0) | Err(_) => {
+                    self.quit = true;
+                    return;
+                },
+                Ok(_) => (),
+            }
+            let line = match line.trim() {
+                ""      => self.last_command.clone(),
+                line    => line.to_string(),
+            };
+            self.last_command = line.clone();
+            match self.command(cpu, &line) {
+                Ok(Flow::Prompt)        => (),
+                Ok(Flow::Resume(mode))  => {
+                    self.mode = mode;
+                    return;
+                },
+                Ok(Flow::Quit)          => {
+                    self.quit = true;
+                    return;
+                },
+                Err(error)              => {
+                    let _ = writeln!(self.output, "{}", error);
+                },
+            }
+        }
+    }
+
+    fn command(&mut self, cpu: &mut Cpu, line: &str) -> Result<Flow, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return Ok(Flow::Prompt);
+        };
+        let arg = |n: usize| -> Result<u16, String> {
+            let text = args.get(n).ok_or(format!("{} needs more arguments, see help", name))?;
+            parse_hex(text)
+        };
+        match name {
+            "break" | "b" => {
+                let addr = arg(0)?;
+                self.breakpoints.insert(addr);
+                let _ = writeln!(self.output, "breakpoint set at {:03X}", addr);
+            },
+            "delete" | "d" => match args.first() {
+                Some(_) => {
+                    let addr = arg(0)?;
+                    if !self.breakpoints.remove(&addr) {
+                        return Err(format!("no breakpoint at {:03X}", addr));
+                    }
+                },
+                None => self.breakpoints.clear(),
+            },
+            "breakpoints" => {
+                let list: Vec<String> = self.breakpoints.iter().map(|addr| format!("{:03X}", addr)).collect();
+                let _ = writeln!(self.output, "{}", if list.is_empty() { "no breakpoints".to_string() } else { list.join(" ") });
+            },
+            "watch" | "rwatch" | "awatch" => {
+                let target = args.first().ok_or(format!("{} needs an address or a register", name))?.to_ascii_lowercase();
+                let watchpoint = match (name, target.as_str(), parse_register(&target)) {
+                    ("watch", "i", _)   => Watchpoint::I(args.get(1).map(|_| arg(1)).transpose()?),
+                    ("watch", "dt", _)  => Watchpoint::DelayTimer(args.get(1).map(|_| arg(1).and_then(byte)).transpose()?),
+                    ("watch", "st", _)  => Watchpoint::SoundTimer(args.get(1).map(|_| arg(1).and_then(byte)).transpose()?),
+                    ("watch", _, Some(x)) => Watchpoint::Register { x, value: args.get(1).map(|_| arg(1).and_then(byte)).transpose()? },
+                    _ => {
+                        let (start, end) = match target.split_once('-') {
+                            Some((start, end))  => (parse_hex(start)?, parse_hex(end)?),
+                            None                => (parse_hex(&target)?, parse_hex(&target)?),
+                        };
+                        if end < start {
+                            return Err(format!("empty range: {}", target));
+                        }
+                        Watchpoint::Memory { start, end, read: name != "watch", write: name != "rwatch" }
+                    },
+                };
+                self.watchpoints.push(watchpoint);
+                let _ = writeln!(self.output, "watchpoint {}: {}", self.watchpoints.len(), watchpoint);
+            },
+            "watchpoints" => {
+                if self.watchpoints.is_empty() {
+                    let _ = writeln!(self.output, "no watchpoints");
+                }
+                for (n, watchpoint) in self.watchpoints.iter().enumerate() {
+                    let _ = writeln!(self.output, "{}: {}", n + 1, watchpoint);
+                }
+            },
+            "unwatch" => match args.first() {
+                Some(text) => {
+                    let n: usize = text.parse().map_err(|_| format!("not a watchpoint number: {}", text))?;
+                    if n == 0 || n > self.watchpoints.len() {
+                        return Err(format!("no watchpoint {}", n));
+                    }
+                    self.watchpoints.remove(n - 1);
+                },
+                None => self.watchpoints.clear(),
+            },
+            "step" | "s" => {
+                let n = if args.is_empty() { 1 } else { arg(0)? as usize };
+                return Ok(Flow::Resume(Mode::Step(n)));
+            },
+            "next" | "n" => {
+                let instruction = cpu.get_instruction().map_err(|error| error.to_string())?;
+                if instruction.nibbles[0] == 0x2 {
+                    let addr = cpu.chip8.registers.pc.wrapping_add(2);
+                    return Ok(Flow::Resume(Mode::Over { addr, depth: cpu.chip8.registers.sp }));
+                }
+                return Ok(Flow::Resume(Mode::Step(1)));
+            },
+            "finish" | "f" => {
+                if cpu.chip8.registers.sp == 0 {
+                    return Err("not inside a subroutine".to_string());
+                }
+                return Ok(Flow::Resume(Mode::Finish(cpu.chip8.registers.sp)));
+            },
+            "continue" | "c" => return Ok(Flow::Resume(Mode::Continue)),
+            "regs" | "r" => self.show_registers(cpu),
+            "mem" | "x" => {
+                let addr = arg(0)? as usize;
+                let len = if args.len() > 1 { arg(1)? as usize } else { 0x40 };
+                self.hexdump(&cpu.chip8.memory.ram, addr, len);
+            },
+            "dis" | "l" => {
+                let pc = cpu.chip8.registers.pc;
+                let addr = if args.is_empty() { pc.saturating_sub(6) } else { arg(0)? };
+                let count = if args.len() > 1 { arg(1)? as usize } else { 8 };
+                self.disassemble(cpu, addr, count);
+            },
+            "set" => {
+                let register = args.first().ok_or("set needs a register and a value")?.to_ascii_lowercase();
+                let value = arg(1)?;
+                let registers = &mut cpu.chip8.registers;
+                match register.as_str() {
+                    "i"     => registers.i = value,
+                    "pc"    => registers.pc = value,
+                    "dt"    => registers.dt = byte(value)?,
+                    "st"    => registers.st = byte(value)?,
+                    _ => match parse_register(&register) {
+                        Some(n) => registers.v[n as usize] = byte(value)?,
+                        None    => return Err(format!("unknown register: {}", register)),
+                    },
+                }
+            },
+            "poke" => {
+                let addr = arg(0)? as usize;
+                if args.len() < 2 {
+                    return Err("poke needs an address and bytes".to_string());
+                }
+                for n in 1..args.len() {
+                    let value = byte(arg(n)?)?;
+                    let cell = cpu.chip8.memory.ram.get_mut(addr + n - 1).ok_or(format!("{:03X} is outside of memory", addr + n - 1))?;
+                    *cell = value;
+                }
+            },
+            "quit" | "q" => return Ok(Flow::Quit),
+            "help" | "h" => {
+                let _ = writeln!(self.output, "{}", DEBUGGER_HELP);
+            },
+            _ => return Err(format!("unknown command: {}, try help", name)),
+        }
+        Ok(Flow::Prompt)
+    }
+
+    fn show_current(&mut self, cpu: &Cpu) {
+        let pc = cpu.chip8.registers.pc;
+        let lines = disasm::disassemble_range(&cpu.chip8.memory.ram, pc, pc.saturating_add(4), cpu.chip8.variant);
+        if let Some(line) = lines.first() {
+            let _ = writeln!(self.output, "=> {}", line);
+        }
+    }
+
+    fn show_registers(&mut self, cpu: &Cpu) {
+        let registers = &cpu.chip8.registers;
+        for (row, values) in registers.v.chunks(4).enumerate() {
+            let cells: Vec<String> = values.iter().enumerate().map(|(n, v)| format!("V{:X} {:02X}", row * 4 + n, v)).collect();
+            let _ = writeln!(self.output, "{}", cells.join("  "));
+        }
+        let _ = writeln!(self.output, "I  {:04X}  PC {:04X}  SP {:X}  DT {:02X}  ST {:02X}", registers.i, registers.pc, registers.sp, registers.dt, registers.st);
+        let stack: Vec<String> = cpu.chip8.stack.stack[..registers.sp].iter().map(|addr| format!("{:03X}", addr)).collect();
+        let _ = writeln!(self.output, "stack: {}", if stack.is_empty() { "empty".to_string() } else { stack.join(" ") });
+    }
+
+    fn hexdump(&mut self, ram: &[u8], addr: usize, len: usize) {
+        let end = addr.saturating_add(len).min(ram.len());
+        for start in (addr.min(end)..end).step_by(16) {
+            let bytes: Vec<String> = ram[start..(start + 16).min(end)].iter().map(|byte| format!("{:02X}", byte)).collect();
+            let _ = writeln!(self.output, "{:03X}: {}", start, bytes.join(" "));
+        }
+    }
+
+    fn disassemble(&mut self, cpu: &Cpu, addr: u16, count: usize) {
+        let end = addr.saturating_add(count as u16 * 4);
+        let lines = disasm::disassemble_range(&cpu.chip8.memory.ram, addr, end, cpu.chip8.variant);
+        for line in lines.iter().take(count) {
+            let marker = if line.addr == cpu.chip8.registers.pc { "=>" } else { "  " };
+            let _ = writeln!(self.output, "{} {}", marker, line);
+        }
+    }
+}
+
+impl StepHook for Debugger {
+    fn before_step(&mut self, cpu: &mut Cpu) -> bool {
+        if self.should_stop(cpu) {
+            cpu.chip8.present();
+            self.prompt(cpu);
+            if self.quit {
+                return false;
+            }
+            self.resumed_at = Some(cpu.chip8.registers.pc);
+        }
+        self.before = (cpu.chip8.registers.v, cpu.chip8.registers.i);
+        let watch_memory = self.watchpoints.iter().any(|watchpoint| matches!(watchpoint, Watchpoint::Memory { .. }));
+        cpu.chip8.access_log = watch_memory.then(Vec::new);
+        true
+    }
+
+    fn after_step(&mut self, cpu: &mut Cpu, pc: u16, result: &Result<Step, Chip8Error>) {
+        match result {
+            Err(error)          => {
+                // let the user look at the wreck before the machine stops
+                let _ = writeln!(self.output, "error at {:03X}: {}, quit to stop", cpu.chip8.registers.pc, error);
+                cpu.chip8.present();
+                self.prompt(cpu);
+            },
+            Ok(Step::Exited)    => (),
+            Ok(step)            => {
+                if let Step::Executed(instruction) = *step {
+                    let (v, i) = self.before;
+                    self.check_watchpoints(cpu, pc, instruction, &v, i);
+                }
+                if let Mode::Step(n) = &mut self.mode {
+                    *n = n.saturating_sub(1);
+                }
+            },
+        }
+    }
+}
+
+impl DebugFrontend for Debugger {
+    fn run_frame(&mut self, cpu: &mut Cpu, cycles: usize) -> Result<usize, Chip8Error> {
+        cpu.run_frame_with(cycles, self)
+    }
+
+    fn pause(&mut self) {
+        self.mode = Mode::Pause;
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.quit
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("not a hex number: {}", text))
+}
+
+/// V0 to VF, in lower case.
+fn parse_register(text: &str) -> Option<u8> {
+    let n = u8::from_str_radix(text.strip_prefix('v')?, 16).ok()?;
+    ((n as usize) < config::CHIP8_REGISTERS_NUM).then_some(n)
+}
+
+fn byte(value: u16) -> Result<u8, String> {
+    u8::try_from(value).map_err(|_| format!("{:X} doesn't fit into a byte", value))
+}
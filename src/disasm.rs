@@ -0,0 +1,314 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// disasm.rs file
+//
+
+use crate::cpu::Instruction;
+use crate::variant::Variant;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// One disassembled instruction (or data).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmLine {
+    pub addr:       u16,
+    pub bytes:      Vec<u8>,
+    pub text:       String,
+    /// Label of the address, if anything refers to it.
+    pub label:      Option<String>,
+    /// Extra information, e.g. the pixels of a sprite byte.
+    pub comment:    Option<String>,
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        write!(f, "{:03X}: {:<11} {}", self.addr, bytes.join(" "), self.text)
+    }
+}
+
+/// Size in bytes of the instruction, 4 for the XO-CHIP F000 NNNN long load
+/// and 2 for everything else.
+pub fn instruction_len(instruction: &Instruction, variant: Variant) -> usize {
+    if variant.has_xochip() && instruction.instruction == 0xF000 {
+        4
+    } else {
+        2
+    }
+}
+
+/// Cowgod style mnemonic of an instruction, `None` if the variant doesn't
+/// know the opcode. `long` is the word after the instruction, only used by
+/// F000 NNNN.
+pub fn mnemonic(instruction: &Instruction, variant: Variant, long: Option<u16>) -> Option<String> {
+    let schip = variant.has_superchip();
+    let xochip = variant.has_xochip();
+    let (x, y, n, kk, nnn) = (instruction.x, instruction.y, instruction.n, instruction.kk, instruction.nnn);
+    let text = match instruction.nibbles {
+        [0x0, 0x0, 0xC, _] if schip         => format!("SCD {}", n),
+        [0x0, 0x0, 0xD, _] if xochip        => format!("SCU {}", n),
+        [0x0, 0x0, 0xE, 0x0]    => "CLS".to_string(),
+        [0x0, 0x0, 0xE, 0xE]    => "RET".to_string(),
+        [0x0, 0x0, 0xF, 0xB] if schip       => "SCR".to_string(),
+        [0x0, 0x0, 0xF, 0xC] if schip       => "SCL".to_string(),
+        [0x0, 0x0, 0xF, 0xD] if schip       => "EXIT".to_string(),
+        [0x0, 0x0, 0xF, 0xE] if schip       => "LOW".to_string(),
+        [0x0, 0x0, 0xF, 0xF] if schip       => "HIGH".to_string(),
+        [0x0, _, _, _]          => format!("SYS {:#05X}", nnn),
+        [0x1, _, _, _]          => format!("JP {:#05X}", nnn),
+        [0x2, _, _, _]          => format!("CALL {:#05X}", nnn),
+        [0x3, _, _, _]          => format!("SE V{:X}, {:#04X}", x, kk),
+        [0x4, _, _, _]          => format!("SNE V{:X}, {:#04X}", x, kk),
+        [0x5, _, _, 0x0]        => format!("SE V{:X}, V{:X}", x, y),
+        [0x5, _, _, 0x2] if xochip          => format!("SAVE V{:X}, V{:X}", x, y),
+        [0x5, _, _, 0x3] if xochip          => format!("LOAD V{:X}, V{:X}", x, y),
+        [0x6, _, _, _]          => format!("LD V{:X}, {:#04X}", x, kk),
+        [0x7, _, _, _]          => format!("ADD V{:X}, {:#04X}", x, kk),
+        [0x8, _, _, 0x0]        => format!("LD V{:X}, V{:X}", x, y),
+        [0x8, _, _, 0x1]        => format!("OR V{:X}, V{:X}", x, y),
+        [0x8, _, _, 0x2]        => format!("AND V{:X}, V{:X}", x, y),
+        [0x8, _, _, 0x3]        => format!("XOR V{:X}, V{:X}", x, y),
+        [0x8, _, _, 0x4]        => format!("ADD V{:X}, V{:X}", x, y),
+        [0x8, _, _, 0x5]        => format!("SUB V{:X}, V{:X}", x, y),
+        [0x8, _, _, 0x6]        => format!("SHR V{:X}, V{:X}", x, y),
+        [0x8, _, _, 0x7]        => format!("SUBN V{:X}, V{:X}", x, y),
+        [0x8, _, _, 0xE]        => format!("SHL V{:X}, V{:X}", x, y),
+        [0x9, _, _, 0x0]        => format!("SNE V{:X}, V{:X}", x, y),
+        [0xA, _, _, _]          => format!("LD I, {:#05X}", nnn),
+        [0xB, _, _, _]          => format!("JP V0, {:#05X}", nnn),
+        [0xC, _, _, _]          => format!("RND V{:X}, {:#04X}", x, kk),
+        [0xD, _, _, _]          => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        [0xE, _, 0x9, 0xE]      => format!("SKP V{:X}", x),
+        [0xE, _, 0xA, 0x1]      => format!("SKNP V{:X}", x),
+        [0xF, 0x0, 0x0, 0x0] if xochip      => format!("LD I, LONG {:#06X}", long?),
+        [0xF, _, 0x0, 0x1] if xochip        => format!("PLANE {}", x),
+        [0xF, 0x0, 0x0, 0x2] if xochip      => "AUDIO".to_string(),
+        [0xF, _, 0x0, 0x7]      => format!("LD V{:X}, DT", x),
+        [0xF, _, 0x0, 0xA]      => format!("LD V{:X}, K", x),
+        [0xF, _, 0x1, 0x5]      => format!("LD DT, V{:X}", x),
+        [0xF, _, 0x1, 0x8]      => format!("LD ST, V{:X}", x),
+        [0xF, _, 0x1, 0xE]      => format!("ADD I, V{:X}", x),
+        [0xF, _, 0x2, 0x9]      => format!("LD F, V{:X}", x),
+        [0xF, _, 0x3, 0x0] if schip         => format!("LD HF, V{:X}", x),
+        [0xF, _, 0x3, 0x3]      => format!("LD B, V{:X}", x),
+        [0xF, _, 0x3, 0xA] if xochip        => format!("PITCH V{:X}", x),
+        [0xF, _, 0x5, 0x5]      => format!("LD [I], V{:X}", x),
+        [0xF, _, 0x6, 0x5]      => format!("LD V{:X}, [I]", x),
+        [0xF, _, 0x7, 0x5] if schip         => format!("LD R, V{:X}", x),
+        [0xF, _, 0x8, 0x5] if schip         => format!("LD V{:X}, R", x),
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Disassemble `bytes` loaded at `origin` one word at a time. Words that
+/// aren't instructions of the variant come out as `DW` data.
+pub fn disassemble(bytes: &[u8], origin: u16, variant: Variant) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let addr = origin.wrapping_add(offset as u16);
+        if offset + 1 >= bytes.len() {
+            lines.push(DisasmLine { addr, bytes: vec![bytes[offset]], text: format!("DB {:#04X}", bytes[offset]), label: None, comment: None });
+            break;
+        }
+        let instruction = Instruction::new((bytes[offset] as u16) << 8 | bytes[offset + 1] as u16);
+        let len = instruction_len(&instruction, variant).min(bytes.len() - offset);
+        let long = if len == 4 { Some((bytes[offset + 2] as u16) << 8 | bytes[offset + 3] as u16) } else { None };
+        let (len, text) = match mnemonic(&instruction, variant, long) {
+            Some(text)  => (len, text),
+            None        => (2, format!("DW {:#06X}", instruction.instruction)),
+        };
+        lines.push(DisasmLine { addr, bytes: bytes[offset..offset + len].to_vec(), text, label: None, comment: None });
+        offset += len;
+    }
+    lines
+}
+
+/// Disassemble the `start..end` range of the machine's memory one word at
+/// a time, e.g. the code around the program counter.
+pub fn disassemble_range(memory: &[u8], start: u16, end: u16, variant: Variant) -> Vec<DisasmLine> {
+    let end = (end as usize).min(memory.len());
+    let start = (start as usize).min(end);
+    disassemble(&memory[start..end], start as u16, variant)
+}
+
+/// What the code flow analysis found out about a byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteKind {
+    Data,
+    /// First byte of an instruction, of the given length.
+    Code(usize),
+    /// Later byte of an instruction.
+    CodeRest,
+}
+
+/// Why an address gets a label, the strongest reason names it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    /// Loaded into I, most likely sprite data.
+    Sprite,
+    Jump,
+    Subroutine,
+}
+
+fn add_label(labels: &mut BTreeMap<u16, LabelKind>, addr: u16, kind: LabelKind) {
+    let entry = labels.entry(addr).or_insert(kind);
+    *entry = (*entry).max(kind);
+}
+
+/// Disassemble a whole program loaded at `origin`. Unlike `disassemble`
+/// it follows the code from the entry point through jumps, calls and
+/// skips, so data mixed into the program comes out as `DB` bytes rather
+/// than bogus instructions. Jump and call targets and the addresses
+/// loaded into I get labels, data loaded into I is shown as sprite pixels.
+pub fn disassemble_program(rom: &[u8], origin: u16, variant: Variant) -> Vec<DisasmLine> {
+    let mut kinds = vec![ByteKind::Data; rom.len()];
+    let mut labels: BTreeMap<u16, LabelKind> = BTreeMap::new();
+    let word_at = |offset: usize| -> Option<u16> {
+        Some((*rom.get(offset)? as u16) << 8 | *rom.get(offset + 1)? as u16)
+    };
+    let mut pending = vec![origin];
+    while let Some(addr) = pending.pop() {
+        let offset = addr.wrapping_sub(origin) as usize;
+        let Some(word) = word_at(offset) else {
+            continue;
+        };
+        if kinds[offset] != ByteKind::Data {
+            continue;
+        }
+        let instruction = Instruction::new(word);
+        let len = instruction_len(&instruction, variant);
+        let long = if len == 4 { word_at(offset + 2) } else { None };
+        if offset + len > rom.len() || mnemonic(&instruction, variant, long).is_none()
+            || kinds[offset..offset + len].iter().any(|kind| *kind != ByteKind::Data) {
+            continue;
+        }
+        kinds[offset] = ByteKind::Code(len);
+        for kind in kinds[offset + 1..offset + len].iter_mut() {
+            *kind = ByteKind::CodeRest;
+        }
+
+        let next = addr.wrapping_add(len as u16);
+        let nnn = instruction.nnn;
+        match instruction.nibbles {
+            [0x0, 0x0, 0xE, 0xE] => (),
+            [0x0, 0x0, 0xF, 0xD] if variant.has_superchip() => (),
+            [0x1, _, _, _] => {
+                add_label(&mut labels, nnn, LabelKind::Jump);
+                pending.push(nnn);
+            },
+            [0x2, _, _, _] => {
+                add_label(&mut labels, nnn, LabelKind::Subroutine);
+                pending.push(nnn);
+                pending.push(next);
+            },
+            // the jump table itself is usually a row of jumps
+            [0xB, _, _, _] => {
+                add_label(&mut labels, nnn, LabelKind::Jump);
+                pending.push(nnn);
+            },
+            [0x3, _, _, _] | [0x4, _, _, _] | [0x5, _, _, 0x0] | [0x9, _, _, 0x0] | [0xE, _, 0x9, 0xE] | [0xE, _, 0xA, 0x1] => {
+                let skipped = match word_at(next.wrapping_sub(origin) as usize) {
+                    Some(0xF000) if variant.has_xochip() => 4,
+                    _ => 2,
+                };
+                pending.push(next.wrapping_add(skipped));
+                pending.push(next);
+            },
+            [0xA, _, _, _] => {
+                add_label(&mut labels, nnn, LabelKind::Sprite);
+                pending.push(next);
+            },
+            [0xF, 0x0, 0x0, 0x0] if len == 4 => {
+                if let Some(long) = long {
+                    add_label(&mut labels, long, LabelKind::Sprite);
+                }
+                pending.push(next);
+            },
+            _ => pending.push(next),
+        }
+    }
+
+    let rom_end = origin as usize + rom.len();
+    let label_name = |addr: u16| -> Option<String> {
+        let kind = labels.get(&addr)?;
+        let offset = addr.wrapping_sub(origin) as usize;
+        if (addr as usize) < origin as usize || addr as usize >= rom_end || kinds[offset] == ByteKind::CodeRest {
+            return None;
+        }
+        Some(match kind {
+            LabelKind::Subroutine   => format!("sub_{:03X}", addr),
+            LabelKind::Jump         => format!("L{:03X}", addr),
+            LabelKind::Sprite       => format!("sprite_{:03X}", addr),
+        })
+    };
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    let mut sprite = false;
+    while offset < rom.len() {
+        let addr = origin.wrapping_add(offset as u16);
+        let label = label_name(addr);
+        if let ByteKind::Code(len) = kinds[offset] {
+            let instruction = Instruction::new((rom[offset] as u16) << 8 | rom[offset + 1] as u16);
+            let long = if len == 4 { word_at(offset + 2) } else { None };
+            let mut text = mnemonic(&instruction, variant, long).unwrap_or_default();
+            match (instruction.nibbles[0], long) {
+                (0x1 | 0x2 | 0xA | 0xB, _) => if let Some(name) = label_name(instruction.nnn) {
+                    text = text.replace(&format!("{:#05X}", instruction.nnn), &name);
+                },
+                (0xF, Some(long)) => if let Some(name) = label_name(long) {
+                    text = text.replace(&format!("{:#06X}", long), &name);
+                },
+                _ => (),
+            }
+            lines.push(DisasmLine { addr, bytes: rom[offset..offset + len].to_vec(), text, label, comment: None });
+            offset += len;
+            sprite = false;
+            continue;
+        }
+
+        // data runs up to the next instruction or label
+        if label.is_some() {
+            sprite = labels.get(&addr) == Some(&LabelKind::Sprite);
+        }
+        if sprite {
+            let byte = rom[offset];
+            let pixels: String = (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect();
+            lines.push(DisasmLine { addr, bytes: vec![byte], text: format!("DB {:#010b}", byte), label, comment: Some(pixels) });
+            offset += 1;
+            continue;
+        }
+        let mut end = offset + 1;
+        while end < rom.len() && end - offset < 8 && kinds[end] == ByteKind::Data && label_name(origin.wrapping_add(end as u16)).is_none() {
+            end += 1;
+        }
+        let bytes: Vec<String> = rom[offset..end].iter().map(|byte| format!("{:#04X}", byte)).collect();
+        lines.push(DisasmLine { addr, bytes: rom[offset..end].to_vec(), text: format!("DB {}", bytes.join(", ")), label, comment: None });
+        offset = end;
+    }
+    lines
+}
+
+/// Lay out disassembled lines as source for `asm::assemble`, with the
+/// address and bytes of every line in a comment.
+pub fn to_source(lines: &[DisasmLine]) -> String {
+    let mut source = String::new();
+    for line in lines.iter() {
+        if let Some(label) = &line.label {
+            source.push_str(&format!("{}:\n", label));
+        }
+        let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let mut comment = format!("{:03X}: {}", line.addr, bytes.join(" "));
+        if let Some(extra) = &line.comment {
+            comment = format!("{:03X}: {}", line.addr, extra);
+        }
+        source.push_str(&format!("    {:<32} ; {}\n", line.text, comment));
+    }
+    source
+}
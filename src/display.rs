@@ -0,0 +1,184 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// display.rs file
+//
+
+use crate::config;
+use crate::error::Chip8Error;
+
+/// Framebuffer, 64x32 in low resolution and 128x64 in the SUPER-CHIP high
+/// resolution mode. Pixels are indexed as `pixels[x][y]` and hold one bit
+/// per XO-CHIP bitplane, so a pixel is one of four colours. Everything
+/// but XO-CHIP only ever draws on the first plane.
+#[derive(Clone)]
+pub struct Display {
+    pub width:  usize,
+    pub height: usize,
+    pub pixels: Vec<Vec<u8>>,
+    /// Bitmask of the planes drawing, clearing and scrolling work on.
+    pub planes: u8,
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Self {
+            width:  config::CHIP8_DISPLAY_WIDTH,
+            height: config::CHIP8_DISPLAY_HEIGHT,
+            pixels: vec![vec![0; config::CHIP8_DISPLAY_HEIGHT]; config::CHIP8_DISPLAY_WIDTH],
+            planes: 1,
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == config::SCHIP_DISPLAY_WIDTH
+    }
+
+    /// Switch between 64x32 and 128x64, the screen is cleared.
+    pub fn set_hires(&mut self, hires: bool) {
+        if hires {
+            self.width = config::SCHIP_DISPLAY_WIDTH;
+            self.height = config::SCHIP_DISPLAY_HEIGHT;
+        } else {
+            self.width = config::CHIP8_DISPLAY_WIDTH;
+            self.height = config::CHIP8_DISPLAY_HEIGHT;
+        }
+        self.pixels = vec![vec![0; self.height]; self.width];
+    }
+
+    fn check_pixel_in_bounds(&self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        if (x >= self.width) || (y >= self.height) {
+            return Err(Chip8Error::PixelOutOfBounds { x, y });
+        }
+        Ok(())
+    }
+
+    /// Set the colour (the plane bits) of a pixel.
+    pub fn set_pixel(&mut self, x: usize, y: usize, val: u8) -> Result<(), Chip8Error> {
+        self.check_pixel_in_bounds(x, y)?;
+        self.pixels[x][y] = val;
+        Ok(())
+    }
+
+    /// Get the colour (the plane bits) of a pixel.
+    pub fn get_pixel(&self, x: usize, y: usize) -> Result<u8, Chip8Error> {
+        self.check_pixel_in_bounds(x, y)?;
+        Ok(self.pixels[x][y])
+    }
+
+    /// Return true if the pixel is lit on any plane.
+    pub fn is_lit(&self, x: usize, y: usize) -> bool {
+        self.get_pixel(x, y).map(|val| val != 0).unwrap_or(false)
+    }
+
+    /// Clear screen (the selected planes of it)
+    pub fn clear(&mut self) {
+        let keep = !self.planes;
+        for column in self.pixels.iter_mut() {
+            for pixel in column.iter_mut() {
+                *pixel &= keep;
+            }
+        }
+    }
+
+    /// XOR a byte onto one plane of the screen from coordinates x and y,
+    /// pixels off the screen are dropped if clip is set and wrap otherwise.
+    /// Return true if pixel collision happened.
+    pub fn draw_byte(&mut self, plane: u8, x: usize, y: usize, byte: u8, clip: bool) -> Result<bool, Chip8Error> {
+        self.draw_bits(plane, x, y, &config::get_bit_values(byte), clip)
+    }
+
+    /// XOR a 16 pixel wide sprite row onto one plane of the screen from
+    /// coordinates x and y, clipped or wrapped like `draw_byte`.
+    /// Return true if pixel collision happened.
+    pub fn draw_word(&mut self, plane: u8, x: usize, y: usize, word: u16, clip: bool) -> Result<bool, Chip8Error> {
+        let high = config::get_bit_values((word >> 8) as u8);
+        let low = config::get_bit_values(word as u8);
+        let mut bits = [false; 16];
+        bits[..8].copy_from_slice(&high);
+        bits[8..].copy_from_slice(&low);
+        self.draw_bits(plane, x, y, &bits, clip)
+    }
+
+    fn draw_bits(&mut self, plane: u8, x: usize, y: usize, bits: &[bool], clip: bool) -> Result<bool, Chip8Error> {
+        let mut collision_happened = false;
+        if clip && y >= self.height {
+            return Ok(false);
+        }
+        for (pixel, bit) in bits.iter().enumerate() {
+            if !bit {
+                continue;
+            }
+            if clip && x + pixel >= self.width {
+                break;
+            }
+            let px = (x + pixel) % self.width;
+            let py = y % self.height;
+            let curr_val = self.get_pixel(px, py)?;
+            if (curr_val & plane) != 0 {
+                collision_happened = true;
+            }
+            self.set_pixel(px, py, curr_val ^ plane)?;
+        }
+        Ok(collision_happened)
+    }
+
+    /// Render the screen as text, one line per row: `.` for pixels that
+    /// are off, `#` for the first plane, `+` for the second and `@` for both.
+    pub fn to_ascii(&self) -> String {
+        let mut text = String::with_capacity((self.width + 1) * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                text.push(['.', '#', '+', '@'][(self.pixels[x][y] & 0b11) as usize]);
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Move the selected planes by dx, dy pixels, filling in blank pixels.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let planes = self.planes;
+        let old = self.pixels.clone();
+        for x in 0..self.width {
+            for y in 0..self.height {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let moved = if (0..self.width as isize).contains(&src_x) && (0..self.height as isize).contains(&src_y) {
+                    old[src_x as usize][src_y as usize] & planes
+                } else {
+                    0
+                };
+                self.pixels[x][y] = (old[x][y] & !planes) | moved;
+            }
+        }
+    }
+
+    /// Scroll the screen down by n pixels.
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    /// Scroll the screen up by n pixels.
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    /// Scroll the screen right by n pixels.
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    /// Scroll the screen left by n pixels.
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
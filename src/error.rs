@@ -0,0 +1,73 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// error.rs file
+//
+
+use std::fmt;
+
+/// Everything that can go wrong while loading or running a program.
+#[derive(Debug)]
+pub enum Chip8Error {
+    /// The ROM doesn't fit into the memory after 0x200.
+    RomTooLarge { size: usize, max: usize },
+    /// The ROM file couldn't be read.
+    RomIo(std::io::Error),
+    /// A call was made with every stack slot in use.
+    StackOverflow,
+    /// A return was made with an empty stack.
+    StackUnderflow,
+    /// The program counter left the addressable memory.
+    PcOutOfBounds(u16),
+    /// An instruction tried to access memory outside of the RAM.
+    MemoryOutOfBounds(usize),
+    /// A pixel outside of the display was addressed.
+    PixelOutOfBounds { x: usize, y: usize },
+    /// No sound device could be opened.
+    AudioUnavailable(String),
+    /// No window could be opened.
+    WindowUnavailable(String),
+    /// A key instruction was given a value that isn't on the keypad.
+    InvalidKey(u8),
+    /// A save state is damaged or from an incompatible version.
+    InvalidState(String),
+    /// A save state was made with a different ROM.
+    StateRomMismatch,
+    /// A save state file couldn't be read or written.
+    StateIo(std::io::Error),
+    /// A movie file is damaged or doesn't fit the ROM.
+    InvalidMovie(String),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::RomTooLarge { size, max } => write!(f, "ROM is {} bytes, but only {} bytes fit into memory", size, max),
+            Chip8Error::RomIo(error)              => write!(f, "couldn't read ROM: {}", error),
+            Chip8Error::StackOverflow             => write!(f, "stack overflow"),
+            Chip8Error::StackUnderflow            => write!(f, "return with an empty stack"),
+            Chip8Error::PcOutOfBounds(pc)         => write!(f, "program counter out of memory bounds: {:#05X}", pc),
+            Chip8Error::MemoryOutOfBounds(addr)   => write!(f, "memory access out of bounds: {:#05X}", addr),
+            Chip8Error::PixelOutOfBounds { x, y } => write!(f, "pixel out of bounds: ({}, {})", x, y),
+            Chip8Error::AudioUnavailable(error)   => write!(f, "audio unavailable: {}", error),
+            Chip8Error::WindowUnavailable(error)  => write!(f, "couldn't create window: {}", error),
+            Chip8Error::InvalidKey(key)           => write!(f, "invalid key: {:#04X}", key),
+            Chip8Error::InvalidState(error)       => write!(f, "invalid save state: {}", error),
+            Chip8Error::StateRomMismatch          => write!(f, "save state belongs to a different ROM"),
+            Chip8Error::StateIo(error)            => write!(f, "couldn't access save state: {}", error),
+            Chip8Error::InvalidMovie(error)       => write!(f, "invalid movie: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::RomIo(error) => Some(error),
+            Chip8Error::StateIo(error) => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,437 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// gdb.rs file
+//
+
+use crate::config;
+use crate::cpu::{Cpu, Step, StepHook};
+use crate::debugger::DebugFrontend;
+use crate::error::Chip8Error;
+
+use std::collections::BTreeSet;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+/// Register numbers as the target description lists them.
+const REG_I: usize  = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+const REGS_NUM: usize = 21;
+
+/// Describes the machine to the debugger: V0-VF, I, PC, SP, DT and ST,
+/// in the order and sizes of the `g` packet, little endian.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+/// Signals in stop replies.
+const SIGINT: u8    = 2;
+const SIGTRAP: u8   = 5;
+const SIGSEGV: u8   = 11;
+
+/// What the stub lets the machine do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Stopped, answering packets. The signal is reported to the debugger.
+    Stopped(u8),
+    /// Run one instruction, then stop.
+    Step,
+    /// Run until a breakpoint or an interrupt from the debugger.
+    Continue,
+    /// The debugger went away with `D`, run freely.
+    Detached,
+}
+
+/// GDB Remote Serial Protocol server. The machine waits for the debugger
+/// before the first instruction, and from then on runs, steps and stops
+/// at breakpoints as it is told. Registers are V0-VF, I, PC, SP, DT and
+/// ST (see the target description it sends), memory is the machine's RAM.
+pub struct GdbStub {
+    reader:         BufReader<Box<dyn Read>>,
+    output:         Box<dyn Write>,
+    /// The socket behind `reader` and `output`, polled for interrupts
+    /// while the machine runs.
+    socket:         Option<TcpStream>,
+    breakpoints:    BTreeSet<u16>,
+    mode:           Mode,
+    /// Don't stop at the breakpoint on this address, execution just
+    /// resumed from it.
+    resumed_at:     Option<u16>,
+    /// The debugger resumed the machine and waits for the stop reply.
+    running:        bool,
+    quit:           bool,
+}
+
+impl GdbStub {
+    /// Listen on localhost and wait for a debugger to connect. `waiting`
+    /// is called with the address listened on before the wait starts,
+    /// the address of the debugger is returned with the stub.
+    pub fn listen(port: u16, waiting: impl FnOnce(SocketAddr)) -> io::Result<(Self, SocketAddr)> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        waiting(listener.local_addr()?);
+        let (stream, address) = listener.accept()?;
+        Ok((Self::new(stream)?, address))
+    }
+
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let mut stub = Self::with_io(Box::new(stream.try_clone()?), Box::new(stream.try_clone()?));
+        stub.socket = Some(stream);
+        Ok(stub)
+    }
+
+    /// Talk to the debugger over any pair of streams instead of a socket,
+    /// e.g. in tests. Interrupts are only noticed on a socket.
+    pub fn with_io(input: Box<dyn Read>, output: Box<dyn Write>) -> Self {
+        Self {
+            reader:         BufReader::new(input),
+            output,
+            socket:         None,
+            breakpoints:    BTreeSet::new(),
+            mode:           Mode::Stopped(SIGTRAP),
+            resumed_at:     None,
+            running:        false,
+            quit:           false,
+        }
+    }
+
+    fn should_stop(&mut self, cpu: &Cpu) -> bool {
+        let pc = cpu.chip8.registers.pc;
+        if self.mode == Mode::Detached {
+            return false;
+        }
+        if self.resumed_at.take() != Some(pc) && self.breakpoints.contains(&pc) {
+            self.mode = Mode::Stopped(SIGTRAP);
+        }
+        matches!(self.mode, Mode::Stopped(_))
+    }
+
+    /// While running, check whether the debugger sent an interrupt.
+    fn poll_interrupt(&mut self) {
+        if self.mode != Mode::Continue || !self.reader.buffer().is_empty() {
+            return;
+        }
+        let Some(socket) = self.socket.as_mut() else {
+            return;
+        };
+        let mut byte = [0u8; 1];
+        let _ = socket.set_nonblocking(true);
+        let read = socket.read(&mut byte);
+        let _ = socket.set_nonblocking(false);
+        match read {
+            Ok(0)                   => self.quit = true,
+            Ok(_) if byte[0] == 3   => self.mode = Mode::Stopped(SIGINT),
+            _                       => (),
+        }
+    }
+
+    /// Report the stop and answer packets until the debugger resumes the
+    /// machine, detaches or goes away.
+    fn serve(&mut self, cpu: &mut Cpu) {
+        if let (Mode::Stopped(signal), true) = (self.mode, self.running) {
+            self.send(&format!("S{:02x}", signal));
+        }
+        self.running = false;
+        while let Mode::Stopped(_) = self.mode {
+            let Some(packet) = self.receive() else {
+                self.quit = true;
+                return;
+            };
+            let reply = self.handle(cpu, &packet);
+            if let Some(reply) = reply {
+                self.send(&reply);
+            }
+            if self.quit {
+                return;
+            }
+        }
+        self.resumed_at = Some(cpu.chip8.registers.pc);
+    }
+
+    /// Answer a packet, None for the commands that resume the machine and
+    /// only answer once it stops again.
+    fn handle(&mut self, cpu: &mut Cpu, packet: &str) -> Option<String> {
+        let reply = match packet.as_bytes().first().copied().unwrap_or(0) {
+            b'?' => format!("S{:02x}", match self.mode { Mode::Stopped(signal) => signal, _ => SIGTRAP }),
+            b'g' => (0..REGS_NUM).map(|n| register_hex(cpu, n)).collect(),
+            b'G' => {
+                let bytes = decode_hex(&packet[1..]);
+                if bytes.len() != (0..REGS_NUM).map(register_size).sum() {
+                    return Some("E01".to_string());
+                }
+                let mut pos = 0;
+                for n in 0..REGS_NUM {
+                    set_register(cpu, n, &bytes[pos..pos + register_size(n)]);
+                    pos += register_size(n);
+                }
+                "OK".to_string()
+            },
+            b'p' => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(n) if n < REGS_NUM   => register_hex(cpu, n),
+                _                       => "E01".to_string(),
+            },
+            b'P' => {
+                let parsed = packet[1..].split_once('=').and_then(|(n, value)| Some((usize::from_str_radix(n, 16).ok()?, decode_hex(value))));
+                match parsed {
+                    Some((n, value)) if n < REGS_NUM && value.len() == register_size(n) => {
+                        set_register(cpu, n, &value);
+                        "OK".to_string()
+                    },
+                    _ => "E01".to_string(),
+                }
+            },
+            b'm' => match parse_range(&packet[1..]).and_then(|(addr, len)| cpu.chip8.memory.ram.get(addr..addr.checked_add(len)?)) {
+                Some(bytes) => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+                None        => "E01".to_string(),
+            },
+            b'M' => {
+                let write = packet[1..].split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_range(range)?;
+                    let bytes = decode_hex(data);
+                    let target = cpu.chip8.memory.ram.get_mut(addr..addr.checked_add(len)?)?;
+                    (bytes.len() == len).then(|| target.copy_from_slice(&bytes))
+                });
+                match write {
+                    Some(())    => "OK".to_string(),
+                    None        => "E01".to_string(),
+                }
+            },
+            b'Z' | b'z' => {
+                let mut fields = packet[1..].split(',');
+                let (kind, addr) = (fields.next(), fields.next().and_then(|addr| u16::from_str_radix(addr, 16).ok()));
+                match (kind, addr) {
+                    (Some("0"), Some(addr)) => {
+                        if packet.starts_with('Z') {
+                            self.breakpoints.insert(addr);
+                        } else {
+                            self.breakpoints.remove(&addr);
+                        }
+                        "OK".to_string()
+                    },
+                    // hardware breakpoints and watchpoints aren't supported
+                    _ => String::new(),
+                }
+            },
+            b'c' | b's' => {
+                if let Some(addr) = packet.get(1..).filter(|addr| !addr.is_empty()) {
+                    match u16::from_str_radix(addr, 16) {
+                        Ok(addr)    => cpu.chip8.registers.pc = addr,
+                        Err(_)      => return Some("E01".to_string()),
+                    }
+                }
+                self.mode = if packet.starts_with('c') { Mode::Continue } else { Mode::Step };
+                self.running = true;
+                return None;
+            },
+            b'D' => {
+                self.mode = Mode::Detached;
+                "OK".to_string()
+            },
+            b'k' => {
+                self.quit = true;
+                return None;
+            },
+            b'H' => "OK".to_string(),
+            b'q' => match packet {
+                _ if packet.starts_with("qSupported") => "PacketSize=1000;qXfer:features:read+".to_string(),
+                _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                    let range = &packet["qXfer:features:read:target.xml:".len()..];
+                    match parse_range(range) {
+                        Some((offset, len)) => {
+                            let chunk = TARGET_XML.get(offset.min(TARGET_XML.len())..(offset.saturating_add(len)).min(TARGET_XML.len())).unwrap_or("");
+                            let more = offset.saturating_add(len) < TARGET_XML.len();
+                            format!("{}{}", if more { "m" } else { "l" }, chunk)
+                        },
+                        None => "E01".to_string(),
+                    }
+                },
+                "qAttached"     => "1".to_string(),
+                "qC"            => "QC1".to_string(),
+                "qfThreadInfo"  => "m1".to_string(),
+                "qsThreadInfo"  => "l".to_string(),
+                _               => String::new(),
+            },
+            // anything else is unsupported, which is an empty reply
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    /// Read the next packet, acknowledging it. None when the connection
+    /// is gone.
+    fn receive(&mut self) -> Option<String> {
+        let mut byte = [0u8; 1];
+        loop {
+            // skip acks and stray interrupts until a packet starts
+            loop {
+                self.reader.read_exact(&mut byte).ok()?;
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                self.reader.read_exact(&mut byte).ok()?;
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0u8; 2];
+            self.reader.read_exact(&mut checksum).ok()?;
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|text| u8::from_str_radix(text, 16).ok());
+            if expected == Some(data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))) {
+                self.output.write_all(b"+").ok()?;
+                return Some(String::from_utf8_lossy(&unescape(&data)).into_owned());
+            }
+            self.output.write_all(b"-").ok()?;
+        }
+    }
+
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        if self.output.write_all(format!("${}#{:02x}", data, checksum).as_bytes()).is_err() {
+            self.quit = true;
+        }
+    }
+}
+
+impl StepHook for GdbStub {
+    fn before_step(&mut self, cpu: &mut Cpu) -> bool {
+        if self.should_stop(cpu) {
+            cpu.chip8.present();
+            self.serve(cpu);
+        }
+        !self.quit
+    }
+
+    fn after_step(&mut self, cpu: &mut Cpu, _pc: u16, result: &Result<Step, Chip8Error>) {
+        match result {
+            Err(_)              => {
+                // let the debugger look at the wreck before the machine stops
+                self.mode = Mode::Stopped(SIGSEGV);
+                self.serve(cpu);
+            },
+            Ok(Step::Exited)    => {
+                if self.running {
+                    self.send("W00");
+                }
+            },
+            Ok(_)               => {
+                if self.mode == Mode::Step {
+                    self.mode = Mode::Stopped(SIGTRAP);
+                }
+            },
+        }
+    }
+}
+
+impl DebugFrontend for GdbStub {
+    fn run_frame(&mut self, cpu: &mut Cpu, cycles: usize) -> Result<usize, Chip8Error> {
+        self.poll_interrupt();
+        cpu.run_frame_with(cycles, self)
+    }
+
+    fn pause(&mut self) {
+        if self.mode != Mode::Detached {
+            self.mode = Mode::Stopped(SIGINT);
+        }
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.quit
+    }
+}
+
+fn register_size(n: usize) -> usize {
+    match n {
+        REG_I | REG_PC  => 2,
+        _               => 1,
+    }
+}
+
+/// The register as little endian hex.
+fn register_hex(cpu: &Cpu, n: usize) -> String {
+    let registers = &cpu.chip8.registers;
+    let value: u16 = match n {
+        REG_I   => registers.i,
+        REG_PC  => registers.pc,
+        REG_SP  => registers.sp as u16,
+        REG_DT  => registers.dt as u16,
+        REG_ST  => registers.st as u16,
+        _       => registers.v[n] as u16,
+    };
+    value.to_le_bytes()[..register_size(n)].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn set_register(cpu: &mut Cpu, n: usize, bytes: &[u8]) {
+    let registers = &mut cpu.chip8.registers;
+    let value = bytes.iter().rev().fold(0u16, |value, byte| value << 8 | *byte as u16);
+    match n {
+        REG_I   => registers.i = value,
+        REG_PC  => registers.pc = value,
+        REG_SP  => registers.sp = (value as usize).min(config::CHIP8_STACK_SIZE),
+        REG_DT  => registers.dt = value as u8,
+        REG_ST  => registers.st = value as u8,
+        _       => registers.v[n] = value as u8,
+    }
+}
+
+/// "addr,length" in hex.
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((usize::from_str_radix(addr, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+}
+
+fn decode_hex(text: &str) -> Vec<u8> {
+    text.as_bytes().chunks_exact(2)
+        .filter_map(|pair| std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+/// Undo the `}` escaping of packet data.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut escaped = false;
+    for byte in data {
+        match (escaped, *byte) {
+            (true, byte)    => {
+                bytes.push(byte ^ 0x20);
+                escaped = false;
+            },
+            (false, b'}')   => escaped = true,
+            (false, byte)   => bytes.push(byte),
+        }
+    }
+    bytes
+}
@@ -0,0 +1,684 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// harness.rs file
+//
+
+use crate::backend::{MemoryKeypad, NullAudio, NullDisplay};
+use crate::config;
+use crate::cpu::{Cpu, Instruction};
+use crate::disasm;
+use crate::error::Chip8Error;
+use crate::keypad::VKeys;
+use crate::octo;
+use crate::quirks::Quirks;
+use crate::reference::Reference;
+use crate::rng::{Rng, RngKind};
+use crate::romhandler::RomCartridge;
+use crate::variant::Variant;
+
+use std::path::Path;
+
+/// Set to regenerate the golden files instead of comparing against them.
+pub const BLESS_VAR: &str = "CHIP8_BLESS";
+
+/// Grey levels of the PNG goldens, the same as the window's default
+/// palette: off, first plane, second plane, both planes.
+#[cfg(feature = "png")]
+const PNG_LEVELS: [u8; 4] = [0x00, 0xFF, 0xAA, 0x55];
+
+/// A headless machine for `cargo test`: load a ROM, press keys, run frames
+/// and check the screen, registers and memory. The random generator is
+/// seeded with 0, so every run of a test sees the same numbers.
+///
+/// ```no_run
+/// # use chip8::harness::TestMachine;
+/// # use chip8::Variant;
+/// # let rom = [0u8; 2];
+/// let mut machine = TestMachine::new(&rom, Variant::Chip8).unwrap();
+/// machine.run_script("wait 10; tap 5; wait 10").unwrap();
+/// assert_eq!(machine.v(0), 5);
+/// machine.assert_golden("tests/golden/keys.txt");
+/// ```
+pub struct TestMachine {
+    pub cpu:                    Cpu,
+    pub instructions_per_frame: usize,
+    keypad:                     MemoryKeypad,
+    frames:                     usize,
+}
+
+impl TestMachine {
+    pub fn new(rom: &[u8], variant: Variant) -> Result<Self, Chip8Error> {
+        let keypad = MemoryKeypad::new();
+        let mut cpu = Cpu::with_backends(RomCartridge::from_bytes(rom)?, Box::new(NullDisplay), Box::new(keypad.clone()), Box::new(NullAudio));
+        cpu.set_variant(variant);
+        cpu.set_rng(Rng::new(RngKind::default(), 0));
+        cpu.load_instructions()?;
+        Ok(Self {
+            cpu,
+            instructions_per_frame: variant.default_speed(),
+            keypad,
+            frames:                 0,
+        })
+    }
+
+    /// Load a ROM file, Octo sources (.8o) are compiled first. Without a
+    /// variant it's guessed from the opcodes.
+    pub fn from_file(path: impl AsRef<Path>, variant: Option<Variant>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let rom = if path.extension().is_some_and(|extension| extension == "8o") {
+            let source = std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+            octo::compile(&source, variant.unwrap_or(Variant::XoChip)).map_err(|error| format!("{}: {}", path.display(), error))?
+        } else {
+            std::fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?
+        };
+        let variant = variant.unwrap_or_else(|| Variant::guess(&rom));
+        Self::new(&rom, variant).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.cpu.set_quirks(quirks);
+    }
+
+    pub fn set_speed(&mut self, instructions_per_frame: usize) {
+        self.instructions_per_frame = instructions_per_frame.max(1);
+    }
+
+    /// Run up to `n` frames, fewer if the program exits or jumps below 0x200.
+    pub fn run_frames(&mut self, n: usize) -> Result<(), Chip8Error> {
+        for _ in 0..n {
+            if self.stopped() {
+                break;
+            }
+            self.cpu.run_frame(self.instructions_per_frame)?;
+            self.frames += 1;
+        }
+        Ok(())
+    }
+
+    /// True once the program exited or jumped below 0x200.
+    pub fn stopped(&self) -> bool {
+        self.cpu.chip8.exited || self.cpu.chip8.registers.pc < 0x200
+    }
+
+    /// Number of frames run so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Hold a key down, the program sees it from the next frame on.
+    pub fn press(&mut self, key: u8) {
+        self.keypad.set_key(key, true);
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.keypad.set_key(key, false);
+    }
+
+    /// Hold a key for a number of frames, then release it.
+    pub fn tap(&mut self, key: u8, frames: usize) -> Result<(), Chip8Error> {
+        self.press(key);
+        self.run_frames(frames)?;
+        self.release(key);
+        Ok(())
+    }
+
+    /// Run a script of commands separated by newlines or `;`, keys are hex:
+    ///
+    /// ```text
+    /// wait 30         run 30 frames
+    /// press 5         hold key 5 down
+    /// release 5
+    /// tap 5 [N]       hold key 5 for N frames (2 by default), then release it
+    /// ```
+    pub fn run_script(&mut self, script: &str) -> Result<(), String> {
+        for command in script.split(['\n', ';']).map(str::trim).filter(|command| !command.is_empty()) {
+            let words: Vec<&str> = command.split_whitespace().collect();
+            let key = |n: usize| match words.get(n).map(|word| u8::from_str_radix(word, 16)) {
+                Some(Ok(key)) if key <= 0xF => Ok(key),
+                _                           => Err(format!("{}: expected a key from 0 to F", command)),
+            };
+            let count = |n: usize, default: usize| match words.get(n) {
+                Some(word)  => word.parse::<usize>().map_err(|_| format!("{}: expected a number of frames", command)),
+                None        => Ok(default),
+            };
+            let result = match words[0] {
+                "wait"      => self.run_frames(count(1, 1)?),
+                "press"     => {
+                    self.press(key(1)?);
+                    Ok(())
+                },
+                "release"   => {
+                    self.release(key(1)?);
+                    Ok(())
+                },
+                "tap"       => self.tap(key(1)?, count(2, 2)?),
+                _           => return Err(format!("unknown command: {}", command)),
+            };
+            result.map_err(|error| format!("{}: {}", command, error))?;
+        }
+        Ok(())
+    }
+
+    pub fn v(&self, x: usize) -> u8 {
+        self.cpu.chip8.registers.v[x]
+    }
+
+    pub fn i(&self) -> u16 {
+        self.cpu.chip8.registers.i
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.cpu.chip8.registers.pc
+    }
+
+    pub fn memory(&self, start: usize, len: usize) -> &[u8] {
+        &self.cpu.chip8.memory.ram[start..start + len]
+    }
+
+    /// The value of a pixel: 0 when off, bit 0 for the first plane and
+    /// bit 1 for the second.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.cpu.chip8.display.pixels[x][y]
+    }
+
+    /// The framebuffer as text, see `Display::to_ascii`.
+    pub fn screen(&self) -> String {
+        self.cpu.chip8.display.to_ascii()
+    }
+
+    /// Panic unless the whole screen matches the ASCII art. Blank lines and
+    /// the indentation around the art are ignored.
+    pub fn assert_screen(&self, expected: &str) {
+        let actual = self.screen();
+        if art_lines(&actual) != art_lines(expected) {
+            panic!("screen doesn't match after {} frames\nexpected:\n{}\nactual:\n{}", self.frames, art_lines(expected).join("\n"), actual);
+        }
+    }
+
+    /// Panic unless the rectangle at `x`, `y` as large as the ASCII art
+    /// matches it, the rest of the screen isn't looked at.
+    pub fn assert_pixels(&self, x: usize, y: usize, expected: &str) {
+        let expected = art_lines(expected);
+        let screen = self.screen();
+        let actual: Vec<String> = screen.lines().skip(y).take(expected.len())
+            .map(|line| line.chars().skip(x).take(expected.first().map_or(0, |row| row.chars().count())).collect())
+            .collect();
+        if actual != expected {
+            panic!("pixels at ({}, {}) don't match after {} frames\nexpected:\n{}\nactual:\n{}\nscreen:\n{}", x, y, self.frames, expected.join("\n"), actual.join("\n"), screen);
+        }
+    }
+
+    /// Compare the screen with a golden file: ASCII art for .txt files,
+    /// an image for .png files. With the `CHIP8_BLESS` environment variable
+    /// set the file is written instead.
+    pub fn check_golden(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let png = path.extension().is_some_and(|extension| extension == "png");
+        if std::env::var_os(BLESS_VAR).is_some() {
+            let bytes = if png { self.screen_png()? } else { self.screen().into_bytes() };
+            return std::fs::write(path, bytes).map_err(|error| format!("{}: {}", path.display(), error));
+        }
+        let expected = match std::fs::read(path) {
+            Ok(bytes)   => bytes,
+            Err(error)  => return Err(format!("{}: {}, run with {}=1 to create it", path.display(), error, BLESS_VAR)),
+        };
+        let expected = if png {
+            decode_png(&expected).map_err(|error| format!("{}: {}", path.display(), error))?
+        } else {
+            String::from_utf8_lossy(&expected).into_owned()
+        };
+        if art_lines(&expected) != art_lines(&self.screen()) {
+            return Err(format!("screen doesn't match {} after {} frames\nexpected:\n{}actual:\n{}", path.display(), self.frames, expected, self.screen()));
+        }
+        Ok(())
+    }
+
+    /// `check_golden` that panics on a mismatch.
+    pub fn assert_golden(&self, path: impl AsRef<Path>) {
+        if let Err(error) = self.check_golden(path) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Encode the screen as an 8-bit greyscale PNG, one pixel per pixel.
+    #[cfg(feature = "png")]
+    pub fn screen_png(&self) -> Result<Vec<u8>, String> {
+        let display = &self.cpu.chip8.display;
+        let mut data = Vec::with_capacity(display.width * display.height);
+        for y in 0..display.height {
+            for x in 0..display.width {
+                data.push(PNG_LEVELS[(display.pixels[x][y] & 0b11) as usize]);
+            }
+        }
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, display.width as u32, display.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|error| error.to_string())?;
+        writer.write_image_data(&data).map_err(|error| error.to_string())?;
+        writer.finish().map_err(|error| error.to_string())?;
+        Ok(bytes)
+    }
+
+    #[cfg(not(feature = "png"))]
+    pub fn screen_png(&self) -> Result<Vec<u8>, String> {
+        Err("PNG goldens need the png feature".to_string())
+    }
+}
+
+/// The parts of a machine a single instruction reads or changes, for
+/// table-driven opcode tests. Memory and the screen are only described
+/// where it matters: `memory` lists runs of bytes, `pixels` the pixels that
+/// are on. Build one with the chained setters:
+///
+/// ```
+/// # use chip8::harness::{run_opcode, MachineState};
+/// # use chip8::{Quirks, Variant};
+/// let before = MachineState::new().v(1, 200).v(2, 100);
+/// let after = before.clone().v(1, 44).v(0xF, 1).pc(0x202);
+/// let actual = run_opcode(Variant::Chip8, Quirks::cosmac_vip(), 0x8124, &before, &after).unwrap();
+/// assert!(after.diff(&actual).is_empty());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineState {
+    pub v:          [u8; config::CHIP8_REGISTERS_NUM],
+    pub i:          u16,
+    pub pc:         u16,
+    /// Return addresses, the innermost call last.
+    pub stack:      Vec<u16>,
+    pub dt:         u8,
+    pub st:         u8,
+    pub memory:     Vec<(usize, Vec<u8>)>,
+    /// Pixels that are on, with their plane bits.
+    pub pixels:     Vec<(usize, usize, u8)>,
+    pub hires:      bool,
+    pub planes:     u8,
+    pub rpl:        [u8; config::CHIP8_REGISTERS_NUM],
+    pub pattern:    [u8; config::XOCHIP_AUDIO_PATTERN_LEN],
+    pub pitch:      u8,
+    pub exited:     bool,
+    /// Keys held down. They are set up before the instruction and never
+    /// compared, instructions can't change them.
+    pub keys:       Vec<u8>,
+}
+
+impl MachineState {
+    /// A machine just after reset, nothing drawn or pressed.
+    pub fn new() -> Self {
+        Self {
+            v:          [0; config::CHIP8_REGISTERS_NUM],
+            i:          0,
+            pc:         0x200,
+            stack:      Vec::new(),
+            dt:         0,
+            st:         0,
+            memory:     Vec::new(),
+            pixels:     Vec::new(),
+            hires:      false,
+            planes:     1,
+            rpl:        [0; config::CHIP8_REGISTERS_NUM],
+            pattern:    [0; config::XOCHIP_AUDIO_PATTERN_LEN],
+            pitch:      config::XOCHIP_DEFAULT_PITCH,
+            exited:     false,
+            keys:       Vec::new(),
+        }
+    }
+
+    pub fn v(mut self, x: usize, value: u8) -> Self {
+        self.v[x] = value;
+        self
+    }
+
+    pub fn i(mut self, i: u16) -> Self {
+        self.i = i;
+        self
+    }
+
+    pub fn pc(mut self, pc: u16) -> Self {
+        self.pc = pc;
+        self
+    }
+
+    pub fn stack(mut self, stack: &[u16]) -> Self {
+        self.stack = stack.to_vec();
+        self
+    }
+
+    pub fn dt(mut self, dt: u8) -> Self {
+        self.dt = dt;
+        self
+    }
+
+    pub fn st(mut self, st: u8) -> Self {
+        self.st = st;
+        self
+    }
+
+    /// Bytes at an address, replacing a run that starts at the same one.
+    pub fn mem(mut self, addr: usize, bytes: &[u8]) -> Self {
+        self.memory.retain(|(start, _)| *start != addr);
+        self.memory.push((addr, bytes.to_vec()));
+        self
+    }
+
+    /// Pixels that are on in the first plane, replacing all pixels.
+    pub fn lit(mut self, pixels: &[(usize, usize)]) -> Self {
+        self.pixels = pixels.iter().map(|(x, y)| (*x, *y, 1)).collect();
+        self
+    }
+
+    /// Pixels with their plane bits, replacing all pixels.
+    pub fn pixels(mut self, pixels: &[(usize, usize, u8)]) -> Self {
+        self.pixels = pixels.to_vec();
+        self
+    }
+
+    pub fn hires(mut self, hires: bool) -> Self {
+        self.hires = hires;
+        self
+    }
+
+    pub fn planes(mut self, planes: u8) -> Self {
+        self.planes = planes;
+        self
+    }
+
+    pub fn rpl(mut self, flags: &[u8]) -> Self {
+        self.rpl[..flags.len()].copy_from_slice(flags);
+        self
+    }
+
+    pub fn pattern(mut self, pattern: &[u8; config::XOCHIP_AUDIO_PATTERN_LEN]) -> Self {
+        self.pattern = *pattern;
+        self
+    }
+
+    pub fn pitch(mut self, pitch: u8) -> Self {
+        self.pitch = pitch;
+        self
+    }
+
+    pub fn exited(mut self, exited: bool) -> Self {
+        self.exited = exited;
+        self
+    }
+
+    pub fn keys(mut self, keys: &[u8]) -> Self {
+        self.keys = keys.to_vec();
+        self
+    }
+
+    /// Build a headless machine in this state, its random generator is
+    /// seeded with 0.
+    pub fn setup(&self, variant: Variant, quirks: Quirks) -> Result<Cpu, Chip8Error> {
+        let mut cpu = Cpu::new(RomCartridge::from_bytes(&[])?);
+        cpu.set_variant(variant);
+        cpu.set_quirks(quirks);
+        cpu.set_rng(Rng::new(RngKind::default(), 0));
+        let chip8 = &mut cpu.chip8;
+        if self.stack.len() > config::CHIP8_STACK_SIZE {
+            return Err(Chip8Error::StackOverflow);
+        }
+        chip8.registers.v = self.v;
+        chip8.registers.i = self.i;
+        chip8.registers.pc = self.pc;
+        chip8.registers.sp = self.stack.len();
+        chip8.stack.stack[..self.stack.len()].copy_from_slice(&self.stack);
+        chip8.registers.dt = self.dt;
+        chip8.registers.st = self.st;
+        for (addr, bytes) in self.memory.iter() {
+            for (offset, byte) in bytes.iter().enumerate() {
+                chip8.write_byte(addr + offset, *byte)?;
+            }
+        }
+        chip8.display.set_hires(self.hires);
+        chip8.display.planes = self.planes;
+        for (x, y, value) in self.pixels.iter() {
+            chip8.display.set_pixel(*x, *y, *value)?;
+        }
+        chip8.rpl = self.rpl;
+        chip8.audio_pattern = self.pattern;
+        chip8.pitch = self.pitch;
+        chip8.exited = self.exited;
+        for key in self.keys.iter() {
+            chip8.keypad.set_key(VKeys::from_num(*key).ok_or(Chip8Error::InvalidKey(*key))?, true);
+        }
+        Ok(cpu)
+    }
+
+    /// Read the state of a machine. Memory is read at the runs `like` lists
+    /// and the keys are copied from it, so the result compares with `like`.
+    pub fn capture(cpu: &Cpu, like: &MachineState) -> Self {
+        let chip8 = &cpu.chip8;
+        let display = &chip8.display;
+        let mut pixels = Vec::new();
+        for y in 0..display.height {
+            for x in 0..display.width {
+                if display.pixels[x][y] != 0 {
+                    pixels.push((x, y, display.pixels[x][y]));
+                }
+            }
+        }
+        Self {
+            v:          chip8.registers.v,
+            i:          chip8.registers.i,
+            pc:         chip8.registers.pc,
+            stack:      chip8.stack.stack[..chip8.registers.sp].to_vec(),
+            dt:         chip8.registers.dt,
+            st:         chip8.registers.st,
+            memory:     like.memory.iter().map(|(addr, bytes)| {
+                (*addr, (0..bytes.len()).map(|offset| chip8.peek_byte(addr + offset).unwrap_or(0)).collect())
+            }).collect(),
+            pixels,
+            hires:      display.is_hires(),
+            planes:     display.planes,
+            rpl:        chip8.rpl,
+            pattern:    chip8.audio_pattern,
+            pitch:      chip8.pitch,
+            exited:     chip8.exited,
+            keys:       like.keys.clone(),
+        }
+    }
+
+    /// One line for every field that differs, `expected -> actual`, empty
+    /// when both are the same. Pixels are compared in any order and only
+    /// the first few that differ are listed.
+    pub fn diff(&self, actual: &MachineState) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut field = |name: &str, expected: String, actual: String| {
+            if expected != actual {
+                lines.push(format!("{}: {} -> {}", name, expected, actual));
+            }
+        };
+        for x in 0..config::CHIP8_REGISTERS_NUM {
+            field(&format!("V{:X}", x), format!("{:#04X}", self.v[x]), format!("{:#04X}", actual.v[x]));
+        }
+        field("I", format!("{:#05X}", self.i), format!("{:#05X}", actual.i));
+        field("PC", format!("{:#05X}", self.pc), format!("{:#05X}", actual.pc));
+        field("stack", format!("{:03X?}", self.stack), format!("{:03X?}", actual.stack));
+        field("DT", self.dt.to_string(), actual.dt.to_string());
+        field("ST", self.st.to_string(), actual.st.to_string());
+        for ((addr, expected), (_, bytes)) in self.memory.iter().zip(actual.memory.iter()) {
+            field(&format!("memory {:#05X}", addr), format!("{:02X?}", expected), format!("{:02X?}", bytes));
+        }
+        // only the pixels that differ, a screen full of them says nothing
+        let missing: Vec<_> = self.pixels.iter().filter(|pixel| !actual.pixels.contains(pixel)).take(8).collect();
+        let extra: Vec<_> = actual.pixels.iter().filter(|pixel| !self.pixels.contains(pixel)).take(8).collect();
+        field("pixels", format!("{:?}", missing), format!("{:?}", extra));
+        field("hires", self.hires.to_string(), actual.hires.to_string());
+        field("planes", self.planes.to_string(), actual.planes.to_string());
+        field("RPL flags", format!("{:02X?}", self.rpl), format!("{:02X?}", actual.rpl));
+        field("audio pattern", format!("{:02X?}", self.pattern), format!("{:02X?}", actual.pattern));
+        field("pitch", self.pitch.to_string(), actual.pitch.to_string());
+        field("exited", self.exited.to_string(), actual.exited.to_string());
+        lines
+    }
+}
+
+impl Default for MachineState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Execute a single opcode through `Cpu::execute_instruction` on a machine
+/// set up as `before`, with the opcode stored at PC, and return the state
+/// it leaves behind, captured like `after`.
+pub fn run_opcode(variant: Variant, quirks: Quirks, opcode: u16, before: &MachineState, after: &MachineState) -> Result<MachineState, Chip8Error> {
+    let mut cpu = before.setup(variant, quirks)?;
+    let pc = before.pc as usize;
+    cpu.chip8.write_byte(pc, (opcode >> 8) as u8)?;
+    cpu.chip8.write_byte(pc + 1, opcode as u8)?;
+    cpu.execute_instruction(Instruction::new(opcode))?;
+    Ok(MachineState::capture(&cpu, after))
+}
+
+/// Run `Cpu` and the `Reference` interpreter side by side on a ROM, one
+/// instruction at a time, and describe the first difference in registers,
+/// stack, memory or screen. Keys go up and down at random, the timers tick
+/// every `variant.default_speed()` instructions. Both stop at the first
+/// error, which has to be the same, or when the program exits. Return the
+/// number of instructions run.
+pub fn lockstep(rom: &[u8], variant: Variant, quirks: Quirks, steps: usize, seed: u64) -> Result<usize, String> {
+    let rng = Rng::new(RngKind::default(), seed);
+    let mut cpu = Cpu::new(RomCartridge::from_bytes(rom).map_err(|error| error.to_string())?);
+    cpu.set_variant(variant);
+    cpu.set_quirks(quirks);
+    cpu.set_rng(rng);
+    cpu.load_instructions().map_err(|error| error.to_string())?;
+    let mut reference = Reference::new(rom, variant, quirks, rng).map_err(|error| error.to_string())?;
+    let mut keys = Rng::new(RngKind::Xorshift, !seed);
+
+    for step in 0..steps {
+        if step % 16 == 0 {
+            let byte = keys.next_byte(&[]);
+            let (key, down) = (byte & 0xF, byte & 0x10 != 0);
+            reference.keys[key as usize] = down;
+            if let Some(key) = VKeys::from_num(key) {
+                cpu.chip8.keypad.set_key(key, down);
+            }
+        }
+        if step % variant.default_speed() == 0 {
+            reference.tick_timers();
+            cpu.tick_timers();
+        }
+        let pc = reference.pc;
+        let opcode = (reference.memory.get(pc as usize).copied().unwrap_or(0) as u16) << 8 | reference.memory.get(pc as usize + 1).copied().unwrap_or(0) as u16;
+        let instruction = format!("step {}, {:04X} {} at {:#05X}", step, opcode,
+            disasm::mnemonic(&Instruction::new(opcode), variant, None).unwrap_or_default(), pc);
+        match (cpu.step(), reference.step()) {
+            (Ok(_), Ok(()))     => (),
+            (Err(actual), Err(expected)) if actual.to_string() == expected.to_string() => return Ok(step),
+            (Err(actual), Err(expected)) => return Err(format!("{}: failed with \"{}\" instead of \"{}\"", instruction, actual, expected)),
+            (Err(actual), Ok(())) => return Err(format!("{}: failed with \"{}\"", instruction, actual)),
+            (Ok(_), Err(expected)) => return Err(format!("{}: didn't fail with \"{}\"", instruction, expected)),
+        }
+        if matches(&cpu, &reference) {
+            if reference.exited {
+                return Ok(step + 1);
+            }
+            continue;
+        }
+        let differences = divergence(&cpu, &reference);
+        if !differences.is_empty() {
+            return Err(format!("{}: expected -> actual\n    {}", instruction, differences.join("\n    ")));
+        }
+        if reference.exited {
+            return Ok(step + 1);
+        }
+    }
+    Ok(steps)
+}
+
+/// Quick check that the machine and the reference agree, before working
+/// out where they don't.
+fn matches(cpu: &Cpu, reference: &Reference) -> bool {
+    let chip8 = &cpu.chip8;
+    let registers = &chip8.registers;
+    let display = &chip8.display;
+    registers.v == reference.v && registers.i == reference.i && registers.pc == reference.pc
+        && chip8.stack.stack[..registers.sp] == reference.stack[..]
+        && registers.dt == reference.dt && registers.st == reference.st
+        && chip8.memory.ram == reference.memory
+        && display.width == reference.width && display.planes == reference.planes
+        && (0..display.height).all(|y| (0..display.width).all(|x| display.pixels[x][y] == reference.pixel(x, y)))
+        && chip8.rpl == reference.rpl && chip8.audio_pattern == reference.pattern
+        && chip8.pitch == reference.pitch && chip8.exited == reference.exited
+}
+
+/// Where the machine differs from the reference, one line per difference.
+fn divergence(cpu: &Cpu, reference: &Reference) -> Vec<String> {
+    let mut pixels = Vec::new();
+    for y in 0..reference.height {
+        for x in 0..reference.width {
+            if reference.pixel(x, y) != 0 {
+                pixels.push((x, y, reference.pixel(x, y)));
+            }
+        }
+    }
+    let expected = MachineState {
+        v:          reference.v,
+        i:          reference.i,
+        pc:         reference.pc,
+        stack:      reference.stack.clone(),
+        dt:         reference.dt,
+        st:         reference.st,
+        memory:     Vec::new(),
+        pixels,
+        hires:      reference.width == config::SCHIP_DISPLAY_WIDTH,
+        planes:     reference.planes,
+        rpl:        reference.rpl,
+        pattern:    reference.pattern,
+        pitch:      reference.pitch,
+        exited:     reference.exited,
+        keys:       Vec::new(),
+    };
+    let mut differences = expected.diff(&MachineState::capture(cpu, &expected));
+    let ram = &cpu.chip8.memory.ram;
+    let bytes: Vec<String> = (0..reference.memory.len().max(ram.len()))
+        .filter(|addr| reference.memory.get(*addr) != ram.get(*addr))
+        .take(8)
+        .map(|addr| format!("{:#05X}: {:02X?} -> {:02X?}", addr, reference.memory.get(addr), ram.get(addr)))
+        .collect();
+    if !bytes.is_empty() {
+        differences.push(format!("memory {}", bytes.join(", ")));
+    }
+    differences
+}
+
+/// The lines of an ASCII art screen without surrounding whitespace and
+/// blank lines, so art can be indented inside a test.
+fn art_lines(text: &str) -> Vec<String> {
+    text.lines().map(str::trim).filter(|line| !line.is_empty()).map(str::to_string).collect()
+}
+
+/// Turn a golden image back into ASCII art. Any colour type works as long
+/// as the pixels are one of the grey levels the goldens are written with.
+#[cfg(feature = "png")]
+fn decode_png(bytes: &[u8]) -> Result<String, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|error| error.to_string())?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|error| error.to_string())?;
+    let channels = info.color_type.samples();
+    let mut text = String::with_capacity((info.width as usize + 1) * info.height as usize);
+    for row in data[..info.buffer_size()].chunks(info.line_size) {
+        for pixel in row.chunks(channels).take(info.width as usize) {
+            match PNG_LEVELS.iter().position(|level| *level == pixel[0]) {
+                Some(value) => text.push(['.', '#', '+', '@'][value]),
+                None        => return Err(format!("unexpected colour {:#04X}, goldens only use {:02X?}", pixel[0], PNG_LEVELS)),
+            }
+        }
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+#[cfg(not(feature = "png"))]
+fn decode_png(_bytes: &[u8]) -> Result<String, String> {
+    Err("PNG goldens need the png feature".to_string())
+}
@@ -6,195 +6,185 @@
 // io.rs file
 //
 
-#[path = "config.rs"]
-mod config;
-
-use minifb::{Key, KeyRepeat, WindowOptions};
-
-pub enum VKeys {
-    Key1,
-    Key2,
-    Key3,
-    KeyC,
-    Key4,
-    Key5,
-    Key6,
-    KeyD,
-    Key7,
-    Key8,
-    Key9,
-    KeyE,
-    KeyA,
-    Key0,
-    KeyB,
-    KeyF,
-}
+use crate::backend::{DisplaySink, Hotkey, KeypadSource};
+use crate::config;
+use crate::display::Display;
+use crate::error::Chip8Error;
+use crate::keypad::{Keypad, VKeys};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
 
 const PIXEL_ON:  u32    = 0b00000000111111111111111111111111;
 const PIXEL_OFF: u32    = 0;
-
+// colours for pixels set on the second XO-CHIP plane only, and on both
+const PIXEL_PLANE2: u32 = 0x00AAAAAA;
+const PIXEL_BOTH:   u32 = 0x00555555;
+pub const DEFAULT_PALETTE: [u32; 4] = [PIXEL_OFF, PIXEL_ON, PIXEL_PLANE2, PIXEL_BOTH];
+
+/// Keyboard keys for the hex keys 0 to F, in the usual layout:
+///
+/// ```text
+/// 1 2 3 4        1 2 3 C
+/// Q W E R   ->   4 5 6 D
+/// A S D F        7 8 9 E
+/// Z X C V        A 0 B F
+/// ```
+pub const DEFAULT_KEYMAP: &str = "x123qweasdzc4rfv";
+
+/// minifb window used both as display and as keypad. Clones share the
+/// same window, hand one to each side of the machine once the palette and
+/// keymap are set.
+#[derive(Clone)]
 pub struct IO {
-    window: crate::minifb::Window,
-    keys: [bool; config::CHIP8_KEYBOARD_SIZE],
-    pub pixels: [[bool; config::CHIP8_DISPLAY_HEIGHT]; config::CHIP8_DISPLAY_WIDTH],
+    window: Rc<RefCell<Window>>,
+    palette: [u32; 4],
+    keymap: [Key; config::CHIP8_KEYBOARD_SIZE],
 }
 
 impl IO {
-    pub fn new(win: crate::minifb::Window) -> Self {
-        Self {
-            keys: [false; config::CHIP8_KEYBOARD_SIZE],
-            pixels: [[false; config::CHIP8_DISPLAY_HEIGHT]; config::CHIP8_DISPLAY_WIDTH],
-            window: win,
-        }
+    pub fn new(win: Window) -> Self {
+        let mut io = Self {
+            window: Rc::new(RefCell::new(win)),
+            palette: DEFAULT_PALETTE,
+            keymap: [Key::Unknown; config::CHIP8_KEYBOARD_SIZE],
+        };
+        let _ = io.set_keymap(DEFAULT_KEYMAP);
+        io
     }
 
-    fn check_pixel_in_bounds(x: usize, y: usize) {
-        if ((x < config::CHIP8_DISPLAY_WIDTH) && (0 <= x)) == false {
-            panic!("Error: Pixel out of bounds");
-        }
-        if ((y < config::CHIP8_DISPLAY_HEIGHT) && (0 <= y)) == false {
-            panic!("Error: Pixel out of bounds");
-        }
+    /// Open a window big enough for the high resolution display. `scale` is
+    /// the size of a low resolution pixel on screen: 2, 4, 8, 16, 32 or 64.
+    pub fn open(title: &str, scale: usize) -> Result<Self, Chip8Error> {
+        let window_scale = match scale {
+            2   => minifb::Scale::X1,
+            4   => minifb::Scale::X2,
+            8   => minifb::Scale::X4,
+            16  => minifb::Scale::X8,
+            32  => minifb::Scale::X16,
+            64  => minifb::Scale::X32,
+            _   => return Err(Chip8Error::WindowUnavailable(format!("unsupported scale: {}", scale))),
+        };
+        let window = Window::new(title, config::SCHIP_DISPLAY_WIDTH, config::SCHIP_DISPLAY_HEIGHT, WindowOptions {
+            scale: window_scale,
+            borderless: false,
+            ..WindowOptions::default()
+        }).map_err(|error| Chip8Error::WindowUnavailable(error.to_string()))?;
+        Ok(Self::new(window))
     }
 
-    pub fn set_pixel(&mut self, x: usize, y: usize, val: bool) {
-        Self::check_pixel_in_bounds(x, y);
-        self.pixels[x][y] = val;
+    pub fn limit_update_rate(&mut self, rate: Option<Duration>) {
+        self.window.borrow_mut().limit_update_rate(rate);
     }
 
-    pub fn get_pixel(&mut self, x: usize, y: usize) -> bool {
-        Self::check_pixel_in_bounds(x, y);
-        self.pixels[x][y]
+    /// Colours (0RGB) for pixels that are off, on the first plane, on the
+    /// second plane and on both.
+    pub fn set_palette(&mut self, palette: [u32; 4]) {
+        self.palette = palette;
     }
 
-    pub fn render(&mut self) {
-        let mut buffer: [u32; config::CHIP8_DISPLAY_WIDTH * config::CHIP8_DISPLAY_HEIGHT] = [PIXEL_OFF; config::CHIP8_DISPLAY_WIDTH * config::CHIP8_DISPLAY_HEIGHT];
-        let mut pixel_coordinate: usize = 0;
-        for x in 0..config::CHIP8_DISPLAY_WIDTH {
-            for y in 0..config::CHIP8_DISPLAY_HEIGHT {
-                pixel_coordinate = (y * config::CHIP8_DISPLAY_WIDTH) + x;
-                if self.get_pixel(x, y) == true {
-                    buffer[pixel_coordinate] = PIXEL_ON;
-                }
-            }
+    /// Set the keyboard keys for the hex keys 0 to F from a 16 character
+    /// string of letters and digits, see `DEFAULT_KEYMAP`.
+    pub fn set_keymap(&mut self, keymap: &str) -> Result<(), String> {
+        let chars: Vec<char> = keymap.chars().collect();
+        if chars.len() != config::CHIP8_KEYBOARD_SIZE {
+            return Err(format!("keymap needs {} keys, got {}", config::CHIP8_KEYBOARD_SIZE, chars.len()));
         }
-        self.window.update_with_buffer(&buffer, 64, 32);
-    }
-    
-    /// Clear screen
-    pub fn clear(&mut self) {
-        self.pixels = [[false; config::CHIP8_DISPLAY_HEIGHT]; config::CHIP8_DISPLAY_WIDTH];
+        for (n, c) in chars.iter().enumerate() {
+            self.keymap[n] = key_from_char(*c).ok_or(format!("can't map key: {}", c))?;
+        }
+        Ok(())
     }
 
-    /// XOR a byte onto the screen from coordinates x and y
-    /// Return true if pixel collision happened.
-    pub fn draw_byte(&mut self, x: usize, y: usize, byte: u8) -> bool {
-        let bits = config::get_bit_values(byte);
-        let mut collision_happened = false;
-        for pixel in 0..8 {
-            let curr_val = self.get_pixel((x + pixel) & 63, y & 31);
-            self.set_pixel((x + pixel) & 63, y & 31, curr_val ^ bits[pixel]);
-            if (curr_val == bits[pixel]) && (bits[pixel] == true) {
-                collision_happened = true;
+    pub fn render(&mut self, display: &Display) {
+        let mut buffer: [u32; config::SCHIP_DISPLAY_WIDTH * config::SCHIP_DISPLAY_HEIGHT] = [PIXEL_OFF; config::SCHIP_DISPLAY_WIDTH * config::SCHIP_DISPLAY_HEIGHT];
+        let scale = config::SCHIP_DISPLAY_WIDTH / display.width;
+        for x in 0..config::SCHIP_DISPLAY_WIDTH {
+            for y in 0..config::SCHIP_DISPLAY_HEIGHT {
+                let pixel_coordinate = (y * config::SCHIP_DISPLAY_WIDTH) + x;
+                buffer[pixel_coordinate] = self.palette[(display.pixels[x / scale][y / scale] & 0b11) as usize];
             }
         }
-        return collision_happened;
-    }
-
-    pub fn get_key_status_from_vkey(&mut self, key: VKeys) -> bool {
-        self.keys[key as usize]
+        let _ = self.window.borrow_mut().update_with_buffer(&buffer, config::SCHIP_DISPLAY_WIDTH, config::SCHIP_DISPLAY_HEIGHT);
     }
 
-    pub fn get_key_status_from_num(&mut self, n: u8) -> bool {
-        match n {
-            0x1 => return self.get_key_status_from_vkey(VKeys::Key1),
-            0x2 => return self.get_key_status_from_vkey(VKeys::Key2),
-            0x3 => return self.get_key_status_from_vkey(VKeys::Key3),
-            0xC => return self.get_key_status_from_vkey(VKeys::KeyC),
-            0x4 => return self.get_key_status_from_vkey(VKeys::Key4),
-            0x5 => return self.get_key_status_from_vkey(VKeys::Key5),
-            0x6 => return self.get_key_status_from_vkey(VKeys::Key6),
-            0xD => return self.get_key_status_from_vkey(VKeys::KeyD),
-            0x7 => return self.get_key_status_from_vkey(VKeys::Key7),
-            0x8 => return self.get_key_status_from_vkey(VKeys::Key8),
-            0x9 => return self.get_key_status_from_vkey(VKeys::Key9),
-            0xE => return self.get_key_status_from_vkey(VKeys::KeyE),
-            0xA => return self.get_key_status_from_vkey(VKeys::KeyA),
-            0x0 => return self.get_key_status_from_vkey(VKeys::Key0),
-            0xB => return self.get_key_status_from_vkey(VKeys::KeyB),
-            0xF => return self.get_key_status_from_vkey(VKeys::KeyF),
-            _   => panic!("ERROR: Couldn't parse KEYID"),
-        };
+    /// Return false once the window was closed or escape was pressed.
+    pub fn poll_quit(&mut self) -> bool {
+        let window = self.window.borrow();
+        window.is_open() && !window.is_key_down(Key::Escape)
     }
 
-    pub fn poll_quit(&mut self) -> bool {
-        self.render();
-        if (!self.window.is_open()) | (!self.window.is_key_down(Key::Escape)) {
-            return true;
-        } else {
-            return false;
+    pub fn check_keys(&mut self, keypad: &mut Keypad) {
+        let window = self.window.borrow();
+        for (n, key) in self.keymap.iter().enumerate() {
+            if let Some(vkey) = VKeys::from_num(n as u8) {
+                keypad.set_key(vkey, window.is_key_down(*key));
+            }
         }
     }
+}
+
+fn key_from_char(c: char) -> Option<Key> {
+    let key = match c.to_ascii_lowercase() {
+        '0' => Key::Key0, '1' => Key::Key1, '2' => Key::Key2, '3' => Key::Key3,
+        '4' => Key::Key4, '5' => Key::Key5, '6' => Key::Key6, '7' => Key::Key7,
+        '8' => Key::Key8, '9' => Key::Key9,
+        'a' => Key::A, 'b' => Key::B, 'c' => Key::C, 'd' => Key::D, 'e' => Key::E,
+        'f' => Key::F, 'g' => Key::G, 'h' => Key::H, 'i' => Key::I, 'j' => Key::J,
+        'k' => Key::K, 'l' => Key::L, 'm' => Key::M, 'n' => Key::N, 'o' => Key::O,
+        'p' => Key::P, 'q' => Key::Q, 'r' => Key::R, 's' => Key::S, 't' => Key::T,
+        'u' => Key::U, 'v' => Key::V, 'w' => Key::W, 'x' => Key::X, 'y' => Key::Y,
+        'z' => Key::Z,
+        _   => return None,
+    };
+    Some(key)
+}
 
-    pub fn check_keys(&mut self) {
-        self.render();
+impl DisplaySink for IO {
+    fn present(&mut self, display: &Display) {
+        self.render(display);
+    }
+}
 
-        self.keys = [false; config::CHIP8_KEYBOARD_SIZE];
+impl KeypadSource for IO {
+    fn poll_keys(&mut self, keypad: &mut Keypad) {
+        self.check_keys(keypad);
+    }
 
-        self.window.get_keys().iter().for_each(|key| 
-            match key {
-                Key::Key1 => self.keys[VKeys::Key1 as usize] = true,
-                Key::Key2 => self.keys[VKeys::Key2 as usize] = true,
-                Key::Key3 => self.keys[VKeys::Key3 as usize] = true,
-                Key::Key4 => self.keys[VKeys::KeyC as usize] = true,
-                Key::Q    => self.keys[VKeys::Key4 as usize] = true,
-                Key::W    => self.keys[VKeys::Key5 as usize] = true,
-                Key::E    => self.keys[VKeys::Key6 as usize] = true,
-                Key::R    => self.keys[VKeys::KeyD as usize] = true,
-                Key::A    => self.keys[VKeys::Key7 as usize] = true,
-                Key::S    => self.keys[VKeys::Key8 as usize] = true,
-                Key::D    => self.keys[VKeys::Key9 as usize] = true,
-                Key::F    => self.keys[VKeys::KeyE as usize] = true,
-                Key::Z    => self.keys[VKeys::KeyA as usize] = true,
-                Key::X    => self.keys[VKeys::Key0 as usize] = true,
-                Key::C    => self.keys[VKeys::KeyB as usize] = true,
-                Key::V    => self.keys[VKeys::KeyF as usize] = true,
-                _ => (),
-        });
+    fn quit_requested(&mut self) -> bool {
+        !self.poll_quit()
     }
 
-    pub fn wait_for_key(&mut self) -> u8 {
-        self.render();
-        let mut key: Result<u8, ()> = Err(());
-        let mut keys = self.window.get_keys();
-        while key == Err(()) {
-            while (keys.len() == 0) {
-                self.render();
-                keys = self.window.get_keys();
+    /// PageUp/+ speed up, PageDown/- slow down, F1-F9 load the state in
+    /// that slot and Shift+F1-F9 save it, holding Backspace rewinds, F12
+    /// breaks into the debugger.
+    fn poll_hotkeys(&mut self) -> Vec<Hotkey> {
+        let window = self.window.borrow();
+        let mut hotkeys: Vec<Hotkey> = window.get_keys_pressed(KeyRepeat::Yes).iter().filter_map(|key|
+            match key {
+                Key::PageUp | Key::Equal | Key::NumPadPlus      => Some(Hotkey::SpeedUp),
+                Key::PageDown | Key::Minus | Key::NumPadMinus   => Some(Hotkey::SlowDown),
+                _ => None,
+        }).collect();
+        if window.is_key_down(Key::Backspace) {
+            hotkeys.push(Hotkey::Rewind);
+        }
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        for key in window.get_keys_pressed(KeyRepeat::No) {
+            if key == Key::F12 {
+                hotkeys.push(Hotkey::Break);
+                continue;
             }
-            key = match keys[0] {
-                Key::Key1   => Ok(0x1),
-                Key::Key2   => Ok(0x2),
-                Key::Key3   => Ok(0x3),
-                Key::Key4   => Ok(0xC),
-                Key::Q      => Ok(0x4),
-                Key::W      => Ok(0x5),
-                Key::E      => Ok(0x6),
-                Key::R      => Ok(0xD),
-                Key::A      => Ok(0x7),
-                Key::S      => Ok(0x8),
-                Key::D      => Ok(0x9),
-                Key::F      => Ok(0xE),
-                Key::Z      => Ok(0xA),
-                Key::X      => Ok(0x0),
-                Key::C      => Ok(0xB),
-                Key::V      => Ok(0xF),
-                Key::Escape => std::process::exit(0),
-                _           => Err(()),
+            let slot = match key {
+                Key::F1 => 1, Key::F2 => 2, Key::F3 => 3, Key::F4 => 4, Key::F5 => 5,
+                Key::F6 => 6, Key::F7 => 7, Key::F8 => 8, Key::F9 => 9,
+                _ => continue,
             };
-            self.render();
-            keys = self.window.get_keys();
+            hotkeys.push(if shift { Hotkey::SaveState(slot) } else { Hotkey::LoadState(slot) });
         }
-        return key.unwrap();
-    } 
+        hotkeys
+    }
 }
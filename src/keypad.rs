@@ -0,0 +1,124 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// keypad.rs file
+//
+
+use crate::config;
+use crate::error::Chip8Error;
+
+pub enum VKeys {
+    Key1,
+    Key2,
+    Key3,
+    KeyC,
+    Key4,
+    Key5,
+    Key6,
+    KeyD,
+    Key7,
+    Key8,
+    Key9,
+    KeyE,
+    KeyA,
+    Key0,
+    KeyB,
+    KeyF,
+}
+
+impl VKeys {
+    pub fn from_num(n: u8) -> Option<Self> {
+        match n {
+            0x1 => Some(VKeys::Key1),
+            0x2 => Some(VKeys::Key2),
+            0x3 => Some(VKeys::Key3),
+            0xC => Some(VKeys::KeyC),
+            0x4 => Some(VKeys::Key4),
+            0x5 => Some(VKeys::Key5),
+            0x6 => Some(VKeys::Key6),
+            0xD => Some(VKeys::KeyD),
+            0x7 => Some(VKeys::Key7),
+            0x8 => Some(VKeys::Key8),
+            0x9 => Some(VKeys::Key9),
+            0xE => Some(VKeys::KeyE),
+            0xA => Some(VKeys::KeyA),
+            0x0 => Some(VKeys::Key0),
+            0xB => Some(VKeys::KeyB),
+            0xF => Some(VKeys::KeyF),
+            _   => None,
+        }
+    }
+
+    pub fn to_num(&self) -> u8 {
+        match self {
+            VKeys::Key1 => 0x1,
+            VKeys::Key2 => 0x2,
+            VKeys::Key3 => 0x3,
+            VKeys::KeyC => 0xC,
+            VKeys::Key4 => 0x4,
+            VKeys::Key5 => 0x5,
+            VKeys::Key6 => 0x6,
+            VKeys::KeyD => 0xD,
+            VKeys::Key7 => 0x7,
+            VKeys::Key8 => 0x8,
+            VKeys::Key9 => 0x9,
+            VKeys::KeyE => 0xE,
+            VKeys::KeyA => 0xA,
+            VKeys::Key0 => 0x0,
+            VKeys::KeyB => 0xB,
+            VKeys::KeyF => 0xF,
+        }
+    }
+}
+
+/// State of the 16-key hex keypad, written by whichever frontend is
+/// driving the machine and read by the key instructions.
+pub struct Keypad {
+    pub keys: [bool; config::CHIP8_KEYBOARD_SIZE],
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Self {
+            keys: [false; config::CHIP8_KEYBOARD_SIZE],
+        }
+    }
+
+    pub fn set_key(&mut self, key: VKeys, pressed: bool) {
+        self.keys[key as usize] = pressed;
+    }
+
+    pub fn release_all(&mut self) {
+        self.keys = [false; config::CHIP8_KEYBOARD_SIZE];
+    }
+
+    pub fn get_key_status_from_vkey(&self, key: VKeys) -> bool {
+        self.keys[key as usize]
+    }
+
+    pub fn get_key_status_from_num(&self, n: u8) -> Result<bool, Chip8Error> {
+        match VKeys::from_num(n) {
+            Some(key) => Ok(self.get_key_status_from_vkey(key)),
+            None      => Err(Chip8Error::InvalidKey(n)),
+        }
+    }
+
+    /// Status of the key in the low nibble of `n`, the high nibble is
+    /// ignored like on the original interpreter.
+    pub fn get_key_status_from_nibble(&self, n: u8) -> bool {
+        self.get_key_status_from_num(n & 0xF).unwrap_or(false)
+    }
+
+    /// Return the hex value of the lowest pressed key, if any.
+    pub fn first_pressed(&self) -> Option<u8> {
+        (0..config::CHIP8_KEYBOARD_SIZE as u8).find(|n| matches!(self.get_key_status_from_num(*n), Ok(true)))
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,47 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// lib.rs file
+//
+
+pub mod config;
+pub mod variant;
+pub mod quirks;
+pub mod rng;
+pub mod error;
+pub mod charset;
+pub mod memory;
+pub mod stack;
+pub mod registers;
+pub mod display;
+pub mod keypad;
+pub mod backend;
+#[cfg(feature = "minifb")]
+pub mod io;
+#[cfg(feature = "rodio")]
+pub mod speakers;
+pub mod romhandler;
+pub mod chip8;
+pub mod cpu;
+pub mod scheduler;
+pub mod state;
+pub mod rewind;
+pub mod movie;
+pub mod disasm;
+pub mod asm;
+pub mod octo;
+pub mod debugger;
+pub mod gdb;
+pub mod trace;
+pub mod harness;
+pub mod reference;
+
+pub use crate::chip8::Chip8;
+pub use crate::error::Chip8Error;
+pub use crate::cpu::{Cpu, Instruction, Step};
+pub use crate::romhandler::RomCartridge;
+pub use crate::variant::Variant;
+pub use crate::quirks::Quirks;
+pub use crate::scheduler::Scheduler;
@@ -6,36 +6,363 @@
 // main.rs file
 //
 
-#[path = "config.rs"]
-mod config;
-#[path = "chip8.rs"]
-mod chip8;
-#[path = "cpu.rs"]
-mod cpu;
+mod cli;
 
-extern crate minifb;
-extern crate rodio;
+use chip8::asm;
+use chip8::backend::{AudioSink, DisplaySink, KeypadSource, NullAudio, NullDisplay, NullKeypad};
+use chip8::cpu::Cpu;
+use chip8::debugger::Debugger;
+use chip8::disasm;
+use chip8::gdb::GdbStub;
+use chip8::io::IO;
+use chip8::movie::{Movie, MoviePlayer, MovieRecorder};
+use chip8::octo;
+use chip8::romhandler::RomCartridge;
+use chip8::scheduler::{HotkeyEvent, Scheduler};
+use chip8::variant::Variant;
+use chip8::quirks::Quirks;
+use chip8::rng::Rng;
+use chip8::trace::Tracer;
 
-use std::time::Duration;
+use cli::{Command, DisasmOptions, MachineOptions, RunOptions, Speed, TestOptions};
 
-use minifb::Window;
-use minifb::WindowOptions;
+#[cfg(feature = "rodio")]
+fn audio_sink(mute: bool, volume: u8) -> Box<dyn AudioSink> {
+    if mute {
+        return Box::new(NullAudio);
+    }
+    match chip8::speakers::Speaker::new() {
+        Ok(mut speaker) => {
+            speaker.volume(volume as f32 / 100.0);
+            Box::new(speaker)
+        },
+        Err(error)  => {
+            eprintln!("WARNING: {}, continuing without sound", error);
+            Box::new(NullAudio)
+        },
+    }
+}
 
-fn main() {
-    let filename = config::read_rom_filename();
+#[cfg(not(feature = "rodio"))]
+fn audio_sink(_mute: bool, _volume: u8) -> Box<dyn AudioSink> {
+    Box::new(NullAudio)
+}
+
+fn is_octo_source(path: &str) -> bool {
+    std::path::Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("8o"))
+}
+
+/// Read a ROM image, or compile it first if it is Octo source. Sources are
+/// compiled for `variant`, or XO-CHIP which accepts every instruction.
+fn load_rom(path: &str, variant: Option<Variant>) -> Result<RomCartridge, String> {
+    if !is_octo_source(path) {
+        return RomCartridge::new(path.to_string()).map_err(|error| error.to_string());
+    }
+    let source = std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    let rom = octo::compile(&source, variant.unwrap_or(Variant::XoChip)).map_err(|error| format!("{}: {}", path, error))?;
+    RomCartridge::from_bytes(&rom).map_err(|error| error.to_string())
+}
+
+/// The variant to run a ROM as: the one asked for, the one the file
+/// extension says, or for Octo sources the one the compiled opcodes need.
+fn rom_variant(path: &str, variant: Option<Variant>, romcartridge: &RomCartridge) -> Option<Variant> {
+    variant
+        .or_else(|| Variant::from_filename(path))
+        .or_else(|| is_octo_source(path).then(|| Variant::guess(&romcartridge.rom)))
+}
+
+/// Build a machine for the ROM from `load_rom`, with the variant and
+/// quirks set up.
+fn load_machine(
+    options:        &MachineOptions,
+    romcartridge:   RomCartridge,
+    display_sink:   Box<dyn DisplaySink>,
+    keypad_source:  Box<dyn KeypadSource>,
+    audio_sink:     Box<dyn AudioSink>,
+) -> Result<Cpu, String> {
+    let variant = rom_variant(&options.rom, options.variant, &romcartridge);
+    let mut chip8cpu = Cpu::with_backends(romcartridge, display_sink, keypad_source, audio_sink);
+    if let Some(variant) = variant {
+        chip8cpu.set_variant(variant);
+    }
+    let mut quirks = chip8cpu.chip8.quirks;
+    let rom_quirks = std::fs::read_to_string(Quirks::rom_quirks_path(&options.rom)).ok();
+    for text in rom_quirks.iter().chain(options.quirks.iter()) {
+        quirks.apply(text)?;
+    }
+    chip8cpu.set_quirks(quirks);
+    chip8cpu.load_instructions().map_err(|error| error.to_string())?;
+    let rng_kind = options.rng.unwrap_or_default();
+    match options.seed {
+        Some(seed)  => chip8cpu.set_rng(Rng::new(rng_kind, seed)),
+        None        => chip8cpu.set_rng(Rng::from_entropy(rng_kind)),
+    }
+    if let Some(path) = &options.load_state {
+        let bytes = std::fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+        chip8cpu.load_state(&bytes).map_err(|error| format!("{}: {}", path, error))?;
+    }
+    if let Some(path) = &options.trace {
+        let file = std::fs::File::create(path).map_err(|error| format!("{}: {}", path, error))?;
+        let mut tracer = Tracer::new(Box::new(std::io::BufWriter::new(file)));
+        for (start, end) in options.trace_ranges.iter() {
+            tracer.add_range(*start, *end);
+        }
+        if let Some(lines) = options.trace_last {
+            tracer.set_ring(lines);
+        }
+        chip8cpu.set_tracer(tracer);
+    }
+    Ok(chip8cpu)
+}
 
-    let mut window = match Window::new(format!("Chip-8 - {}", filename).as_str(), 64, 32, WindowOptions {
-        scale: minifb::Scale::X8,
-        borderless: false,
-        ..WindowOptions::default()
-    }) {
-        Ok(win)     => win,
-        Err(error)  => panic!("Couldn't create window instance!"),
+fn scheduler(speed: Option<Speed>, variant: Variant) -> Scheduler {
+    match speed {
+        Some(Speed::PerFrame(instructions)) => Scheduler::new(instructions),
+        Some(Speed::Hz(hz))                 => Scheduler::from_hz(hz),
+        None                                => Scheduler::new(variant.default_speed()),
+    }
+}
+
+fn read_movie(path: &str) -> Result<Movie, String> {
+    let text = std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    Movie::from_text(&text).map_err(|error| format!("{}: {}", path, error))
+}
+
+fn report_hotkey(event: HotkeyEvent) {
+    match event {
+        HotkeyEvent::NotWhileDeterministic    => eprintln!("not available while recording or replaying a movie"),
+        HotkeyEvent::Speed(instructions)      => eprintln!("speed: {} instructions per frame", instructions),
+        HotkeyEvent::DebuggerOff              => eprintln!("the debugger is off, start with --debug"),
+        HotkeyEvent::Saved(slot)              => eprintln!("saved state to slot {}", slot),
+        HotkeyEvent::Loaded(slot)             => eprintln!("loaded state from slot {}", slot),
+        HotkeyEvent::SlotFailed(slot, error)  => eprintln!("slot {}: {}", slot, error),
+    }
+}
+
+/// Keep the last frame on screen until the user closes the window.
+fn hold_window(io: &mut IO, chip8cpu: &mut Cpu) {
+    while io.poll_quit() {
+        io.render(&chip8cpu.chip8.display);
+        std::thread::sleep(std::time::Duration::from_millis(16));
+    }
+}
+
+fn run(options: RunOptions) -> Result<(), String> {
+    // check the ROM and the movie before opening a window for them
+    let romcartridge = load_rom(&options.machine.rom, options.machine.variant)?;
+    let replay = options.machine.replay.as_deref().map(read_movie).transpose()?;
+    let gdb = match options.gdb {
+        Some(port)  => {
+            let (gdb, address) = GdbStub::listen(port, |address| eprintln!("waiting for gdb on {}", address))
+                .map_err(|error| format!("gdb: {}", error))?;
+            eprintln!("gdb connected from {}", address);
+            Some(gdb)
+        },
+        None        => None,
     };
 
-    window.limit_update_rate(Some(Duration::from_micros(2083)));
+    let mut io = IO::open(format!("Chip-8 - {}", options.machine.rom).as_str(), options.scale).map_err(|error| error.to_string())?;
+    // the scheduler paces the frames, don't let minifb sleep on top of it
+    io.limit_update_rate(None);
+    if let Some(palette) = options.palette {
+        io.set_palette(palette);
+    }
+    if let Some(keymap) = &options.keymap {
+        io.set_keymap(keymap)?;
+    }
+
+    let mut recording = None;
+    let keypad_source: Box<dyn KeypadSource> = match (&replay, &options.record) {
+        (Some(movie), _)    => Box::new(MoviePlayer::new(movie, Some(Box::new(io.clone())))),
+        (None, Some(_))     => {
+            let recorder = MovieRecorder::new(Box::new(io.clone()));
+            recording = Some(recorder.recording());
+            Box::new(recorder)
+        },
+        (None, None)        => Box::new(io.clone()),
+    };
+    let mut chip8cpu = load_machine(&options.machine, romcartridge, Box::new(io.clone()), keypad_source, audio_sink(options.mute, options.volume))?;
+    let mut scheduler = scheduler(options.machine.speed, chip8cpu.chip8.variant);
+    scheduler.set_save_slots(&options.machine.rom);
+    scheduler.set_rewind(options.rewind);
+    if options.debug {
+        eprintln!("debugger: type help for the commands");
+        scheduler.set_debugger(Box::new(Debugger::new()));
+    }
+    if let Some(gdb) = gdb {
+        scheduler.set_debugger(Box::new(gdb));
+    }
 
-    let mut chip8cpu = cpu::Cpu::new(window, filename);
-    chip8cpu.load_instructions();
-    chip8cpu.start_execution();
+    let mut movie = None;
+    if let Some(replay) = &replay {
+        replay.prepare(&mut chip8cpu).map_err(|error| error.to_string())?;
+        scheduler.set_speed(replay.instructions_per_frame);
+        scheduler.set_deterministic(true);
+    } else if recording.is_some() {
+        let seed = options.machine.seed.unwrap_or_else(rand::random);
+        movie = Some(Movie::start(&mut chip8cpu, seed, scheduler.instructions_per_frame));
+        scheduler.set_deterministic(true);
+    }
+
+    let result = scheduler.run(&mut chip8cpu, report_hotkey);
+    // exiting below skips the destructors, write the trace out now
+    if let Some(tracer) = chip8cpu.tracer.as_mut() {
+        tracer.finish();
+    }
+    // save the movie even if the program crashed, that's what it's for
+    if let (Some(mut movie), Some(frames), Some(path)) = (movie, recording, &options.record) {
+        movie.frames = frames.borrow().clone();
+        std::fs::write(path, movie.to_text()).map_err(|error| format!("{}: {}", path, error))?;
+        eprintln!("recorded {} frames to {}", movie.frames.len(), path);
+    }
+    if let Err(error) = result {
+        eprintln!("ERROR: {}", error);
+        hold_window(&mut io, &mut chip8cpu);
+        std::process::exit(1);
+    }
+    if replay.is_some() {
+        eprintln!("replay finished");
+        hold_window(&mut io, &mut chip8cpu);
+    }
+    Ok(())
+}
+
+/// Write to the file, or to stdout without one.
+fn write_output(output: &Option<String>, bytes: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+    match output {
+        Some(path)  => std::fs::write(path, bytes).map_err(|error| format!("{}: {}", path, error)),
+        None        => std::io::stdout().write_all(bytes).map_err(|error| error.to_string()),
+    }
+}
+
+fn disassemble(options: DisasmOptions) -> Result<(), String> {
+    let romcartridge = load_rom(&options.rom, options.variant)?;
+    let variant = rom_variant(&options.rom, options.variant, &romcartridge).unwrap_or_else(|| Variant::guess(&romcartridge.rom));
+    let text = match options.range {
+        Some((start, end)) => {
+            let mut chip8cpu = Cpu::new(romcartridge);
+            chip8cpu.set_variant(variant);
+            chip8cpu.load_instructions().map_err(|error| error.to_string())?;
+            let lines = disasm::disassemble_range(&chip8cpu.chip8.memory.ram, start, end.saturating_add(1), variant);
+            lines.iter().map(|line| format!("{}\n", line)).collect()
+        },
+        None if options.linear => {
+            let lines = disasm::disassemble(&romcartridge.rom, 0x200, variant);
+            lines.iter().map(|line| format!("{}\n", line)).collect()
+        },
+        None => format!("; {} ({})\n{}", options.rom, variant, disasm::to_source(&disasm::disassemble_program(&romcartridge.rom, 0x200, variant))),
+    };
+    write_output(&options.output, text.as_bytes())
+}
+
+/// Assemble into the file given with -o, `-` for stdout, or next to the
+/// source with the .ch8 extension. Octo sources (.8o) are compiled and get
+/// the extension of the variant their opcodes need.
+fn assemble(source: &str, variant: Option<Variant>, output: &Option<String>) -> Result<(), String> {
+    let (bytes, extension) = if is_octo_source(source) {
+        let bytes = load_rom(source, variant)?.rom;
+        let extension = match Variant::guess(&bytes) {
+            Variant::Chip8      => "ch8",
+            Variant::SuperChip  => "sc8",
+            Variant::XoChip     => "xo8",
+        };
+        (bytes, extension)
+    } else if variant.is_some() {
+        return Err("--variant only applies to Octo sources".to_string());
+    } else {
+        (asm::assemble_file(std::path::Path::new(source)).map_err(|error| error.to_string())?, "ch8")
+    };
+    let output = match output.as_deref() {
+        Some("-")   => None,
+        Some(path)  => Some(path.to_string()),
+        None        => Some(std::path::Path::new(source).with_extension(extension).display().to_string()),
+    };
+    write_output(&output, &bytes)
+}
+
+fn info(rom: &str) -> Result<(), String> {
+    let romcartridge = load_rom(rom, None)?;
+    let guessed = Variant::guess(&romcartridge.rom);
+    let variant = rom_variant(rom, None, &romcartridge).unwrap_or(guessed);
+    println!("file:     {}", rom);
+    println!("size:     {} bytes", romcartridge.rom.len());
+    println!("variant:  {} (opcodes look like {})", variant, guessed);
+    println!("speed:    {} instructions per frame", variant.default_speed());
+    match std::fs::read_to_string(Quirks::rom_quirks_path(rom)) {
+        Ok(text) => {
+            let mut quirks = Quirks::for_variant(variant);
+            quirks.apply(&text)?;
+            println!("quirks:   {} (from {})", quirks, Quirks::rom_quirks_path(rom).display());
+        },
+        Err(_) => println!("quirks:   {}", Quirks::for_variant(variant)),
+    }
+    Ok(())
+}
+
+/// Run without a window or sound for a number of frames, as fast as
+/// possible, and print or check the final screen.
+fn test(options: TestOptions) -> Result<(), String> {
+    let replay = options.machine.replay.as_deref().map(read_movie).transpose()?;
+    let keypad_source: Box<dyn KeypadSource> = match &replay {
+        Some(movie) => Box::new(MoviePlayer::new(movie, None)),
+        None        => Box::new(NullKeypad),
+    };
+    let romcartridge = load_rom(&options.machine.rom, options.machine.variant)?;
+    let mut chip8cpu = load_machine(&options.machine, romcartridge, Box::new(NullDisplay), keypad_source, Box::new(NullAudio))?;
+    let mut instructions_per_frame = scheduler(options.machine.speed, chip8cpu.chip8.variant).instructions_per_frame;
+    let mut frames = options.frames.unwrap_or(60);
+    if let Some(replay) = &replay {
+        replay.prepare(&mut chip8cpu).map_err(|error| error.to_string())?;
+        instructions_per_frame = replay.instructions_per_frame;
+        frames = options.frames.unwrap_or(replay.frames.len());
+    }
+    for _ in 0..frames {
+        if chip8cpu.chip8.exited || chip8cpu.chip8.registers.pc < 0x200 {
+            break;
+        }
+        chip8cpu.run_frame(instructions_per_frame).map_err(|error| error.to_string())?;
+    }
+    if let Some(path) = &options.save_state {
+        std::fs::write(path, chip8cpu.save_state()).map_err(|error| format!("{}: {}", path, error))?;
+    }
+    let screen = chip8cpu.chip8.display.to_ascii();
+    match &options.expect {
+        Some(path) => {
+            let expected = std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+            if expected.trim_end() != screen.trim_end() {
+                print!("{}", screen);
+                return Err(format!("screen doesn't match {}", path));
+            }
+            println!("ok");
+        },
+        None => print!("{}", screen),
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(error)  => {
+            eprintln!("ERROR: {}\nrun `chip8 help` for usage", error);
+            std::process::exit(2);
+        },
+    };
+    let result = match command {
+        Command::Run(options)                       => run(options),
+        Command::Disasm(options)                    => disassemble(options),
+        Command::Asm { source, variant, output }    => assemble(&source, variant, &output),
+        Command::Info { rom }                       => info(&rom),
+        Command::Test(options)                      => test(options),
+        Command::Help                               => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        },
+    };
+    if let Err(error) = result {
+        eprintln!("ERROR: {}", error);
+        std::process::exit(1);
+    }
 }
@@ -6,23 +6,34 @@
 // memory.rs file
 //
 
-#[path = "config.rs"]
-mod config;
-#[path = "charset.rs"]
-mod charset;
+use crate::config;
+use crate::charset;
 
 pub struct Memory {
-    pub ram: [u8; config::CHIP8_MEMORY_SIZE],
+    pub ram: Vec<u8>,
 }
 
 impl Memory {
     pub fn new() -> Self {
-        let mut ram: [u8; config::CHIP8_MEMORY_SIZE] = [0; config::CHIP8_MEMORY_SIZE];
-        // loading the character set into the first 512 bytes
+        let mut ram: Vec<u8> = vec![0; config::CHIP8_MEMORY_SIZE];
+        // loading the character sets into the first 512 bytes
         ram[..config::CHIP8_CHARSET_LEN].clone_from_slice(&charset::CHIP8_CHARSET);
+        ram[config::SCHIP_BIG_CHARSET_ADDR..config::SCHIP_BIG_CHARSET_ADDR + config::SCHIP_BIG_CHARSET_LEN].clone_from_slice(&charset::SCHIP_BIG_CHARSET);
         Self {
-            ram: ram,
+            ram,
         }
     }
 
+    /// Grow or shrink the RAM, 4 KiB for CHIP-8 and SUPER-CHIP, 64 KiB for
+    /// XO-CHIP. New bytes are zero.
+    pub fn resize(&mut self, size: usize) {
+        self.ram.resize(size, 0);
+    }
+
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
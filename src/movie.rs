@@ -0,0 +1,245 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// movie.rs file
+//
+
+use crate::backend::{Hotkey, KeypadSource};
+use crate::config;
+use crate::cpu::Cpu;
+use crate::error::Chip8Error;
+use crate::keypad::{Keypad, VKeys};
+use crate::quirks::Quirks;
+use crate::rng::{Rng, RngKind};
+use crate::state;
+use crate::variant::Variant;
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::rc::Rc;
+
+/// First line of every movie file, with the format version.
+pub const MOVIE_HEADER: &str = "chip8-movie 1";
+/// Longest movie `Movie::from_text` accepts, 24 hours at 60 frames a
+/// second, so a broken file can't run the machine out of memory.
+pub const MAX_MOVIE_FRAMES: usize = 24 * 60 * 60 * 60;
+
+/// A recorded run: everything that makes it reproducible (ROM, variant,
+/// quirks, random seed, speed) and the keypad state of every frame.
+///
+/// Movies are text files, the frames are stored run-length encoded as the
+/// keypad bitmask (bit n set while key n is down) and the number of
+/// frames it was held:
+///
+/// ```text
+/// chip8-movie 1
+/// rom 9f2c6e0c1d3b5a47
+/// variant chip8
+/// quirks shift = vy, memory = x+1, jump = v0, vf_reset = true, clip = true, display_wait = true
+/// rng xorshift
+/// seed 1234
+/// speed 8
+/// frames
+/// 0000 120
+/// 0020 4
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash:               u64,
+    pub variant:                Variant,
+    pub quirks:                 Quirks,
+    pub rng:                    RngKind,
+    pub seed:                   u64,
+    pub instructions_per_frame: usize,
+    pub frames:                 Vec<u16>,
+}
+
+impl Movie {
+    /// An empty movie for the machine as it is set up now. The machine's
+    /// generator is reseeded with `seed` so the recording can be replayed.
+    pub fn start(cpu: &mut Cpu, seed: u64, instructions_per_frame: usize) -> Self {
+        let rng = cpu.chip8.rng.kind;
+        cpu.set_rng(Rng::new(rng, seed));
+        Self {
+            rom_hash:               state::rom_hash(&cpu.chip8.romcartridge.rom),
+            variant:                cpu.chip8.variant,
+            quirks:                 cpu.chip8.quirks,
+            rng,
+            seed,
+            instructions_per_frame,
+            frames:                 Vec::new(),
+        }
+    }
+
+    /// Set a freshly loaded machine up the way the movie was recorded:
+    /// variant, quirks and random seed. Fails if the ROM is a different one.
+    pub fn prepare(&self, cpu: &mut Cpu) -> Result<(), Chip8Error> {
+        if self.rom_hash != state::rom_hash(&cpu.chip8.romcartridge.rom) {
+            return Err(Chip8Error::InvalidMovie("recorded with a different ROM".to_string()));
+        }
+        cpu.set_variant(self.variant);
+        cpu.set_quirks(self.quirks);
+        cpu.load_instructions()?;
+        cpu.set_rng(Rng::new(self.rng, self.seed));
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "{}", MOVIE_HEADER);
+        let _ = writeln!(text, "rom {:016x}", self.rom_hash);
+        let _ = writeln!(text, "variant {}", self.variant);
+        let _ = writeln!(text, "quirks {}", self.quirks);
+        let _ = writeln!(text, "rng {}", self.rng);
+        let _ = writeln!(text, "seed {}", self.seed);
+        let _ = writeln!(text, "speed {}", self.instructions_per_frame);
+        let _ = writeln!(text, "frames");
+        let mut frames = self.frames.iter().peekable();
+        while let Some(keys) = frames.next() {
+            let mut count = 1;
+            while frames.next_if_eq(&keys).is_some() {
+                count += 1;
+            }
+            let _ = writeln!(text, "{:04x} {}", keys, count);
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, Chip8Error> {
+        let invalid = |line: usize, message: &str| Chip8Error::InvalidMovie(format!("line {}: {}", line + 1, message));
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(MOVIE_HEADER) {
+            return Err(Chip8Error::InvalidMovie("not a movie file, or an unsupported version".to_string()));
+        }
+
+        let mut movie = Movie {
+            rom_hash:               0,
+            variant:                Variant::default(),
+            quirks:                 Quirks::default(),
+            rng:                    RngKind::default(),
+            seed:                   0,
+            instructions_per_frame: Variant::default().default_speed(),
+            frames:                 Vec::new(),
+        };
+        let mut rom = false;
+        for (n, line) in lines.by_ref() {
+            let (key, value) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match key {
+                ""          => (),
+                "rom"       => {
+                    movie.rom_hash = u64::from_str_radix(value, 16).map_err(|_| invalid(n, "invalid ROM hash"))?;
+                    rom = true;
+                },
+                "variant"   => movie.variant = value.parse().map_err(|error: String| invalid(n, &error))?,
+                "quirks"    => movie.quirks.apply(value).map_err(|error| invalid(n, &error))?,
+                "rng"       => movie.rng = value.parse().map_err(|error: String| invalid(n, &error))?,
+                "seed"      => movie.seed = value.parse().map_err(|_| invalid(n, "invalid seed"))?,
+                "speed"     => movie.instructions_per_frame = value.parse().map_err(|_| invalid(n, "invalid speed"))?,
+                "frames"    => break,
+                _           => return Err(invalid(n, &format!("unknown setting: {}", key))),
+            }
+        }
+        if !rom {
+            return Err(Chip8Error::InvalidMovie("no ROM hash".to_string()));
+        }
+        for (n, line) in lines {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (keys, count) = line.split_once(' ').ok_or(invalid(n, "expected keys and a frame count"))?;
+            let keys = u16::from_str_radix(keys, 16).map_err(|_| invalid(n, "invalid keys"))?;
+            let count: usize = count.trim().parse().map_err(|_| invalid(n, "invalid frame count"))?;
+            if count > MAX_MOVIE_FRAMES {
+                return Err(invalid(n, &format!("frame count above {}", MAX_MOVIE_FRAMES)));
+            }
+            if movie.frames.len() + count > MAX_MOVIE_FRAMES {
+                return Err(invalid(n, &format!("movie longer than {} frames", MAX_MOVIE_FRAMES)));
+            }
+            movie.frames.extend(std::iter::repeat_n(keys, count));
+        }
+        Ok(movie)
+    }
+}
+
+/// Bit n set while the key with hex value n is down.
+fn keypad_bits(keypad: &Keypad) -> u16 {
+    (0..config::CHIP8_KEYBOARD_SIZE as u8).fold(0, |bits, n| bits | (matches!(keypad.get_key_status_from_num(n), Ok(true)) as u16) << n)
+}
+
+/// Keypad backend that passes another one through and writes down the
+/// keys of every frame. Keep the handle from `recording` to get at the
+/// frames after the recorder was handed to the machine.
+pub struct MovieRecorder {
+    inner:  Box<dyn KeypadSource>,
+    frames: Rc<RefCell<Vec<u16>>>,
+}
+
+impl MovieRecorder {
+    pub fn new(inner: Box<dyn KeypadSource>) -> Self {
+        Self {
+            inner,
+            frames: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn recording(&self) -> Rc<RefCell<Vec<u16>>> {
+        Rc::clone(&self.frames)
+    }
+}
+
+impl KeypadSource for MovieRecorder {
+    fn poll_keys(&mut self, keypad: &mut Keypad) {
+        self.inner.poll_keys(keypad);
+        self.frames.borrow_mut().push(keypad_bits(keypad));
+    }
+
+    fn quit_requested(&mut self) -> bool {
+        self.inner.quit_requested()
+    }
+
+    fn poll_hotkeys(&mut self) -> Vec<Hotkey> {
+        self.inner.poll_hotkeys()
+    }
+}
+
+/// Keypad backend that presses the keys of a movie, frame by frame, and
+/// asks to quit once the movie is over. The optional inner backend still
+/// provides quitting and hotkeys, its keys are ignored.
+pub struct MoviePlayer {
+    frames: Vec<u16>,
+    pos:    usize,
+    inner:  Option<Box<dyn KeypadSource>>,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: &Movie, inner: Option<Box<dyn KeypadSource>>) -> Self {
+        Self {
+            frames: movie.frames.clone(),
+            pos:    0,
+            inner,
+        }
+    }
+}
+
+impl KeypadSource for MoviePlayer {
+    fn poll_keys(&mut self, keypad: &mut Keypad) {
+        let keys = self.frames.get(self.pos).copied().unwrap_or(0);
+        self.pos += 1;
+        for n in 0..config::CHIP8_KEYBOARD_SIZE as u8 {
+            if let Some(key) = VKeys::from_num(n) {
+                keypad.set_key(key, keys & (1 << n) != 0);
+            }
+        }
+    }
+
+    fn quit_requested(&mut self) -> bool {
+        self.pos >= self.frames.len() || self.inner.as_mut().is_some_and(|inner| inner.quit_requested())
+    }
+
+    fn poll_hotkeys(&mut self) -> Vec<Hotkey> {
+        self.inner.as_mut().map_or(Vec::new(), |inner| inner.poll_hotkeys())
+    }
+}
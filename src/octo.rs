@@ -0,0 +1,941 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// octo.rs file
+//
+
+use crate::asm::AsmError;
+use crate::variant::Variant;
+
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    text:   String,
+    line:   usize,
+}
+
+/// What a forward reference to a label patches once the label is known.
+#[derive(Debug, Clone, Copy)]
+enum Patch {
+    /// The low 12 bits of the instruction word.
+    Nnn,
+    /// A single byte: `((addr >> shift) & mask) | or`.
+    Byte { shift: u8, mask: u16, or: u8 },
+}
+
+struct Fixup {
+    at:     u16,
+    patch:  Patch,
+    label:  String,
+    line:   usize,
+}
+
+/// Open `begin` and `loop` blocks, waiting for their `else`/`end`/`again`.
+enum Block {
+    /// Address of the jump over the block (or over the else branch).
+    Begin { jump: u16, has_else: bool },
+    /// Start of the loop and the jumps out of it made by `while`.
+    Loop { start: u16, exits: Vec<u16> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Key,
+    NotKey,
+}
+
+impl Compare {
+    fn negate(self) -> Self {
+        match self {
+            Compare::Eq     => Compare::Ne,
+            Compare::Ne     => Compare::Eq,
+            Compare::Lt     => Compare::Ge,
+            Compare::Ge     => Compare::Lt,
+            Compare::Gt     => Compare::Le,
+            Compare::Le     => Compare::Gt,
+            Compare::Key    => Compare::NotKey,
+            Compare::NotKey => Compare::Key,
+        }
+    }
+}
+
+/// Either side of a comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(u8),
+    Value(u8),
+}
+
+struct Macro {
+    params: Vec<String>,
+    body:   Vec<Token>,
+}
+
+/// Compile Octo source into a ROM image loaded at 0x200, rejecting the
+/// instructions `variant` doesn't have.
+///
+/// Supported are the statements and directives of Octo 1.2: registers
+/// and `i` assignments and arithmetic, `if ... then`, `if ... begin ...
+/// else ... end`, `loop ... while ... again`, `:` labels, `:const`,
+/// `:alias`, `:calc` and `:byte` with `{ }` expressions, `:macro`,
+/// `:unpack`, `:next`, `:org`, plus the SUPER-CHIP and XO-CHIP
+/// statements. As in Octo, expressions are evaluated right to left without
+/// operator precedence, `{ 2 * 3 + 1 }` is 8. `:breakpoint` and
+/// `:monitor` are accepted and ignored.
+pub fn compile(source: &str, variant: Variant) -> Result<Vec<u8>, AsmError> {
+    let mut compiler = Compiler::new(tokenize(source), variant);
+    compiler.compile()?;
+    Ok(compiler.rom)
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        for word in code.split_whitespace() {
+            tokens.push_back(Token { text: word.to_string(), line: index + 1 });
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits)    => (true, digits),
+        None            => (false, text),
+    };
+    let lower = digits.to_ascii_lowercase();
+    let value = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()? as f64
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        lower.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|n| n as u8),
+        _ => None,
+    }
+}
+
+struct Compiler {
+    tokens:     VecDeque<Token>,
+    variant:    Variant,
+    rom:        Vec<u8>,
+    here:       u16,
+    line:       usize,
+    labels:     HashMap<String, u16>,
+    constants:  HashMap<String, f64>,
+    aliases:    HashMap<String, u8>,
+    macros:     HashMap<String, Macro>,
+    fixups:     Vec<Fixup>,
+    blocks:     Vec<Block>,
+    /// Label to define at the address of the next instruction's second byte.
+    next_label: Option<String>,
+}
+
+impl Compiler {
+    fn new(tokens: VecDeque<Token>, variant: Variant) -> Self {
+        Self {
+            tokens,
+            variant,
+            rom:        Vec::new(),
+            here:       0x200,
+            line:       1,
+            labels:     HashMap::new(),
+            constants:  HashMap::new(),
+            aliases:    HashMap::new(),
+            macros:     HashMap::new(),
+            fixups:     Vec::new(),
+            blocks:     Vec::new(),
+            next_label: None,
+        }
+    }
+
+    fn error(&self, message: String) -> AsmError {
+        AsmError { file: None, line: self.line, message }
+    }
+
+    fn compile(&mut self) -> Result<(), AsmError> {
+        // execution starts at main, jump there unless it comes first
+        let main_first = self.tokens.len() >= 2 && self.tokens[0].text == ":" && self.tokens[1].text == "main";
+        if !main_first {
+            self.emit_jump(0x1000, "main".to_string())?;
+        }
+        while let Some(token) = self.tokens.pop_front() {
+            self.line = token.line;
+            self.statement(&token.text)?;
+        }
+        if let Some(block) = self.blocks.last() {
+            return Err(self.error(match block {
+                Block::Begin { .. } => "begin without end".to_string(),
+                Block::Loop { .. }  => "loop without again".to_string(),
+            }));
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let addr = *self.labels.get(&fixup.label).ok_or_else(|| self.error(format!("undefined label: {}", fixup.label)))?;
+            self.apply_patch(fixup.at, fixup.patch, addr)?;
+        }
+        Ok(())
+    }
+
+    fn next_token(&mut self) -> Result<Token, AsmError> {
+        let token = self.tokens.pop_front().ok_or_else(|| self.error("unexpected end of file".to_string()))?;
+        self.line = token.line;
+        Ok(token)
+    }
+
+    fn next_text(&mut self) -> Result<String, AsmError> {
+        Ok(self.next_token()?.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AsmError> {
+        let text = self.next_text()?;
+        if text != expected {
+            return Err(self.error(format!("expected {}, found {}", expected, text)));
+        }
+        Ok(())
+    }
+
+    fn require(&self, needed: bool, what: &str, variant: Variant) -> Result<(), AsmError> {
+        if !needed {
+            return Err(self.error(format!("{} needs {}, compiling for {}", what, variant, self.variant)));
+        }
+        Ok(())
+    }
+
+    fn register(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn expect_register(&mut self) -> Result<u8, AsmError> {
+        let text = self.next_text()?;
+        self.register(&text).ok_or_else(|| self.error(format!("expected a register, found {}", text)))
+    }
+
+    fn is_name(text: &str) -> bool {
+        text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && parse_register(text).is_none()
+    }
+
+    /// A number, a constant, or a `{ }` expression.
+    fn value(&mut self, text: &str) -> Result<f64, AsmError> {
+        if text == "{" {
+            return self.expression();
+        }
+        if let Some(value) = parse_number(text) {
+            return Ok(value);
+        }
+        if let Some(value) = self.constants.get(text) {
+            return Ok(*value);
+        }
+        Err(self.error(format!("expected a number or constant, found {}", text)))
+    }
+
+    /// A value that fits into `bits` bits, negative values count from the top.
+    fn sized_value(&mut self, text: &str, bits: u32) -> Result<u16, AsmError> {
+        let value = self.value(text)?.floor() as i64;
+        let max = (1i64 << bits) - 1;
+        if value > max || value < -(max + 1) / 2 {
+            return Err(self.error(format!("{} doesn't fit into {} bits", text, bits)));
+        }
+        Ok((value & max) as u16)
+    }
+
+    fn byte_value(&mut self) -> Result<u8, AsmError> {
+        let text = self.next_text()?;
+        Ok(self.sized_value(&text, 8)? as u8)
+    }
+
+    fn nibble_value(&mut self) -> Result<u8, AsmError> {
+        let text = self.next_text()?;
+        Ok(self.sized_value(&text, 4)? as u8)
+    }
+
+    // ---- output ----
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), AsmError> {
+        let offset = self.here.checked_sub(0x200).ok_or_else(|| self.error(format!("{:#05X} is below 0x200", self.here)))? as usize;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here = self.here.checked_add(1).ok_or_else(|| self.error("program doesn't fit into 64 KiB".to_string()))?;
+        Ok(())
+    }
+
+    fn emit(&mut self, word: u16) -> Result<(), AsmError> {
+        if let Some(label) = self.next_label.take() {
+            self.define_label(label, self.here.wrapping_add(1))?;
+        }
+        self.emit_byte((word >> 8) as u8)?;
+        self.emit_byte(word as u8)
+    }
+
+    /// Emit `opcode | addr`, patched later if the label isn't known yet.
+    fn emit_jump(&mut self, opcode: u16, label: String) -> Result<(), AsmError> {
+        match self.labels.get(&label).copied().or_else(|| self.constants.get(&label).map(|value| *value as u16)) {
+            Some(addr) if addr > 0xFFF => Err(self.error(format!("{} is above 0xFFF, out of reach of this instruction", label))),
+            Some(addr) => self.emit(opcode | addr),
+            None => {
+                if let Some(addr) = parse_number(&label) {
+                    if !(0.0..=0xFFF as f64).contains(&addr) {
+                        return Err(self.error(format!("{} is outside 0x000-0xFFF, out of reach of this instruction", label)));
+                    }
+                    return self.emit(opcode | addr as u16);
+                }
+                if !Self::is_name(&label) {
+                    return Err(self.error(format!("expected a label, found {}", label)));
+                }
+                self.fixups.push(Fixup { at: self.here, patch: Patch::Nnn, label, line: self.line });
+                self.emit(opcode)
+            },
+        }
+    }
+
+    fn apply_patch(&mut self, at: u16, patch: Patch, addr: u16) -> Result<(), AsmError> {
+        let offset = (at - 0x200) as usize;
+        match patch {
+            Patch::Nnn => {
+                if addr > 0xFFF {
+                    return Err(self.error(format!("{:#X} is above 0xFFF, out of reach of this instruction", addr)));
+                }
+                self.rom[offset] = (self.rom[offset] & 0xF0) | (addr >> 8) as u8;
+                self.rom[offset + 1] = addr as u8;
+            },
+            Patch::Byte { shift, mask, or } => self.rom[offset] = ((addr >> shift) & mask) as u8 | or,
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: String, addr: u16) -> Result<(), AsmError> {
+        if !Self::is_name(&name) {
+            return Err(self.error(format!("invalid label name: {}", name)));
+        }
+        if self.labels.insert(name.clone(), addr).is_some() {
+            return Err(self.error(format!("label {} defined twice", name)));
+        }
+        Ok(())
+    }
+
+    /// Emit a byte holding part of a label's address.
+    fn emit_address_byte(&mut self, label: &str, shift: u8, mask: u16, or: u8) -> Result<(), AsmError> {
+        let addr = self.labels.get(label).copied().or_else(|| self.constants.get(label).map(|value| *value as u16));
+        match addr {
+            Some(addr) => self.emit_byte(((addr >> shift) & mask) as u8 | or),
+            None => {
+                self.fixups.push(Fixup { at: self.here, patch: Patch::Byte { shift, mask, or }, label: label.to_string(), line: self.line });
+                self.emit_byte(0)
+            },
+        }
+    }
+
+    // ---- statements ----
+
+    fn statement(&mut self, text: &str) -> Result<(), AsmError> {
+        if let Some(register) = self.register(text) {
+            return self.register_statement(register);
+        }
+        if let Some(definition) = self.macros.get(text) {
+            let params = definition.params.clone();
+            let body = definition.body.clone();
+            let mut args = HashMap::new();
+            for param in params {
+                let arg = self.next_text()?;
+                args.insert(param, arg);
+            }
+            let line = self.line;
+            for token in body.into_iter().rev() {
+                let text = args.get(&token.text).cloned().unwrap_or(token.text);
+                self.tokens.push_front(Token { text, line });
+            }
+            return Ok(());
+        }
+        if parse_number(text).is_some() || self.constants.contains_key(text) || text == "{" {
+            // bare numbers are data, sprites mostly
+            let value = self.sized_value(text, 8)? as u8;
+            return self.emit_byte(value);
+        }
+        let schip = self.variant.has_superchip();
+        let xochip = self.variant.has_xochip();
+        match text {
+            ":" => {
+                let name = self.next_text()?;
+                let here = self.here;
+                self.define_label(name, here)
+            },
+            ":const" => {
+                let name = self.next_text()?;
+                let value_text = self.next_text()?;
+                let value = self.value(&value_text)?;
+                self.define_constant(name, value)
+            },
+            ":calc" => {
+                let name = self.next_text()?;
+                self.expect("{")?;
+                let value = self.expression()?;
+                self.define_constant(name, value)
+            },
+            ":alias" => {
+                let name = self.next_text()?;
+                let register = self.expect_register()?;
+                if !Self::is_name(&name) {
+                    return Err(self.error(format!("invalid alias name: {}", name)));
+                }
+                self.aliases.insert(name, register);
+                Ok(())
+            },
+            ":byte" => {
+                let value = self.byte_value()?;
+                self.emit_byte(value)
+            },
+            ":pointer" => {
+                let name = self.next_text()?;
+                self.emit_address_byte(&name, 8, 0xFF, 0)?;
+                self.emit_address_byte(&name, 0, 0xFF, 0)
+            },
+            ":org" => {
+                let value_text = self.next_text()?;
+                let addr = self.sized_value(&value_text, 16)?;
+                if addr < 0x200 {
+                    return Err(self.error(format!(":org {:#X} is below 0x200", addr)));
+                }
+                self.here = addr;
+                Ok(())
+            },
+            ":next" => {
+                self.next_label = Some(self.next_text()?);
+                Ok(())
+            },
+            ":unpack" => {
+                let high = self.next_text()?;
+                let label = self.next_text()?;
+                // v0 := high nibble | address bits 8-11 (or bits 8-15 for long), v1 := address bits 0-7
+                self.emit_byte(0x60)?;
+                if high == "long" {
+                    self.emit_address_byte(&label, 8, 0xFF, 0)?;
+                } else {
+                    let nibble = self.sized_value(&high, 4)? as u8;
+                    self.emit_address_byte(&label, 8, 0x0F, nibble << 4)?;
+                }
+                self.emit_byte(0x61)?;
+                self.emit_address_byte(&label, 0, 0xFF, 0)
+            },
+            ":macro" => self.define_macro(),
+            ":breakpoint" => self.next_text().map(|_| ()),
+            ":monitor" => {
+                self.next_text()?;
+                self.next_text().map(|_| ())
+            },
+            "clear"         => self.emit(0x00E0),
+            "return" | ";"  => self.emit(0x00EE),
+            "jump"          => {
+                let label = self.next_text()?;
+                self.emit_jump(0x1000, label)
+            },
+            "jump0"         => {
+                let label = self.next_text()?;
+                self.emit_jump(0xB000, label)
+            },
+            "native"        => {
+                let label = self.next_text()?;
+                self.emit_jump(0x0000, label)
+            },
+            "bcd"           => self.fx(0x33),
+            "save" | "load" => {
+                let x = self.expect_register()?;
+                if self.peek() == Some("-") {
+                    self.require(xochip, "save/load of a register range", Variant::XoChip)?;
+                    self.next_text()?;
+                    let y = self.expect_register()?;
+                    let low = if text == "save" { 0x2 } else { 0x3 };
+                    return self.emit(0x5000 | (x as u16) << 8 | (y as u16) << 4 | low);
+                }
+                self.emit(0xF000 | (x as u16) << 8 | if text == "save" { 0x55 } else { 0x65 })
+            },
+            "saveflags" | "loadflags" => {
+                self.require(schip, text, Variant::SuperChip)?;
+                self.fx(if text == "saveflags" { 0x75 } else { 0x85 })
+            },
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = self.nibble_value()?;
+                if n == 0 {
+                    self.require(schip, "sprite with 0 rows", Variant::SuperChip)?;
+                }
+                self.emit(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n as u16)
+            },
+            "hires" | "lores" | "exit" | "scroll-left" | "scroll-right" => {
+                self.require(schip, text, Variant::SuperChip)?;
+                self.emit(match text {
+                    "scroll-right"  => 0x00FB,
+                    "scroll-left"   => 0x00FC,
+                    "exit"          => 0x00FD,
+                    "lores"         => 0x00FE,
+                    _               => 0x00FF,
+                })
+            },
+            "scroll-down" => {
+                self.require(schip, text, Variant::SuperChip)?;
+                let n = self.nibble_value()?;
+                self.emit(0x00C0 | n as u16)
+            },
+            "scroll-up" => {
+                self.require(xochip, text, Variant::XoChip)?;
+                let n = self.nibble_value()?;
+                self.emit(0x00D0 | n as u16)
+            },
+            "plane" => {
+                self.require(xochip, text, Variant::XoChip)?;
+                let n = self.nibble_value()?;
+                self.emit(0xF001 | (n as u16) << 8)
+            },
+            "audio" => {
+                self.require(xochip, text, Variant::XoChip)?;
+                self.emit(0xF002)
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                if text == "pitch" {
+                    self.require(xochip, text, Variant::XoChip)?;
+                }
+                self.fx(match text {
+                    "delay"     => 0x15,
+                    "buzzer"    => 0x18,
+                    _           => 0x3A,
+                })
+            },
+            "i" => self.i_statement(),
+            "if" => self.if_statement(),
+            "else" => match self.blocks.pop() {
+                Some(Block::Begin { jump, has_else: false }) => {
+                    let else_jump = self.here;
+                    self.emit(0x1000)?;
+                    let here = self.here;
+                    self.apply_patch(jump, Patch::Nnn, here)?;
+                    self.blocks.push(Block::Begin { jump: else_jump, has_else: true });
+                    Ok(())
+                },
+                _ => Err(self.error("else without begin".to_string())),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::Begin { jump, .. }) => {
+                    let here = self.here;
+                    self.apply_patch(jump, Patch::Nnn, here)
+                },
+                _ => Err(self.error("end without begin".to_string())),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop { start: self.here, exits: Vec::new() });
+                Ok(())
+            },
+            "while" => {
+                // skip the jump out of the loop while the condition holds
+                self.condition(true)?;
+                let exit = self.here;
+                self.emit(0x1000)?;
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. })) {
+                    Some(Block::Loop { exits, .. }) => {
+                        exits.push(exit);
+                        Ok(())
+                    },
+                    _ => Err(self.error("while outside of a loop".to_string())),
+                }
+            },
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits }) => {
+                    self.emit(0x1000 | start)?;
+                    let here = self.here;
+                    for exit in exits {
+                        self.apply_patch(exit, Patch::Nnn, here)?;
+                    }
+                    Ok(())
+                },
+                _ => Err(self.error("again without loop".to_string())),
+            },
+            _ if Self::is_name(text) => self.emit_jump(0x2000, text.to_string()),
+            _ => Err(self.error(format!("unknown statement: {}", text))),
+        }
+    }
+
+    fn fx(&mut self, low: u16) -> Result<(), AsmError> {
+        let x = self.expect_register()?;
+        self.emit(0xF000 | (x as u16) << 8 | low)
+    }
+
+    fn define_constant(&mut self, name: String, value: f64) -> Result<(), AsmError> {
+        if !Self::is_name(&name) {
+            return Err(self.error(format!("invalid constant name: {}", name)));
+        }
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.next_text()?;
+        let mut params = Vec::new();
+        loop {
+            let text = self.next_text()?;
+            if text == "{" {
+                break;
+            }
+            params.push(text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next_token()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                _ => (),
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), AsmError> {
+        let op = self.next_text()?;
+        let rhs = self.next_text()?;
+        let vx = (x as u16) << 8;
+        if let Some(y) = self.register(&rhs) {
+            let low = match op.as_str() {
+                ":="    => 0x0,
+                "|="    => 0x1,
+                "&="    => 0x2,
+                "^="    => 0x3,
+                "+="    => 0x4,
+                "-="    => 0x5,
+                ">>="   => 0x6,
+                "=-"    => 0x7,
+                "<<="   => 0xE,
+                _       => return Err(self.error(format!("unknown register operation: {}", op))),
+            };
+            return self.emit(0x8000 | vx | (y as u16) << 4 | low);
+        }
+        match (op.as_str(), rhs.as_str()) {
+            (":=", "random") => {
+                let mask = self.byte_value()?;
+                self.emit(0xC000 | vx | mask as u16)
+            },
+            (":=", "delay")  => self.emit(0xF007 | vx),
+            (":=", "key")    => self.emit(0xF00A | vx),
+            (":=", _) => {
+                let value = self.sized_value(&rhs, 8)?;
+                self.emit(0x6000 | vx | value)
+            },
+            ("+=", _) => {
+                let value = self.sized_value(&rhs, 8)?;
+                self.emit(0x7000 | vx | value)
+            },
+            ("-=", _) => {
+                let value = self.sized_value(&rhs, 8)?;
+                self.emit(0x7000 | vx | (value.wrapping_neg() & 0xFF))
+            },
+            _ => Err(self.error(format!("invalid operation: v{:x} {} {}", x, op, rhs))),
+        }
+    }
+
+    fn i_statement(&mut self) -> Result<(), AsmError> {
+        let op = self.next_text()?;
+        let rhs = self.next_text()?;
+        match (op.as_str(), rhs.as_str()) {
+            ("+=", _) => {
+                let x = self.register(&rhs).ok_or_else(|| self.error(format!("expected a register, found {}", rhs)))?;
+                self.emit(0xF01E | (x as u16) << 8)
+            },
+            (":=", "hex") => self.fx(0x29),
+            (":=", "bighex") => {
+                self.require(self.variant.has_superchip(), "i := bighex", Variant::SuperChip)?;
+                self.fx(0x30)
+            },
+            (":=", "long") => {
+                self.require(self.variant.has_xochip(), "i := long", Variant::XoChip)?;
+                let label = self.next_text()?;
+                self.emit(0xF000)?;
+                match parse_number(&label).or_else(|| self.constants.get(&label).copied()) {
+                    Some(addr) => self.emit(addr as u16),
+                    None => {
+                        self.emit_address_byte(&label, 8, 0xFF, 0)?;
+                        self.emit_address_byte(&label, 0, 0xFF, 0)
+                    },
+                }
+            },
+            (":=", "{") => {
+                let addr = self.expression()?;
+                if !(0.0..=4095.0).contains(&addr) {
+                    return Err(self.error(format!("{} is out of reach of i :=, use i := long", addr)));
+                }
+                self.emit(0xA000 | addr as u16)
+            },
+            (":=", _) => self.emit_jump(0xA000, rhs),
+            _ => Err(self.error(format!("invalid operation: i {} {}", op, rhs))),
+        }
+    }
+
+    /// `if <condition> then <statement>` or `if <condition> begin ... end`.
+    fn if_statement(&mut self) -> Result<(), AsmError> {
+        // peek past the condition to see which form it is
+        let form = self.tokens.iter().find(|token| token.text == "then" || token.text == "begin").map(|token| token.text.clone());
+        match form.as_deref() {
+            Some("then") => {
+                self.condition(false)?;
+                self.expect("then")
+            },
+            Some(_) => {
+                self.condition(true)?;
+                self.expect("begin")?;
+                let jump = self.here;
+                self.emit(0x1000)?;
+                self.blocks.push(Block::Begin { jump, has_else: false });
+                Ok(())
+            },
+            None => Err(self.error("if without then or begin".to_string())),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, AsmError> {
+        let text = self.next_text()?;
+        if let Some(register) = self.register(&text) {
+            return Ok(Operand::Register(register));
+        }
+        Ok(Operand::Value(self.sized_value(&text, 8)? as u8))
+    }
+
+    /// Compile a condition into instructions that skip the next one when it
+    /// is false, or when it is true if `skip_when_true`.
+    fn condition(&mut self, skip_when_true: bool) -> Result<(), AsmError> {
+        let left = self.expect_register()?;
+        let op_text = self.next_text()?;
+        let mut compare = match op_text.as_str() {
+            "=="    => Compare::Eq,
+            "!="    => Compare::Ne,
+            "<"     => Compare::Lt,
+            ">"     => Compare::Gt,
+            "<="    => Compare::Le,
+            ">="    => Compare::Ge,
+            "key"   => Compare::Key,
+            "-key"  => Compare::NotKey,
+            _       => return Err(self.error(format!("unknown comparison: {}", op_text))),
+        };
+        if skip_when_true {
+            compare = compare.negate();
+        }
+        let vx = (left as u16) << 8;
+        match compare {
+            Compare::Key        => return self.emit(0xE0A1 | vx),
+            Compare::NotKey     => return self.emit(0xE09E | vx),
+            _ => (),
+        }
+        let right = self.operand()?;
+        match (compare, right) {
+            (Compare::Eq, Operand::Value(n))    => self.emit(0x4000 | vx | n as u16),
+            (Compare::Ne, Operand::Value(n))    => self.emit(0x3000 | vx | n as u16),
+            (Compare::Eq, Operand::Register(y)) => self.emit(0x9000 | vx | (y as u16) << 4),
+            (Compare::Ne, Operand::Register(y)) => self.emit(0x5000 | vx | (y as u16) << 4),
+            _ => {
+                // vf := a - b sets vf to 1 when a >= b, 0 when a < b
+                let (a, b) = match compare {
+                    Compare::Lt | Compare::Ge   => (Operand::Register(left), right),
+                    _                           => (right, Operand::Register(left)),
+                };
+                match (a, b) {
+                    (Operand::Register(a), Operand::Register(b)) => {
+                        self.emit(0x8F00 | (a as u16) << 4)?;
+                        self.emit(0x8F05 | (b as u16) << 4)?;
+                    },
+                    (Operand::Register(a), Operand::Value(b)) => {
+                        self.emit(0x6F00 | b as u16)?;
+                        self.emit(0x8F07 | (a as u16) << 4)?;
+                    },
+                    (Operand::Value(a), Operand::Register(b)) => {
+                        self.emit(0x6F00 | a as u16)?;
+                        self.emit(0x8F05 | (b as u16) << 4)?;
+                    },
+                    (Operand::Value(_), Operand::Value(_)) => unreachable!("the left side is always a register"),
+                }
+                match compare {
+                    // true when a < b, that is vf == 0: skip when vf != 0
+                    Compare::Lt | Compare::Gt   => self.emit(0x4F00),
+                    // true when a >= b, that is vf == 1: skip when vf == 0
+                    _                           => self.emit(0x3F00),
+                }
+            },
+        }
+    }
+
+    // ---- expressions ----
+
+    /// Evaluate the tokens up to the closing `}`. Octo evaluates right to
+    /// left without precedence, so `a - b - c` is `a - (b - c)`.
+    fn expression(&mut self) -> Result<f64, AsmError> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_text()?;
+            if token == "}" {
+                break;
+            }
+            tokens.extend(split_expression(&token));
+        }
+        let mut pos = 0;
+        let value = self.expression_from(&tokens, &mut pos)?;
+        if pos != tokens.len() {
+            return Err(self.error(format!("unexpected {} in expression", tokens[pos])));
+        }
+        Ok(value)
+    }
+
+    fn expression_from(&self, tokens: &[String], pos: &mut usize) -> Result<f64, AsmError> {
+        let left = self.term(tokens, pos)?;
+        let Some(op) = tokens.get(*pos) else {
+            return Ok(left);
+        };
+        let op = op.clone();
+        let apply: fn(f64, f64) -> f64 = match op.as_str() {
+            "+"     => |a, b| a + b,
+            "-"     => |a, b| a - b,
+            "*"     => |a, b| a * b,
+            "/"     => |a, b| a / b,
+            "%"     => |a, b| a % b,
+            "&"     => |a, b| ((a as i64) & (b as i64)) as f64,
+            "|"     => |a, b| ((a as i64) | (b as i64)) as f64,
+            "^"     => |a, b| ((a as i64) ^ (b as i64)) as f64,
+            "<<"    => |a, b| ((a as i64) << (b as i64)) as f64,
+            ">>"    => |a, b| ((a as i64) >> (b as i64)) as f64,
+            "<"     => |a, b| (a < b) as i64 as f64,
+            ">"     => |a, b| (a > b) as i64 as f64,
+            "<="    => |a, b| (a <= b) as i64 as f64,
+            ">="    => |a, b| (a >= b) as i64 as f64,
+            "=="    => |a, b| (a == b) as i64 as f64,
+            "!="    => |a, b| (a != b) as i64 as f64,
+            "min"   => f64::min,
+            "max"   => f64::max,
+            "pow"   => f64::powf,
+            ")"     => return Ok(left),
+            _       => return Err(self.error(format!("unknown operator in expression: {}", op))),
+        };
+        *pos += 1;
+        let right = self.expression_from(tokens, pos)?;
+        Ok(apply(left, right))
+    }
+
+    fn term(&self, tokens: &[String], pos: &mut usize) -> Result<f64, AsmError> {
+        let token = tokens.get(*pos).ok_or_else(|| self.error("expression ends early".to_string()))?.clone();
+        *pos += 1;
+        let unary: Option<fn(f64) -> f64> = match token.as_str() {
+            "-"     => Some(|a| -a),
+            "~"     => Some(|a| !(a as i64) as f64),
+            "!"     => Some(|a| (a == 0.0) as i64 as f64),
+            "sin"   => Some(f64::sin),
+            "cos"   => Some(f64::cos),
+            "tan"   => Some(f64::tan),
+            "exp"   => Some(f64::exp),
+            "log"   => Some(f64::ln),
+            "abs"   => Some(f64::abs),
+            "sqrt"  => Some(f64::sqrt),
+            "sign"  => Some(f64::signum),
+            "ceil"  => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _       => None,
+        };
+        if let Some(unary) = unary {
+            return Ok(unary(self.term(tokens, pos)?));
+        }
+        match token.as_str() {
+            "(" => {
+                let value = self.expression_from(tokens, pos)?;
+                if tokens.get(*pos).map(|token| token.as_str()) != Some(")") {
+                    return Err(self.error("missing ) in expression".to_string()));
+                }
+                *pos += 1;
+                Ok(value)
+            },
+            "@" => {
+                let addr = self.term(tokens, pos)? as i64;
+                let byte = usize::try_from(addr - 0x200).ok().and_then(|offset| self.rom.get(offset));
+                byte.map(|byte| *byte as f64).ok_or_else(|| self.error(format!("@ reads outside of the program: {:#X}", addr)))
+            },
+            "HERE"  => Ok(self.here as f64),
+            "PI"    => Ok(std::f64::consts::PI),
+            "E"     => Ok(std::f64::consts::E),
+            _ => {
+                if let Some(value) = parse_number(&token) {
+                    return Ok(value);
+                }
+                if let Some(value) = self.constants.get(&token) {
+                    return Ok(*value);
+                }
+                if let Some(addr) = self.labels.get(&token) {
+                    return Ok(*addr as f64);
+                }
+                if let Some(register) = self.register(&token) {
+                    return Ok(register as f64);
+                }
+                Err(self.error(format!("unknown name in expression: {}", token)))
+            },
+        }
+    }
+}
+
+/// Split an expression token further, so `(HERE+2)` works as well as
+/// `( HERE + 2 )`.
+fn split_expression(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+        let op_len = if ["<<", ">>", "<=", ">=", "==", "!="].contains(&two.as_str()) {
+            2
+        } else if "+-*/%&|^<>()~!@".contains(chars[i]) {
+            1
+        } else {
+            0
+        };
+        if op_len == 0 {
+            current.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        // a minus right after a number's 0x prefix can't happen, but one
+        // starting an expression or following an operator is unary
+        if !current.is_empty() {
+            tokens.push(std::mem::take(&mut current));
+        }
+        tokens.push(chars[i..i + op_len].iter().collect());
+        i += op_len;
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
@@ -0,0 +1,210 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// quirks.rs file
+//
+
+use crate::variant::Variant;
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// What Fx55 and Fx65 do to I after storing or loading the registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryIncrement {
+    /// I is left alone.
+    None,
+    /// I ends up pointing at the last register's byte, I += x.
+    X,
+    /// I ends up pointing past the last register's byte, I += x + 1.
+    XPlusOne,
+}
+
+/// The behaviour of the opcodes the different interpreters disagree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8xy6/8xyE shift Vy into Vx instead of shifting Vx in place.
+    pub shift_uses_vy:      bool,
+    /// How Fx55/Fx65 change I.
+    pub memory_increment:   MemoryIncrement,
+    /// Bnnn jumps to xnn + Vx instead of nnn + V0.
+    pub jump_uses_vx:       bool,
+    /// 8xy1/8xy2/8xy3 reset VF to 0.
+    pub logic_resets_vf:    bool,
+    /// Sprites are cut off at the screen edges instead of wrapping around.
+    pub clip_sprites:       bool,
+    /// Dxyn waits for the next frame, so at most one sprite is drawn per frame.
+    pub display_wait:       bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the COSMAC VIP.
+    pub fn cosmac_vip() -> Self {
+        Self {
+            shift_uses_vy:      true,
+            memory_increment:   MemoryIncrement::XPlusOne,
+            jump_uses_vx:       false,
+            logic_resets_vf:    true,
+            clip_sprites:       true,
+            display_wait:       true,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub fn chip48() -> Self {
+        Self {
+            shift_uses_vy:      false,
+            memory_increment:   MemoryIncrement::X,
+            jump_uses_vx:       true,
+            logic_resets_vf:    false,
+            clip_sprites:       true,
+            display_wait:       false,
+        }
+    }
+
+    pub fn superchip_1_0() -> Self {
+        Self {
+            shift_uses_vy:      false,
+            memory_increment:   MemoryIncrement::X,
+            jump_uses_vx:       true,
+            logic_resets_vf:    false,
+            clip_sprites:       true,
+            display_wait:       false,
+        }
+    }
+
+    pub fn superchip_1_1() -> Self {
+        Self {
+            shift_uses_vy:      false,
+            memory_increment:   MemoryIncrement::None,
+            jump_uses_vx:       true,
+            logic_resets_vf:    false,
+            clip_sprites:       true,
+            display_wait:       false,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo.
+    pub fn xo_chip() -> Self {
+        Self {
+            shift_uses_vy:      true,
+            memory_increment:   MemoryIncrement::XPlusOne,
+            jump_uses_vx:       false,
+            logic_resets_vf:    false,
+            clip_sprites:       false,
+            display_wait:       false,
+        }
+    }
+
+    /// The preset the programs for a variant usually expect.
+    pub fn for_variant(variant: Variant) -> Self {
+        match variant {
+            Variant::Chip8      => Self::cosmac_vip(),
+            Variant::SuperChip  => Self::superchip_1_1(),
+            Variant::XoChip     => Self::xo_chip(),
+        }
+    }
+
+    /// Path of the per-ROM quirks file, `<rom>.quirks`.
+    pub fn rom_quirks_path(romfilename: &str) -> PathBuf {
+        PathBuf::from(format!("{}.quirks", romfilename))
+    }
+
+    /// Apply `name = value` overrides on top of these quirks, one per line
+    /// (or separated by commas). A line with just a preset name replaces
+    /// everything set so far, `#` starts a comment.
+    ///
+    /// ```text
+    /// schip1.1
+    /// shift = vy
+    /// clip = false
+    /// ```
+    pub fn apply(&mut self, text: &str) -> Result<(), String> {
+        for entry in text.lines().flat_map(|line| line.split('#').next().unwrap_or("").split(',')) {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let (name, value) = match entry.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => {
+                    *self = entry.parse()?;
+                    continue;
+                },
+            };
+            match name {
+                "shift"         => self.shift_uses_vy = match value {
+                    "vy"    => true,
+                    "vx"    => false,
+                    _       => parse_bool(value)?,
+                },
+                "memory"        => self.memory_increment = match value {
+                    "none" | "0"    => MemoryIncrement::None,
+                    "x"             => MemoryIncrement::X,
+                    "x+1"           => MemoryIncrement::XPlusOne,
+                    _               => return Err(format!("invalid value for memory: {}", value)),
+                },
+                "jump"          => self.jump_uses_vx = match value {
+                    "vx"    => true,
+                    "v0"    => false,
+                    _       => parse_bool(value)?,
+                },
+                "vf_reset"      => self.logic_resets_vf = parse_bool(value)?,
+                "clip"          => self.clip_sprites = parse_bool(value)?,
+                "display_wait"  => self.display_wait = parse_bool(value)?,
+                _ => return Err(format!("unknown quirk: {}", name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "on" | "yes" | "1"     => Ok(true),
+        "false" | "off" | "no" | "0"    => Ok(false),
+        _ => Err(format!("invalid quirk value: {}", value)),
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::for_variant(Variant::default())
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    /// Look up a preset by name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmac-vip" | "chip8"   => Ok(Self::cosmac_vip()),
+            "chip48" | "chip-48"                        => Ok(Self::chip48()),
+            "schip1.0" | "superchip1.0"                 => Ok(Self::superchip_1_0()),
+            "schip" | "schip1.1" | "superchip1.1"       => Ok(Self::superchip_1_1()),
+            "xochip" | "xo-chip"                        => Ok(Self::xo_chip()),
+            _ => Err(format!("unknown quirks preset: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let memory = match self.memory_increment {
+            MemoryIncrement::None       => "none",
+            MemoryIncrement::X          => "x",
+            MemoryIncrement::XPlusOne   => "x+1",
+        };
+        write!(f, "shift = {}, memory = {}, jump = {}, vf_reset = {}, clip = {}, display_wait = {}",
+            if self.shift_uses_vy { "vy" } else { "vx" },
+            memory,
+            if self.jump_uses_vx { "vx" } else { "v0" },
+            self.logic_resets_vf,
+            self.clip_sprites,
+            self.display_wait)
+    }
+}
@@ -0,0 +1,398 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// reference.rs file
+//
+
+use crate::charset;
+use crate::config;
+use crate::error::Chip8Error;
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::rng::Rng;
+use crate::variant::Variant;
+
+/// A second interpreter, written to be obviously right rather than fast or
+/// pluggable: one flat struct, one match over the opcodes, each arm a
+/// direct transcription of what the instruction does. `harness::lockstep`
+/// runs it next to `Cpu` to catch the two disagreeing.
+///
+/// Only the machine is modelled, there are no frames: the host sets `keys`
+/// and calls `tick_timers` itself.
+#[derive(Clone)]
+pub struct Reference {
+    pub variant:    Variant,
+    pub quirks:     Quirks,
+    pub v:          [u8; 16],
+    pub i:          u16,
+    pub pc:         u16,
+    /// Return addresses, the innermost call last.
+    pub stack:      Vec<u16>,
+    pub dt:         u8,
+    pub st:         u8,
+    pub memory:     Vec<u8>,
+    pub width:      usize,
+    pub height:     usize,
+    /// Plane bits of every pixel, row by row.
+    pub screen:     Vec<u8>,
+    pub planes:     u8,
+    pub rpl:        [u8; 16],
+    pub pattern:    [u8; 16],
+    pub pitch:      u8,
+    pub exited:     bool,
+    pub keys:       [bool; 16],
+    pub rng:        Rng,
+}
+
+impl Reference {
+    /// A machine with the fonts and the ROM loaded, about to run 0x200.
+    pub fn new(rom: &[u8], variant: Variant, quirks: Quirks, rng: Rng) -> Result<Self, Chip8Error> {
+        let mut memory = vec![0; variant.memory_size()];
+        if rom.len() > memory.len() - 0x200 {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max: memory.len() - 0x200 });
+        }
+        memory[..config::CHIP8_CHARSET_LEN].copy_from_slice(&charset::CHIP8_CHARSET);
+        memory[config::SCHIP_BIG_CHARSET_ADDR..config::SCHIP_BIG_CHARSET_ADDR + config::SCHIP_BIG_CHARSET_LEN].copy_from_slice(&charset::SCHIP_BIG_CHARSET);
+        memory[0x200..0x200 + rom.len()].copy_from_slice(rom);
+        Ok(Self {
+            variant,
+            quirks,
+            v:          [0; 16],
+            i:          0,
+            pc:         0x200,
+            stack:      Vec::new(),
+            dt:         0,
+            st:         0,
+            memory,
+            width:      64,
+            height:     32,
+            screen:     vec![0; 64 * 32],
+            planes:     1,
+            rpl:        [0; 16],
+            pattern:    [0; 16],
+            pitch:      config::XOCHIP_DEFAULT_PITCH,
+            exited:     false,
+            keys:       [false; 16],
+            rng,
+        })
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.screen[y * self.width + x]
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+    }
+
+    fn read(&self, addr: usize) -> Result<u8, Chip8Error> {
+        self.memory.get(addr).copied().ok_or(Chip8Error::MemoryOutOfBounds(addr))
+    }
+
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), Chip8Error> {
+        match self.memory.get_mut(addr) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            },
+            None => Err(Chip8Error::MemoryOutOfBounds(addr)),
+        }
+    }
+
+    /// Where a skip lands: over the next instruction, which on XO-CHIP may
+    /// be the four byte F000 nnnn.
+    fn skip(&self) -> Result<u16, Chip8Error> {
+        let next = self.pc as usize + 2;
+        let long = self.variant.has_xochip() && self.read(next)? == 0xF0 && self.read(next + 1)? == 0x00;
+        Ok(self.pc.wrapping_add(if long { 6 } else { 4 }))
+    }
+
+    /// Ex9E and ExA1 only look at the low nibble.
+    fn key(&self, n: u8) -> bool {
+        self.keys[(n & 0xF) as usize]
+    }
+
+    fn set_resolution(&mut self, hires: bool) {
+        (self.width, self.height) = if hires { (128, 64) } else { (64, 32) };
+        self.screen = vec![0; self.width * self.height];
+    }
+
+    /// Move the selected planes by dx, dy pixels, blank pixels come in.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let old = self.screen.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (from_x, from_y) = (x as isize - dx, y as isize - dy);
+                let inside = from_x >= 0 && from_y >= 0 && (from_x as usize) < self.width && (from_y as usize) < self.height;
+                let moved = if inside { old[from_y as usize * self.width + from_x as usize] } else { 0 };
+                self.screen[y * self.width + x] = (old[y * self.width + x] & !self.planes) | (moved & self.planes);
+            }
+        }
+    }
+
+    /// Dxyn: XOR the sprite at I onto every selected plane, VF = any pixel
+    /// of the plane turned off.
+    fn draw(&mut self, x: usize, y: usize, n: u8) -> Result<(), Chip8Error> {
+        let wide = n == 0 && self.variant.has_superchip();
+        let (rows, row_bytes) = if wide { (16, 2) } else { (n as usize, 1) };
+        let left = self.v[x] as usize % self.width;
+        let top = self.v[y] as usize % self.height;
+        let mut addr = self.i;
+        self.v[0xF] = 0;
+        for plane in [1, 2] {
+            if self.planes & plane == 0 {
+                continue;
+            }
+            for row in 0..rows {
+                let row_addr = addr.wrapping_add((row * row_bytes) as u16) as usize;
+                let mut bits = self.read(row_addr)? as u16;
+                if wide {
+                    bits = bits << 8 | self.read(row_addr + 1)? as u16;
+                }
+                let mut py = top + row;
+                if py >= self.height {
+                    if self.quirks.clip_sprites {
+                        continue;
+                    }
+                    py %= self.height;
+                }
+                for column in 0..row_bytes * 8 {
+                    if bits & (1 << (row_bytes * 8 - 1 - column)) == 0 {
+                        continue;
+                    }
+                    let mut px = left + column;
+                    if px >= self.width {
+                        if self.quirks.clip_sprites {
+                            break;
+                        }
+                        px %= self.width;
+                    }
+                    let pixel = &mut self.screen[py * self.width + px];
+                    if *pixel & plane != 0 {
+                        self.v[0xF] = 1;
+                    }
+                    *pixel ^= plane;
+                }
+            }
+            addr = addr.wrapping_add((rows * row_bytes) as u16);
+        }
+        Ok(())
+    }
+
+    /// Execute the instruction at PC. After an error the machine is left
+    /// as it is, half done.
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        if self.exited {
+            return Ok(());
+        }
+        let pc = self.pc as usize;
+        if pc + 1 >= self.memory.len() {
+            return Err(Chip8Error::PcOutOfBounds(self.pc));
+        }
+        let opcode = (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16;
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let n = (opcode & 0xF) as u8;
+        let kk = opcode as u8;
+        let nnn = opcode & 0xFFF;
+        let schip = self.variant.has_superchip();
+        let xochip = self.variant.has_xochip();
+        let next = self.pc.wrapping_add(2);
+
+        self.pc = match opcode >> 12 {
+            0x0 if opcode & 0xFFF0 == 0x00C0 && schip => {
+                self.scroll(0, n as isize);
+                next
+            },
+            0x0 if opcode & 0xFFF0 == 0x00D0 && xochip => {
+                self.scroll(0, -(n as isize));
+                next
+            },
+            0x0 => match opcode {
+                0x00E0 => {
+                    for pixel in self.screen.iter_mut() {
+                        *pixel &= !self.planes;
+                    }
+                    next
+                },
+                0x00EE => self.stack.pop().ok_or(Chip8Error::StackUnderflow)?,
+                0x00FB if schip => {
+                    self.scroll(4, 0);
+                    next
+                },
+                0x00FC if schip => {
+                    self.scroll(-4, 0);
+                    next
+                },
+                0x00FD if schip => {
+                    self.exited = true;
+                    self.pc
+                },
+                0x00FE if schip => {
+                    self.set_resolution(false);
+                    next
+                },
+                0x00FF if schip => {
+                    self.set_resolution(true);
+                    next
+                },
+                _ => next,
+            },
+            0x1 => nnn,
+            0x2 => {
+                if self.stack.len() >= config::CHIP8_STACK_SIZE {
+                    return Err(Chip8Error::StackOverflow);
+                }
+                self.stack.push(next);
+                nnn
+            },
+            0x3 if self.v[x] == kk => self.skip()?,
+            0x4 if self.v[x] != kk => self.skip()?,
+            0x5 if n == 0 && self.v[x] == self.v[y] => self.skip()?,
+            0x5 if (n == 2 || n == 3) && xochip => {
+                let registers: Vec<usize> = if x <= y { (x..=y).collect() } else { (y..=x).rev().collect() };
+                for (offset, r) in registers.into_iter().enumerate() {
+                    if n == 2 {
+                        self.write(self.i as usize + offset, self.v[r])?;
+                    } else {
+                        self.v[r] = self.read(self.i as usize + offset)?;
+                    }
+                }
+                next
+            },
+            0x6 => {
+                self.v[x] = kk;
+                next
+            },
+            0x7 => {
+                self.v[x] = self.v[x].wrapping_add(kk);
+                next
+            },
+            0x8 => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                let shifted = if self.quirks.shift_uses_vy { vy } else { vx };
+                // the result goes to Vx first, then the flag to VF
+                let (result, flag) = match n {
+                    0x0 => (vy, None),
+                    0x1 => (vx | vy, self.quirks.logic_resets_vf.then_some(0)),
+                    0x2 => (vx & vy, self.quirks.logic_resets_vf.then_some(0)),
+                    0x3 => (vx ^ vy, self.quirks.logic_resets_vf.then_some(0)),
+                    0x4 => (vx.wrapping_add(vy), Some((vx as u16 + vy as u16 > 0xFF) as u8)),
+                    0x5 => (vx.wrapping_sub(vy), Some((vx >= vy) as u8)),
+                    0x6 => (shifted >> 1, Some(shifted & 1)),
+                    0x7 => (vy.wrapping_sub(vx), Some((vy >= vx) as u8)),
+                    0xE => (shifted << 1, Some(shifted >> 7)),
+                    _   => (vx, None),
+                };
+                self.v[x] = result;
+                if let Some(flag) = flag {
+                    self.v[0xF] = flag;
+                }
+                next
+            },
+            0x9 if n == 0 && self.v[x] != self.v[y] => self.skip()?,
+            0xA => {
+                self.i = nnn;
+                next
+            },
+            0xB => nnn + self.v[if self.quirks.jump_uses_vx { x } else { 0 }] as u16,
+            0xC => {
+                self.v[x] = self.rng.next_byte(&self.memory) & kk;
+                next
+            },
+            0xD => {
+                self.draw(x, y, n)?;
+                next
+            },
+            0xE if kk == 0x9E && self.key(self.v[x]) => self.skip()?,
+            0xE if kk == 0xA1 && !self.key(self.v[x]) => self.skip()?,
+            0xF => match kk {
+                0x00 if x == 0 && xochip => {
+                    self.i = (self.read(pc + 2)? as u16) << 8 | self.read(pc + 3)? as u16;
+                    self.pc.wrapping_add(4)
+                },
+                0x01 if xochip => {
+                    self.planes = x as u8 & 0b11;
+                    next
+                },
+                0x02 if x == 0 && xochip => {
+                    for offset in 0..16 {
+                        self.pattern[offset] = self.read(self.i as usize + offset)?;
+                    }
+                    next
+                },
+                0x07 => {
+                    self.v[x] = self.dt;
+                    next
+                },
+                0x0A => match self.keys.iter().position(|down| *down) {
+                    Some(key) => {
+                        self.v[x] = key as u8;
+                        next
+                    },
+                    None => self.pc,
+                },
+                0x15 => {
+                    self.dt = self.v[x];
+                    next
+                },
+                0x18 => {
+                    self.st = self.v[x];
+                    next
+                },
+                0x1E => {
+                    self.i = self.i.wrapping_add(self.v[x] as u16);
+                    next
+                },
+                0x29 => {
+                    self.i = (self.v[x] & 0xF) as u16 * 5;
+                    next
+                },
+                0x30 if schip => {
+                    self.i = (config::SCHIP_BIG_CHARSET_ADDR + (self.v[x] & 0xF) as usize * 10) as u16;
+                    next
+                },
+                0x33 => {
+                    let i = self.i as usize;
+                    self.write(i, self.v[x] / 100)?;
+                    self.write(i + 1, self.v[x] / 10 % 10)?;
+                    self.write(i + 2, self.v[x] % 10)?;
+                    next
+                },
+                0x3A if xochip => {
+                    self.pitch = self.v[x];
+                    next
+                },
+                0x55 | 0x65 => {
+                    for r in 0..=x {
+                        if kk == 0x55 {
+                            self.write(self.i as usize + r, self.v[r])?;
+                        } else {
+                            self.v[r] = self.read(self.i as usize + r)?;
+                        }
+                    }
+                    self.i = self.i.wrapping_add(match self.quirks.memory_increment {
+                        MemoryIncrement::None       => 0,
+                        MemoryIncrement::X          => x as u16,
+                        MemoryIncrement::XPlusOne   => x as u16 + 1,
+                    });
+                    next
+                },
+                0x75 | 0x85 if schip => {
+                    let last = x.min(self.variant.rpl_flags_num() - 1);
+                    if kk == 0x75 {
+                        self.rpl[..=last].copy_from_slice(&self.v[..=last]);
+                    } else {
+                        self.v[..=last].copy_from_slice(&self.rpl[..=last]);
+                    }
+                    next
+                },
+                _ => next,
+            },
+            _ => next,
+        };
+        Ok(())
+    }
+}
@@ -6,8 +6,7 @@
 // registers.rs file
 //
 
-#[path = "config.rs"]
-mod config;
+use crate::config;
 
 pub struct Registers {
     pub v: [u8; config::CHIP8_REGISTERS_NUM],
@@ -30,3 +29,9 @@ impl Registers {
         }
     }
 }
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// rewind.rs file
+//
+
+use crate::cpu::Cpu;
+use crate::error::Chip8Error;
+
+use std::collections::VecDeque;
+
+/// How to get from a snapshot back to the one recorded before it.
+enum Undo {
+    /// Runs of bytes to XOR into the newer snapshot: (offset, bytes).
+    Delta(Vec<(usize, Vec<u8>)>),
+    /// The older snapshot as a whole, used when the size changed (for
+    /// example when the display switched resolution).
+    Full(Vec<u8>),
+}
+
+/// Ring buffer of per-frame save states. Only the newest snapshot is kept
+/// whole, every older one is stored as the difference to its successor,
+/// which is a handful of bytes for most frames.
+pub struct RewindBuffer {
+    capacity:   usize,
+    current:    Option<Vec<u8>>,
+    undo:       VecDeque<Undo>,
+}
+
+impl RewindBuffer {
+    /// Keep up to `capacity` frames to step back through.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            current:    None,
+            undo:       VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Number of frames that can be rewound.
+    pub fn len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.undo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.undo.clear();
+    }
+
+    /// Record the state the machine is in now, call it once per frame.
+    pub fn record(&mut self, cpu: &Cpu) {
+        let snapshot = cpu.save_state();
+        if let Some(previous) = self.current.take() {
+            self.undo.push_back(diff(&previous, &snapshot));
+            if self.undo.len() > self.capacity {
+                self.undo.pop_front();
+            }
+        }
+        self.current = Some(snapshot);
+    }
+
+    /// Put the machine back to the frame before the last recorded one.
+    /// Return false if there is nothing left to rewind.
+    pub fn rewind(&mut self, cpu: &mut Cpu) -> Result<bool, Chip8Error> {
+        let (Some(current), Some(undo)) = (self.current.as_mut(), self.undo.pop_back()) else {
+            return Ok(false);
+        };
+        match undo {
+            Undo::Delta(runs) => {
+                for (offset, bytes) in runs {
+                    for (n, byte) in bytes.iter().enumerate() {
+                        current[offset + n] ^= byte;
+                    }
+                }
+            },
+            Undo::Full(snapshot) => *current = snapshot,
+        }
+        cpu.load_state(current)?;
+        Ok(true)
+    }
+}
+
+/// XOR the two snapshots and keep only the runs that differ. Runs closer
+/// than a few bytes are merged, a new run costs more than the zeros.
+fn diff(old: &[u8], new: &[u8]) -> Undo {
+    if old.len() != new.len() {
+        return Undo::Full(old.to_vec());
+    }
+    const MERGE_GAP: usize = 8;
+    let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut last_diff = 0;
+    for (offset, (a, b)) in old.iter().zip(new.iter()).enumerate() {
+        if a == b {
+            continue;
+        }
+        match runs.last_mut() {
+            Some((start, bytes)) if offset - last_diff <= MERGE_GAP => {
+                bytes.extend(old[*start + bytes.len()..=offset].iter().zip(&new[*start + bytes.len()..=offset]).map(|(a, b)| a ^ b));
+            },
+            _ => runs.push((offset, vec![a ^ b])),
+        }
+        last_diff = offset;
+    }
+    Undo::Delta(runs)
+}
@@ -0,0 +1,113 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// rng.rs file
+//
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The generator behind Cxkk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RngKind {
+    /// xorshift64*, uniform over all 256 values.
+    #[default]
+    Xorshift,
+    /// A pointer walks through low memory (the font) and the byte found
+    /// there is added into a rotating 8-bit accumulator. Small state and
+    /// visible patterns, for games that look odd with uniform numbers. It
+    /// is not the COSMAC VIP's routine, which also mixed in hardware timing.
+    FontWalk,
+}
+
+/// Random number generator that is part of the machine state, so a run
+/// started from the same seed always makes the same choices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    pub kind:   RngKind,
+    state:      u64,
+}
+
+impl Rng {
+    pub fn new(kind: RngKind, seed: u64) -> Self {
+        let mut rng = Self { kind, state: 0 };
+        rng.set_state(match kind {
+            // xorshift gets stuck at 0, spread the seed over all the bits
+            RngKind::Xorshift   => splitmix64(seed),
+            RngKind::FontWalk   => seed,
+        });
+        rng
+    }
+
+    /// Seed from the operating system's randomness.
+    pub fn from_entropy(kind: RngKind) -> Self {
+        Self::new(kind, rand::random())
+    }
+
+    /// The raw generator state, for save states.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: u64) {
+        self.state = match self.kind {
+            RngKind::Xorshift if state == 0 => splitmix64(0),
+            RngKind::Xorshift               => state,
+            RngKind::FontWalk               => state & 0xFFFF,
+        };
+    }
+
+    /// Next random byte, `memory` is only read by the font walk generator.
+    pub fn next_byte(&mut self, memory: &[u8]) -> u8 {
+        match self.kind {
+            RngKind::Xorshift => {
+                self.state ^= self.state >> 12;
+                self.state ^= self.state << 25;
+                self.state ^= self.state >> 27;
+                (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+            },
+            RngKind::FontWalk => {
+                let pointer = (self.state >> 8) as u8;
+                let accumulator = (self.state as u8).rotate_right(1).wrapping_add(memory.get(pointer as usize).copied().unwrap_or(0)).wrapping_add(pointer);
+                self.state = (pointer.wrapping_add(1) as u64) << 8 | accumulator as u64;
+                accumulator
+            },
+        }
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::from_entropy(RngKind::default())
+    }
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+impl fmt::Display for RngKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RngKind::Xorshift   => write!(f, "xorshift"),
+            RngKind::FontWalk   => write!(f, "fontwalk"),
+        }
+    }
+}
+
+impl FromStr for RngKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "xorshift"  => Ok(RngKind::Xorshift),
+            "fontwalk"  => Ok(RngKind::FontWalk),
+            _ => Err(format!("unknown random number generator: {}", s)),
+        }
+    }
+}
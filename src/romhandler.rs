@@ -6,35 +6,32 @@
 // romhandler.rs file
 //
 
-#[path = "config.rs"]
-mod config;
+use crate::config;
+use crate::error::Chip8Error;
 
 use std::fs;
 use std::path::Path;
 
 pub struct RomCartridge {
-    pub rom: [u8; config::CHIP8_INSTRUCTIONS_SIZE],
+    pub rom: Vec<u8>,
 }
 
 impl RomCartridge {
-    pub fn new(romfilename: String) -> Self {
-        let (bytes, bytes_size) = Self::load_bytes_from_rom(romfilename);
-        Self {
-            rom: bytes,
-        }
+    pub fn new(romfilename: String) -> Result<Self, Chip8Error> {
+        let bytes_buf: Vec<u8> = fs::read(Path::new(romfilename.as_str())).map_err(Chip8Error::RomIo)?;
+        Self::from_bytes(&bytes_buf)
     }
 
-
-    fn load_bytes_from_rom(romfilename: String) -> ([u8; config::CHIP8_INSTRUCTIONS_SIZE], usize) {
-        let bytes_buf: Vec<u8> = fs::read(Path::new(romfilename.as_str())).unwrap(); 
-        let mut bytes = [0u8; config::CHIP8_INSTRUCTIONS_SIZE];
-        if bytes_buf.len() > config::CHIP8_INSTRUCTIONS_SIZE {
-            panic!("Error: ROM bytes exceed memory size");
-        }
-        for i in 0..bytes_buf.len() {
-            bytes[i] = bytes_buf[i];
+    /// Build a cartridge from ROM bytes that are already in memory.
+    /// Anything up to the XO-CHIP limit is accepted here, whether it fits
+    /// the memory of the selected variant is checked when it's loaded.
+    pub fn from_bytes(bytes_buf: &[u8]) -> Result<Self, Chip8Error> {
+        if bytes_buf.len() > config::XOCHIP_INSTRUCTIONS_SIZE {
+            return Err(Chip8Error::RomTooLarge { size: bytes_buf.len(), max: config::XOCHIP_INSTRUCTIONS_SIZE });
         }
-        (bytes, bytes.len())
+        Ok(Self {
+            rom: bytes_buf.to_vec(),
+        })
     }
 
 }
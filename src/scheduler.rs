@@ -0,0 +1,217 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// scheduler.rs file
+//
+
+use crate::backend::Hotkey;
+use crate::config;
+use crate::cpu::Cpu;
+use crate::debugger::DebugFrontend;
+use crate::error::Chip8Error;
+use crate::rewind::RewindBuffer;
+use crate::state;
+
+use std::time::{Duration, Instant};
+
+/// What a hotkey did, for the frontend to show.
+#[derive(Debug)]
+pub enum HotkeyEvent {
+    /// The hotkey would make a movie impossible to replay and was ignored.
+    NotWhileDeterministic,
+    /// The speed changed, to this many instructions per frame.
+    Speed(usize),
+    /// Break was pressed without a debugger attached.
+    DebuggerOff,
+    /// The machine state was saved to the numbered slot.
+    Saved(u8),
+    /// The machine state was restored from the numbered slot.
+    Loaded(u8),
+    /// Saving to or loading from the slot failed, the machine is unchanged.
+    SlotFailed(u8, Chip8Error),
+}
+
+/// Paces the machine in real time. Every 1/60th of a second of wall-clock
+/// time one frame runs: a fixed number of instructions, one tick of the
+/// timers and one render, no matter how long the instructions took.
+pub struct Scheduler {
+    pub instructions_per_frame: usize,
+    frame_duration:             Duration,
+    next_frame:                 Option<Instant>,
+    /// ROM path the numbered save state slots are stored next to.
+    save_slots:                 Option<String>,
+    rewind:                     Option<RewindBuffer>,
+    /// Set while recording or replaying a movie.
+    deterministic:              bool,
+    debugger:                   Option<Box<dyn DebugFrontend>>,
+}
+
+impl Scheduler {
+    pub fn new(instructions_per_frame: usize) -> Self {
+        Self {
+            instructions_per_frame: instructions_per_frame.max(1),
+            frame_duration:         Duration::from_nanos(1_000_000_000 / config::CHIP8_TIMER_HZ),
+            next_frame:             None,
+            save_slots:             None,
+            rewind:                 None,
+            deterministic:          false,
+            debugger:               None,
+        }
+    }
+
+    /// Build a scheduler running about `hz` instructions per second.
+    pub fn from_hz(hz: usize) -> Self {
+        Self::new(hz / config::CHIP8_TIMER_HZ as usize)
+    }
+
+    pub fn set_speed(&mut self, instructions_per_frame: usize) {
+        self.instructions_per_frame = instructions_per_frame.max(1);
+    }
+
+    /// Run about a quarter faster.
+    pub fn speed_up(&mut self) {
+        self.set_speed(self.instructions_per_frame + (self.instructions_per_frame / 4).max(1));
+    }
+
+    /// Run about a fifth slower.
+    pub fn slow_down(&mut self) {
+        self.set_speed(self.instructions_per_frame - (self.instructions_per_frame / 5).max(1));
+    }
+
+    /// Enable the save state hotkeys, the slots are stored as files next
+    /// to the ROM.
+    pub fn set_save_slots(&mut self, rom: &str) {
+        self.save_slots = Some(rom.to_string());
+    }
+
+    /// Record the last `seconds` of play so the rewind hotkey can step back
+    /// through them, 0 turns rewinding off.
+    pub fn set_rewind(&mut self, seconds: usize) {
+        self.rewind = match seconds {
+            0 => None,
+            _ => Some(RewindBuffer::new(seconds * config::CHIP8_TIMER_HZ as usize)),
+        };
+    }
+
+    /// Ignore the hotkeys that would make the run impossible to replay:
+    /// speed changes, loading states and rewinding.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+        if deterministic {
+            self.rewind = None;
+        }
+    }
+
+    /// Run the frames through a debugger, which stops the machine in
+    /// between instructions whenever it wants to.
+    pub fn set_debugger(&mut self, debugger: Box<dyn DebugFrontend>) {
+        self.debugger = Some(debugger);
+    }
+
+    /// Handle a frontend hotkey and return what happened, if anything. A
+    /// failed save or load leaves the machine as it was, the save state
+    /// hotkeys do nothing until `set_save_slots` was called.
+    pub fn handle_hotkey(&mut self, cpu: &mut Cpu, hotkey: Hotkey) -> Option<HotkeyEvent> {
+        match (hotkey, &self.save_slots) {
+            (Hotkey::SpeedUp | Hotkey::SlowDown | Hotkey::LoadState(_), _) if self.deterministic => {
+                Some(HotkeyEvent::NotWhileDeterministic)
+            },
+            (Hotkey::SpeedUp, _) => {
+                self.speed_up();
+                Some(HotkeyEvent::Speed(self.instructions_per_frame))
+            },
+            (Hotkey::SlowDown, _) => {
+                self.slow_down();
+                Some(HotkeyEvent::Speed(self.instructions_per_frame))
+            },
+            (Hotkey::SaveState(slot), Some(rom)) => {
+                match std::fs::write(state::slot_path(rom, slot), cpu.save_state()) {
+                    Ok(())      => Some(HotkeyEvent::Saved(slot)),
+                    Err(error)  => Some(HotkeyEvent::SlotFailed(slot, Chip8Error::StateIo(error))),
+                }
+            },
+            (Hotkey::LoadState(slot), Some(rom)) => {
+                let result = std::fs::read(state::slot_path(rom, slot)).map_err(Chip8Error::StateIo)
+                    .and_then(|bytes| cpu.load_state(&bytes));
+                match result {
+                    Ok(())      => {
+                        // the recorded frames lead up to a different moment now
+                        if let Some(rewind) = self.rewind.as_mut() {
+                            rewind.clear();
+                        }
+                        Some(HotkeyEvent::Loaded(slot))
+                    },
+                    Err(error)  => Some(HotkeyEvent::SlotFailed(slot, error)),
+                }
+            },
+            (Hotkey::SaveState(_) | Hotkey::LoadState(_), None) => None,
+            (Hotkey::Break, _) => match self.debugger.as_mut() {
+                Some(debugger)  => {
+                    debugger.pause();
+                    None
+                },
+                None            => Some(HotkeyEvent::DebuggerOff),
+            },
+            // handled in run, it replaces the frame
+            (Hotkey::Rewind, _) => None,
+        }
+    }
+
+    /// Run one frame and sleep until it's time for the next one.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<usize, Chip8Error> {
+        let executed = match self.debugger.as_mut() {
+            Some(debugger)  => debugger.run_frame(cpu, self.instructions_per_frame)?,
+            None            => cpu.run_frame(self.instructions_per_frame)?,
+        };
+        self.wait_for_next_frame();
+        Ok(executed)
+    }
+
+    /// Sleep until the next frame is due. If the host fell more than a few
+    /// frames behind, the lost time is given up instead of being caught up
+    /// with a burst of frames.
+    fn wait_for_next_frame(&mut self) {
+        let now = Instant::now();
+        let next_frame = self.next_frame.unwrap_or(now) + self.frame_duration;
+        if next_frame > now {
+            std::thread::sleep(next_frame - now);
+            self.next_frame = Some(next_frame);
+        } else if now - next_frame > self.frame_duration * 4 {
+            self.next_frame = Some(now);
+        } else {
+            self.next_frame = Some(next_frame);
+        }
+    }
+
+    /// Run until the program jumps below 0x200, exits or the keypad backend
+    /// (or the debugger) asks to quit. What the hotkeys did is passed to
+    /// `report`.
+    pub fn run(&mut self, cpu: &mut Cpu, mut report: impl FnMut(HotkeyEvent)) -> Result<(), Chip8Error> {
+        cpu.chip8.present();
+        while (cpu.chip8.registers.pc >= 0x200) && !cpu.chip8.exited && !cpu.chip8.quit_requested() {
+            if self.debugger.as_ref().is_some_and(|debugger| debugger.quit_requested()) {
+                break;
+            }
+            let hotkeys = cpu.chip8.poll_hotkeys();
+            for hotkey in hotkeys.iter() {
+                if let Some(event) = self.handle_hotkey(cpu, *hotkey) {
+                    report(event);
+                }
+            }
+            if hotkeys.contains(&Hotkey::Rewind) {
+                if let Some(rewind) = self.rewind.as_mut() {
+                    rewind.rewind(cpu)?;
+                    self.wait_for_next_frame();
+                    continue;
+                }
+            }
+            self.run_frame(cpu)?;
+            if let Some(rewind) = self.rewind.as_mut() {
+                rewind.record(cpu);
+            }
+        }
+        Ok(())
+    }
+}
@@ -6,32 +6,102 @@
 // speakers.rs file
 //
 
-use rodio::{Decoder, OutputStream, Sink};
-use rodio::source::{SineWave, Source};
+use crate::backend::AudioSink;
+use crate::config;
+use crate::error::Chip8Error;
 
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rodio::{OutputStream, Sink};
+use rodio::source::Source;
+
+const SAMPLE_RATE: u32 = 44100;
+const TONE_HZ: f32 = 700.0;
+
+/// What the buzzer plays: the plain tone, or an XO-CHIP audio pattern with
+/// its playback rate in bits per second.
+type Waveform = Option<([u8; config::XOCHIP_AUDIO_PATTERN_LEN], f32)>;
+
+/// Endless source playing whatever waveform is currently set.
+struct BuzzerWave {
+    waveform: Arc<Mutex<Waveform>>,
+    sample:   u64,
+}
+
+impl Iterator for BuzzerWave {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let time = self.sample as f32 / SAMPLE_RATE as f32;
+        self.sample = self.sample.wrapping_add(1);
+        let waveform = match self.waveform.lock() {
+            Ok(waveform) => *waveform,
+            Err(_)       => None,
+        };
+        match waveform {
+            None => Some((time * TONE_HZ * 2.0 * std::f32::consts::PI).sin()),
+            Some((pattern, rate)) => {
+                let bit = (time * rate) as usize % (config::XOCHIP_AUDIO_PATTERN_LEN * 8);
+                if (pattern[bit / 8] >> (7 - bit % 8)) & 1 == 1 {
+                    Some(0.5)
+                } else {
+                    Some(-0.5)
+                }
+            },
+        }
+    }
+}
+
+impl Source for BuzzerWave {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// rodio buzzer playing a 700 Hz sine wave, or the XO-CHIP audio pattern
+/// once a program set one.
 pub struct Speaker {
     pub sink: Sink, // remove pub after done testing
-    stream: OutputStream,
+    _stream: OutputStream,
+    waveform: Arc<Mutex<Waveform>>,
 }
 
 impl Speaker {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self, Chip8Error> {
         let (stream_creator, stream_handle) = match OutputStream::try_default() {
             Ok((str, str_hand)) => (str, str_hand),
-            Err(error) => panic!("Failed to create sound stream handle! ERROR: {}", error),
+            Err(error) => return Err(Chip8Error::AudioUnavailable(format!("failed to create sound stream handle: {}", error))),
         };
         let sink_creator = match Sink::try_new(&stream_handle) {
             Ok(sink) => sink,
-            Err(error) => panic!("Failed to create sound sink! ERROR: {}", error),
+            Err(error) => return Err(Chip8Error::AudioUnavailable(format!("failed to create sound sink: {}", error))),
+        };
+        let waveform = Arc::new(Mutex::new(None));
+        let source = BuzzerWave {
+            waveform: Arc::clone(&waveform),
+            sample:   0,
         };
-        let source = SineWave::new(700.0);
         sink_creator.append(source);
         sink_creator.set_volume(0.5);
         sink_creator.pause();
-        Self {
+        Ok(Self {
             sink: sink_creator,
-            stream: stream_creator,
-        }
+            _stream: stream_creator,
+            waveform,
+        })
     }
 
     pub fn sound(&mut self) {
@@ -48,13 +118,24 @@ impl Speaker {
 
     pub fn check_st(&mut self, st: u8) {
         if st > 0 {
-            if self.sink.is_paused() == true {
+            if self.sink.is_paused() {
                 self.sink.play();
             }
-        } else {
-            if self.sink.is_paused() == false {
-                self.sink.pause();
-            }
+        } else if !self.sink.is_paused() {
+            self.sink.pause();
+        }
+    }
+}
+
+impl AudioSink for Speaker {
+    fn check_st(&mut self, st: u8) {
+        Speaker::check_st(self, st);
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; config::XOCHIP_AUDIO_PATTERN_LEN], pitch: u8) {
+        let rate = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+        if let Ok(mut waveform) = self.waveform.lock() {
+            *waveform = Some((*pattern, rate));
         }
     }
 }
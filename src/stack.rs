@@ -6,10 +6,7 @@
 // stack.rs file
 //
 
-#[path = "config.rs"]
-mod config;
-#[path = "registers.rs"]
-mod registers;
+use crate::config;
 
 pub struct Stack {
     pub stack: [u16; config::CHIP8_STACK_SIZE],
@@ -22,3 +19,9 @@ impl Stack {
         }
     }
 }
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// state.rs file
+//
+
+use crate::chip8::Chip8;
+use crate::config;
+use crate::error::Chip8Error;
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::rng::RngKind;
+use crate::variant::Variant;
+
+use std::path::PathBuf;
+
+/// First bytes of every save state.
+pub const STATE_MAGIC: &[u8; 4] = b"C8ST";
+/// Bumped whenever the layout below changes, older states are refused.
+pub const STATE_VERSION: u16 = 1;
+
+// Layout, all numbers little endian:
+//
+//   magic "C8ST", version u16, ROM hash u64
+//   variant u8, quirks 6 x u8
+//   V0-VF, I u16, PC u16, SP u8, DT u8, ST u8
+//   stack 16 x u16, RPL flags 16 x u8, exited u8
+//   audio pattern 16 x u8, pitch u8
+//   hires u8, selected planes u8, pixels width x height u8 (column by column)
+//   memory size u32, memory
+//   random number generator kind u8, state u64
+
+/// FNV-1a hash of the ROM, stored in the states so they are only loaded
+/// into the game they were saved from.
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in rom {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// File of a numbered save slot, `<rom>.state<slot>` next to the ROM.
+pub fn slot_path(rom: &str, slot: u8) -> PathBuf {
+    PathBuf::from(format!("{}.state{}", rom, slot))
+}
+
+/// Serialize the complete machine state. The keypad isn't part of it, it
+/// follows whatever the user holds down.
+pub fn save(chip8: &Chip8) -> Vec<u8> {
+    let mut out = Vec::with_capacity(chip8.memory.ram.len() + 256);
+    out.extend_from_slice(STATE_MAGIC);
+    out.extend_from_slice(&STATE_VERSION.to_le_bytes());
+    out.extend_from_slice(&rom_hash(&chip8.romcartridge.rom).to_le_bytes());
+
+    out.push(match chip8.variant {
+        Variant::Chip8      => 0,
+        Variant::SuperChip  => 1,
+        Variant::XoChip     => 2,
+    });
+    let quirks = &chip8.quirks;
+    out.push(quirks.shift_uses_vy as u8);
+    out.push(match quirks.memory_increment {
+        MemoryIncrement::None       => 0,
+        MemoryIncrement::X          => 1,
+        MemoryIncrement::XPlusOne   => 2,
+    });
+    out.push(quirks.jump_uses_vx as u8);
+    out.push(quirks.logic_resets_vf as u8);
+    out.push(quirks.clip_sprites as u8);
+    out.push(quirks.display_wait as u8);
+
+    let registers = &chip8.registers;
+    out.extend_from_slice(&registers.v);
+    out.extend_from_slice(&registers.i.to_le_bytes());
+    out.extend_from_slice(&registers.pc.to_le_bytes());
+    out.push(registers.sp as u8);
+    out.push(registers.dt);
+    out.push(registers.st);
+
+    for addr in chip8.stack.stack.iter() {
+        out.extend_from_slice(&addr.to_le_bytes());
+    }
+    out.extend_from_slice(&chip8.rpl);
+    out.push(chip8.exited as u8);
+    out.extend_from_slice(&chip8.audio_pattern);
+    out.push(chip8.pitch);
+
+    out.push(chip8.display.is_hires() as u8);
+    out.push(chip8.display.planes);
+    for column in chip8.display.pixels.iter() {
+        out.extend_from_slice(column);
+    }
+
+    out.extend_from_slice(&(chip8.memory.ram.len() as u32).to_le_bytes());
+    out.extend_from_slice(&chip8.memory.ram);
+
+    out.push(match chip8.rng.kind {
+        RngKind::Xorshift   => 0,
+        RngKind::FontWalk   => 1,
+    });
+    out.extend_from_slice(&chip8.rng.state().to_le_bytes());
+    out
+}
+
+/// Restore a state made by `save`. Nothing changes if the state is broken
+/// or belongs to another ROM.
+pub fn load(chip8: &mut Chip8, bytes: &[u8]) -> Result<(), Chip8Error> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(4)? != STATE_MAGIC {
+        return Err(Chip8Error::InvalidState("not a save state".to_string()));
+    }
+    let version = reader.u16()?;
+    if version != STATE_VERSION {
+        return Err(Chip8Error::InvalidState(format!("unsupported version {}", version)));
+    }
+    if reader.u64()? != rom_hash(&chip8.romcartridge.rom) {
+        return Err(Chip8Error::StateRomMismatch);
+    }
+
+    let variant = match reader.u8()? {
+        0 => Variant::Chip8,
+        1 => Variant::SuperChip,
+        2 => Variant::XoChip,
+        n => return Err(Chip8Error::InvalidState(format!("unknown variant {}", n))),
+    };
+    let quirks = Quirks {
+        shift_uses_vy:      reader.bool()?,
+        memory_increment:   match reader.u8()? {
+            0 => MemoryIncrement::None,
+            1 => MemoryIncrement::X,
+            2 => MemoryIncrement::XPlusOne,
+            n => return Err(Chip8Error::InvalidState(format!("unknown memory quirk {}", n))),
+        },
+        jump_uses_vx:       reader.bool()?,
+        logic_resets_vf:    reader.bool()?,
+        clip_sprites:       reader.bool()?,
+        display_wait:       reader.bool()?,
+    };
+
+    let mut v = [0; config::CHIP8_REGISTERS_NUM];
+    v.copy_from_slice(reader.take(config::CHIP8_REGISTERS_NUM)?);
+    let i = reader.u16()?;
+    let pc = reader.u16()?;
+    let sp = reader.u8()? as usize;
+    if sp > config::CHIP8_STACK_SIZE {
+        return Err(Chip8Error::InvalidState(format!("stack pointer out of range: {}", sp)));
+    }
+    let dt = reader.u8()?;
+    let st = reader.u8()?;
+
+    let mut stack = [0; config::CHIP8_STACK_SIZE];
+    for addr in stack.iter_mut() {
+        *addr = reader.u16()?;
+    }
+    let mut rpl = [0; config::CHIP8_REGISTERS_NUM];
+    rpl.copy_from_slice(reader.take(config::CHIP8_REGISTERS_NUM)?);
+    let exited = reader.bool()?;
+    let mut audio_pattern = [0; config::XOCHIP_AUDIO_PATTERN_LEN];
+    audio_pattern.copy_from_slice(reader.take(config::XOCHIP_AUDIO_PATTERN_LEN)?);
+    let pitch = reader.u8()?;
+
+    let mut display = chip8.display.clone();
+    display.set_hires(reader.bool()?);
+    display.planes = reader.u8()?;
+    // only XO-CHIP selects planes, and it has two of them
+    if display.planes > 0b11 || (!variant.has_xochip() && display.planes != 1) {
+        return Err(Chip8Error::InvalidState(format!("planes {:#04X} on {}", display.planes, variant)));
+    }
+    let height = display.height;
+    for column in display.pixels.iter_mut() {
+        column.copy_from_slice(reader.take(height)?);
+        if column.iter().any(|pixel| *pixel > 0b11) {
+            return Err(Chip8Error::InvalidState("pixel outside of the two planes".to_string()));
+        }
+    }
+
+    let memory_size = reader.u32()? as usize;
+    if memory_size != variant.memory_size() {
+        return Err(Chip8Error::InvalidState(format!("{} bytes of memory for {}", memory_size, variant)));
+    }
+    let ram = reader.take(memory_size)?.to_vec();
+    let mut rng = chip8.rng;
+    rng.kind = match reader.u8()? {
+        0 => RngKind::Xorshift,
+        1 => RngKind::FontWalk,
+        n => return Err(Chip8Error::InvalidState(format!("unknown random number generator {}", n))),
+    };
+    rng.set_state(reader.u64()?);
+    if reader.pos != bytes.len() {
+        return Err(Chip8Error::InvalidState("trailing bytes".to_string()));
+    }
+
+    chip8.variant = variant;
+    chip8.quirks = quirks;
+    chip8.registers.v = v;
+    chip8.registers.i = i;
+    chip8.registers.pc = pc;
+    chip8.registers.sp = sp;
+    chip8.registers.dt = dt;
+    chip8.registers.st = st;
+    chip8.stack.stack = stack;
+    chip8.rpl = rpl;
+    chip8.exited = exited;
+    chip8.vblank_wait = false;
+    chip8.audio_pattern = audio_pattern;
+    chip8.pitch = pitch;
+    chip8.display = display;
+    chip8.memory.ram = ram;
+    chip8.rng = rng;
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes:  &'a [u8],
+    pos:    usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Chip8Error> {
+        let bytes = self.bytes.get(self.pos..self.pos + len).ok_or(Chip8Error::InvalidState("truncated".to_string()))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, Chip8Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            n => Err(Chip8Error::InvalidState(format!("invalid flag {}", n))),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, Chip8Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Chip8Error> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, Chip8Error> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+}
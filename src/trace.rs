@@ -0,0 +1,107 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// trace.rs file
+//
+
+use crate::chip8::Chip8;
+use crate::cpu::Instruction;
+use crate::disasm;
+use crate::error::Chip8Error;
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// Writes a line for every executed instruction: its address, opcode and
+/// mnemonic, then the registers as they are after it ran.
+///
+/// ```text
+/// 0204  6102  LD V1, 0x02          V 01020000000000000000000000000000 I 0000 SP 0 DT 00 ST 00
+/// ```
+///
+/// Attach it with `Cpu::set_tracer`. In ring buffer mode only the last
+/// lines are kept and written out by `finish`, which also runs when the
+/// tracer is dropped.
+pub struct Tracer {
+    output:     Box<dyn Write>,
+    /// Only instructions at these addresses (both ends included) are
+    /// traced, all of them if empty.
+    ranges:     Vec<(u16, u16)>,
+    ring:       Option<(usize, VecDeque<String>)>,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write>) -> Self {
+        Self {
+            output,
+            ranges: Vec::new(),
+            ring:   None,
+        }
+    }
+
+    /// Only trace instructions in the address range, call it again to add
+    /// more ranges.
+    pub fn add_range(&mut self, start: u16, end: u16) {
+        self.ranges.push((start, end));
+    }
+
+    /// Keep only the last `lines` instructions and write them on `finish`.
+    /// 0 turns ring buffer mode off again, every line is written straight
+    /// away.
+    pub fn set_ring(&mut self, lines: usize) {
+        self.ring = (lines > 0).then(|| (lines, VecDeque::with_capacity(lines)));
+    }
+
+    /// Trace the instruction that was at `pc`, with the error it failed
+    /// with if it did.
+    pub fn record(&mut self, chip8: &Chip8, pc: u16, instruction: &Instruction, error: Option<&Chip8Error>) {
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|(start, end)| (*start..=*end).contains(&pc)) {
+            return;
+        }
+        let long = (instruction.instruction == 0xF000).then(|| {
+            let word = |n: u16| chip8.peek_byte(pc.wrapping_add(n) as usize).unwrap_or(0) as u16;
+            word(2) << 8 | word(3)
+        });
+        let text = disasm::mnemonic(instruction, chip8.variant, long).unwrap_or_else(|| format!("DW 0x{:04X}", instruction.instruction));
+        let registers = &chip8.registers;
+        let mut line = format!("{:04X}  {:04X}  {:<20} V ", pc, instruction.instruction, text);
+        for v in registers.v.iter() {
+            let _ = write!(line, "{:02X}", v);
+        }
+        let _ = write!(line, " I {:04X} SP {:X} DT {:02X} ST {:02X}", registers.i, registers.sp, registers.dt, registers.st);
+        if let Some(error) = error {
+            let _ = write!(line, "  error: {}", error);
+        }
+        match self.ring.as_mut() {
+            Some((capacity, lines)) => {
+                if lines.len() == *capacity {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            },
+            None => {
+                let _ = writeln!(self.output, "{}", line);
+            },
+        }
+    }
+
+    /// Write out the ring buffer and flush, call it when the machine stops
+    /// or crashed.
+    pub fn finish(&mut self) {
+        if let Some((_, lines)) = self.ring.as_mut() {
+            for line in lines.drain(..) {
+                let _ = writeln!(self.output, "{}", line);
+            }
+        }
+        let _ = self.output.flush();
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// variant.rs file
+//
+
+use crate::config;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The instruction set a program was written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    /// The original 35 instructions.
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: high resolution, scrolling, big font, RPL flags.
+    SuperChip,
+    /// XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bitplanes and
+    /// sample based audio.
+    XoChip,
+}
+
+impl Variant {
+    /// Return true if the SUPER-CHIP instructions are available.
+    pub fn has_superchip(&self) -> bool {
+        matches!(self, Variant::SuperChip | Variant::XoChip)
+    }
+
+    /// Return true if the XO-CHIP instructions are available.
+    pub fn has_xochip(&self) -> bool {
+        matches!(self, Variant::XoChip)
+    }
+
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Variant::XoChip => config::XOCHIP_MEMORY_SIZE,
+            _               => config::CHIP8_MEMORY_SIZE,
+        }
+    }
+
+    /// Instructions per frame programs for this variant usually expect.
+    pub fn default_speed(&self) -> usize {
+        match self {
+            Variant::Chip8      => 8,
+            Variant::SuperChip  => 30,
+            Variant::XoChip     => 100,
+        }
+    }
+
+    /// Number of RPL user flags Fx75/Fx85 can reach.
+    pub fn rpl_flags_num(&self) -> usize {
+        match self {
+            Variant::XoChip => config::XOCHIP_RPL_FLAGS_NUM,
+            _               => config::SCHIP_RPL_FLAGS_NUM,
+        }
+    }
+
+    /// Guess the variant from the usual ROM file extensions (.ch8, .sc8, .xo8).
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let extension = std::path::Path::new(filename).extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "ch8" => Some(Variant::Chip8),
+            "sc8" => Some(Variant::SuperChip),
+            "xo8" => Some(Variant::XoChip),
+            _     => None,
+        }
+    }
+
+    /// Guess the variant from the opcodes in a ROM: any XO-CHIP only
+    /// opcode makes it XO-CHIP, any SUPER-CHIP one SUPER-CHIP. Data mixed
+    /// into the code can fool it.
+    pub fn guess(rom: &[u8]) -> Self {
+        let mut variant = Variant::Chip8;
+        for word in rom.chunks_exact(2).map(|word| (word[0] as u16) << 8 | word[1] as u16) {
+            match (word >> 12, word & 0xFF) {
+                (0x0, 0xD0..=0xDF) if word & 0x0F00 == 0 => return Variant::XoChip,
+                (0x5, _) if matches!(word & 0xF, 0x2 | 0x3) => return Variant::XoChip,
+                (0xF, 0x00 | 0x02) if word & 0x0F00 == 0 => return Variant::XoChip,
+                (0xF, 0x01 | 0x3A) => return Variant::XoChip,
+                (0x0, 0xC0..=0xCF | 0xFB..=0xFF) if word & 0x0F00 == 0 => variant = Variant::SuperChip,
+                (0xF, 0x30 | 0x75 | 0x85) => variant = Variant::SuperChip,
+                _ => (),
+            }
+        }
+        variant
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Variant::Chip8      => write!(f, "chip8"),
+            Variant::SuperChip  => write!(f, "schip"),
+            Variant::XoChip     => write!(f, "xochip"),
+        }
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8"                  => Ok(Variant::Chip8),
+            "schip" | "superchip" | "super-chip" => Ok(Variant::SuperChip),
+            "xochip" | "xo-chip"                => Ok(Variant::XoChip),
+            _ => Err(format!("unknown variant: {}", s)),
+        }
+    }
+}
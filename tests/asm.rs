@@ -0,0 +1,165 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/asm.rs file
+//
+
+use chip8::asm::{assemble, assemble_file, AsmError};
+use chip8::disasm::{disassemble, disassemble_program, to_source};
+use chip8::rng::{Rng, RngKind};
+use chip8::Variant;
+
+use std::path::PathBuf;
+
+const VARIANTS: [Variant; 3] = [Variant::Chip8, Variant::SuperChip, Variant::XoChip];
+
+/// ROMs picked to trip up the round trip, plus random ones.
+fn corpus() -> Vec<(String, Vec<u8>)> {
+    let mut roms = vec![
+        // XO-CHIP long load, then a skip over a long load
+        ("long load".to_string(), vec![0xF0, 0x00, 0x02, 0x0A, 0x30, 0x01, 0xF0, 0x00, 0x12, 0x34, 0x12, 0x00]),
+        // F000 right at the end with its word cut off
+        ("cut off long load".to_string(), vec![0x60, 0x01, 0xF0, 0x00, 0x02]),
+        ("odd length".to_string(), vec![0x00, 0xE0, 0x12, 0x00, 0xAB]),
+        ("single byte".to_string(), vec![0xFF]),
+        // sprite data after the code, loaded into I, then jumped over data
+        ("data mixed into code".to_string(), vec![
+            0xA2, 0x0A, 0xD0, 0x15, 0x22, 0x0F, 0x12, 0x04, 0xFF, 0xFF,
+            0xF0, 0x90, 0x90, 0x90, 0xF0, 0x00, 0xEE, 0x5A, 0x5B, 0x00,
+        ]),
+        // opcodes only some variants know, and words no variant knows
+        ("unknown words".to_string(), vec![0x00, 0xFB, 0x50, 0x12, 0x80, 0x0F, 0xE0, 0x00, 0xF0, 0x01, 0xF2, 0x02, 0x00, 0xFD]),
+    ];
+    for seed in 0..100 {
+        let mut rng = Rng::new(RngKind::Xorshift, seed);
+        let len = 1 + rng.next_byte(&[]) as usize;
+        roms.push((format!("random {}", seed), (0..len).map(|_| rng.next_byte(&[])).collect()));
+    }
+    roms
+}
+
+#[test]
+fn disassembly_assembles_to_the_same_bytes() {
+    for (name, rom) in corpus() {
+        for variant in VARIANTS {
+            for (mode, lines) in [("linear", disassemble(&rom, 0x200, variant)), ("flow", disassemble_program(&rom, 0x200, variant))] {
+                let source = to_source(&lines);
+                let assembled = assemble(&source).unwrap_or_else(|error| panic!("{} on {} ({}): {}\n{}", name, variant, mode, error, source));
+                assert_eq!(assembled, rom, "{} on {} ({}):\n{}", name, variant, mode, source);
+            }
+        }
+    }
+}
+
+fn error(source: &str) -> AsmError {
+    match assemble(source) {
+        Ok(rom)     => panic!("{:?} assembled to {:02X?}", source, rom),
+        Err(error)  => error,
+    }
+}
+
+#[test]
+fn errors_name_the_line() {
+    let cases = [
+        ("CLS\nFROB V0, V1\n",                  2,  "invalid instruction"),
+        ("CLS\n\nJP nowhere\n",                 3,  "unknown label or constant: nowhere"),
+        ("start:\n  CLS\nstart:\n  RET\n",      3,  "start defined twice"),
+        ("N = 1\nN EQU 2\n",                    2,  "N defined twice"),
+        ("LD V0, 0x100\n",                      1,  "doesn't fit"),
+        ("CLS\nDRW V0, V1, 16\n",               2,  "doesn't fit"),
+        ("JP 0x1000\n",                         1,  "doesn't fit"),
+        ("DB 1, 2\nDB 300\n",                   2,  "doesn't fit"),
+        ("LD V0, 12z\n",                        1,  "invalid number"),
+        ("CLS\ninclude \"other.asm\"\n",        2,  "only possible when assembling a file"),
+    ];
+    for (source, line, message) in cases {
+        let error = error(source);
+        assert_eq!(error.line, line, "{:?}: {}", source, error);
+        assert_eq!(error.file, None);
+        assert!(error.message.contains(message), "{:?}: {}", source, error);
+    }
+}
+
+/// A fresh directory for include tests.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chip8-asm-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn includes() {
+    let dir = scratch_dir("include");
+    std::fs::write(dir.join("main.asm"), "CALL draw\nJP 0x200\ninclude \"lib/draw.asm\"\n").unwrap();
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(dir.join("lib/draw.asm"), "draw:\n  include \"sprite.asm\"\n  RET\n").unwrap();
+    std::fs::write(dir.join("lib/sprite.asm"), "LD I, 0x300\nDRW V0, V1, 5\n").unwrap();
+    let rom = assemble_file(&dir.join("main.asm")).unwrap();
+    assert_eq!(rom, [0x22, 0x04, 0x12, 0x00, 0xA3, 0x00, 0xD0, 0x15, 0x00, 0xEE]);
+
+    // errors point into the included file
+    std::fs::write(dir.join("lib/sprite.asm"), "LD I, 0x300\nDRW V0, V1, 99\n").unwrap();
+    let error = assemble_file(&dir.join("main.asm")).unwrap_err();
+    assert_eq!(error.line, 2);
+    assert!(error.file.as_deref().is_some_and(|file| file.ends_with("sprite.asm")), "{}", error);
+
+    std::fs::write(dir.join("lib/draw.asm"), "draw:\n  include \"missing.asm\"\n").unwrap();
+    let error = assemble_file(&dir.join("main.asm")).unwrap_err();
+    assert_eq!(error.line, 2);
+    assert!(error.file.as_deref().is_some_and(|file| file.ends_with("draw.asm")), "{}", error);
+    assert!(error.message.contains("missing.asm"), "{}", error);
+}
+
+#[test]
+fn include_cycles_hit_the_depth_limit() {
+    let dir = scratch_dir("cycle");
+    std::fs::write(dir.join("a.asm"), "CLS\ninclude \"b.asm\"\n").unwrap();
+    std::fs::write(dir.join("b.asm"), "; back again\ninclude \"a.asm\"\n").unwrap();
+    let error = assemble_file(&dir.join("a.asm")).unwrap_err();
+    assert!(error.message.contains("nested more than"), "{}", error);
+    assert!(error.file.is_some());
+    assert_eq!(error.line, 2);
+}
+
+#[test]
+fn labels_constants_and_data() {
+    let source = "
+        SPEED = 3
+        HEIGHT EQU SPEED + 2        ; constants may use other constants
+        TOP = sprite - 1            ; and labels further down
+        start:
+            LD V0, SPEED
+            ADD V0, -1
+            LD I, sprite
+            DRW V0, V1, HEIGHT
+            JP start + 2
+        loop: SE V0, TOP - 0x200
+            JP loop
+        sprite: DB 0b11110000, %1001, $0F, #10, 255
+        words:  DW sprite, 0xBEEF
+        LD I, LONG words
+    ";
+    let rom = assemble(source).unwrap();
+    assert_eq!(rom, [
+        0x60, 0x03,             // LD V0, SPEED
+        0x70, 0xFF,             // ADD V0, -1
+        0xA2, 0x0E,             // LD I, sprite
+        0xD0, 0x15,             // DRW V0, V1, HEIGHT
+        0x12, 0x02,             // JP start + 2
+        0x30, 0x0D,             // SE V0, TOP - 0x200
+        0x12, 0x0A,             // JP loop
+        0xF0, 0x09, 0x0F, 0x10, 0xFF,
+        0x02, 0x0E, 0xBE, 0xEF,
+        0xF0, 0x00, 0x02, 0x13, // LD I, LONG words
+    ]);
+}
+
+#[test]
+fn constants_defined_in_terms_of_themselves() {
+    let error = error("A = B\nB = A + 1\nLD V0, A\n");
+    assert_eq!(error.line, 3);
+    assert!(error.message.contains("in terms of itself"), "{}", error);
+}
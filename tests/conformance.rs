@@ -0,0 +1,82 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/conformance.rs file
+//
+
+use chip8::harness::TestMachine;
+use chip8::Variant;
+
+use std::path::Path;
+
+/// Our own test programs in tests/programs, run on every variant. They
+/// draw a tick for every passing check and a cross for every failing one.
+const PROGRAMS: &[&str] = &["flags", "opcodes"];
+
+/// Community test ROMs. They aren't in the repository, CI downloads the
+/// Timendus suite into tests/roms before running the tests (see
+/// .github/workflows/rust.yml), others have to be copied there by hand.
+/// ROMs that aren't there are skipped.
+struct CommunityRom {
+    file:       &'static str,
+    variant:    Variant,
+    /// Written to 0x1FF before starting, where the Timendus tests look
+    /// for a menu choice so they don't wait for one.
+    select:     Option<u8>,
+    script:     &'static str,
+}
+
+const COMMUNITY_ROMS: &[CommunityRom] = &[
+    // https://github.com/Timendus/chip8-test-suite
+    CommunityRom { file: "1-chip8-logo.ch8",    variant: Variant::Chip8,    select: None,       script: "wait 60" },
+    CommunityRom { file: "2-ibm-logo.ch8",      variant: Variant::Chip8,    select: None,       script: "wait 60" },
+    CommunityRom { file: "3-corax+.ch8",        variant: Variant::Chip8,    select: None,       script: "wait 60" },
+    CommunityRom { file: "4-flags.ch8",         variant: Variant::Chip8,    select: None,       script: "wait 120" },
+    // 1 picks the CHIP-8 quirks
+    CommunityRom { file: "5-quirks.ch8",        variant: Variant::Chip8,    select: Some(1),    script: "wait 600" },
+    // 3 picks the Fx0A test, which wants a key pressed and released
+    CommunityRom { file: "6-keypad.ch8",        variant: Variant::Chip8,    select: Some(3),    script: "wait 30; tap 5 5; wait 30" },
+    // BestCoder's opcode test
+    CommunityRom { file: "BC_test.ch8",         variant: Variant::Chip8,    select: None,       script: "wait 60" },
+];
+
+fn golden(name: &str) -> String {
+    format!("tests/golden/{}", name)
+}
+
+#[test]
+fn programs_pass_on_every_variant() {
+    for program in PROGRAMS {
+        for variant in [Variant::Chip8, Variant::SuperChip, Variant::XoChip] {
+            let mut machine = TestMachine::from_file(format!("tests/programs/{}.8o", program), Some(variant)).unwrap();
+            machine.run_frames(300).unwrap();
+            if let Err(error) = machine.check_golden(golden(&format!("{}.txt", program))) {
+                panic!("{} on {}: {}", program, variant, error);
+            }
+        }
+    }
+}
+
+#[test]
+fn community_roms() {
+    let mut skipped = Vec::new();
+    for rom in COMMUNITY_ROMS {
+        let path = Path::new("tests/roms").join(rom.file);
+        if !path.exists() {
+            skipped.push(rom.file);
+            continue;
+        }
+        let mut machine = TestMachine::from_file(&path, Some(rom.variant)).unwrap();
+        if let Some(select) = rom.select {
+            machine.cpu.chip8.memory.ram[0x1FF] = select;
+        }
+        machine.run_script(rom.script).unwrap_or_else(|error| panic!("{}: {}", rom.file, error));
+        let name = Path::new(rom.file).with_extension("png");
+        machine.assert_golden(golden(&name.display().to_string()));
+    }
+    if !skipped.is_empty() {
+        eprintln!("skipped, not in tests/roms: {}", skipped.join(", "));
+    }
+}
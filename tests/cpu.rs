@@ -0,0 +1,88 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/cpu.rs file
+//
+
+use chip8::harness::TestMachine;
+use chip8::octo;
+use chip8::cpu::{Cpu, Step, StepHook};
+use chip8::{Chip8Error, Variant};
+
+/// Run `body` until it spins at the end.
+fn run(body: &str) -> TestMachine {
+    let source = format!(": main\n{}\nloop again\n", body);
+    let mut machine = TestMachine::new(&octo::compile(&source, Variant::Chip8).unwrap(), Variant::Chip8).unwrap();
+    machine.run_frames(2).unwrap();
+    machine
+}
+
+#[test]
+fn arithmetic_sets_vf_after_the_result() {
+    let cases = [
+        // 8xy4 with x = F, VF ends up holding the carry
+        ("vf := 0x10 v1 := 0x20 vf += v1",  0x0),
+        ("vf := 0xF0 v1 := 0x20 vf += v1",  0x1),
+        ("vf := 0x30 v1 := 0x20 vf -= v1",  0x1),
+        ("vf := 0x10 v1 := 0x20 vf -= v1",  0x0),
+        ("vf := 0x10 v1 := 0x20 vf =- v1",  0x1),
+        ("vf := 0x30 v1 := 0x20 vf =- v1",  0x0),
+        ("vf := 0x02 vf >>= vf",            0x0),
+        ("vf := 0x81 vf <<= vf",            0x1),
+    ];
+    for (body, flag) in cases {
+        assert_eq!(run(body).v(0xF), flag, "{}", body);
+    }
+}
+
+#[test]
+fn subtraction_wraps_and_equal_values_dont_borrow() {
+    let machine = run("v1 := 3 v2 := 5 v1 -= v2 v3 := 4 v4 := 4 v3 -= v4 v5 := 5 v6 := 3 v5 =- v6 v7 := 4 v8 := 4 v7 =- v8");
+    // both subtractions borrowed, the last of them sets VF
+    assert_eq!((machine.v(1), machine.v(3), machine.v(5), machine.v(7)), (0xFE, 0x00, 0xFE, 0x00));
+    assert_eq!(machine.v(0xF), 1);
+    let machine = run("v1 := 3 v2 := 3 v1 -= v2");
+    assert_eq!((machine.v(1), machine.v(0xF)), (0x00, 1));
+}
+
+#[test]
+fn fx55_stores_v0_up_to_and_including_vx() {
+    let machine = run("v0 := 1 v1 := 2 v2 := 3 v3 := 4 i := 0x300 save v2");
+    assert_eq!(machine.memory(0x300, 4), [1, 2, 3, 0]);
+}
+
+/// Writes down the address of every instruction and ends the frame after
+/// `limit` of them.
+struct Recorder {
+    limit:  usize,
+    pcs:    Vec<u16>,
+}
+
+impl StepHook for Recorder {
+    fn before_step(&mut self, _cpu: &mut Cpu) -> bool {
+        self.pcs.len() < self.limit
+    }
+
+    fn after_step(&mut self, _cpu: &mut Cpu, pc: u16, result: &Result<Step, Chip8Error>) {
+        assert!(matches!(result, Ok(Step::Executed(_))));
+        self.pcs.push(pc);
+    }
+}
+
+#[test]
+fn step_hooks_see_every_instruction() {
+    let rom = octo::compile(": main\nv0 := 1\nloop v0 += 1 again\n", Variant::Chip8).unwrap();
+    let mut machine = TestMachine::new(&rom, Variant::Chip8).unwrap();
+    machine.cpu.chip8.registers.dt = 5;
+    let mut hook = Recorder { limit: 4, pcs: Vec::new() };
+    assert_eq!(machine.cpu.run_frame_with(10, &mut hook).unwrap(), 4);
+    assert_eq!(hook.pcs, [0x200, 0x202, 0x204, 0x202]);
+    // ended early, before the timers ticked
+    assert_eq!(machine.cpu.chip8.registers.dt, 5);
+
+    hook.limit = 100;
+    assert_eq!(machine.cpu.run_frame_with(10, &mut hook).unwrap(), 10);
+    assert_eq!(machine.cpu.chip8.registers.dt, 4);
+}
@@ -0,0 +1,321 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/debugger.rs file
+//
+
+use chip8::asm::assemble;
+use chip8::debugger::{DebugFrontend, Debugger};
+use chip8::harness::TestMachine;
+use chip8::{Cpu, Variant};
+
+use std::cell::RefCell;
+use std::io::{Cursor, Write};
+use std::rc::Rc;
+
+/// 200 sets V0 and V1, then 204 loops calling the subroutine at 20A, which
+/// points I at the data and counts V1 up, and counting V0 up itself.
+const PROGRAM: &str = "
+        LD V0, 1
+        LD V1, 2
+    loop:
+        CALL sub
+        ADD V0, 1
+        JP loop
+    sub:
+        LD I, data
+        ADD V1, 1
+        RET
+    data:
+        DB 0xAA, 0xBB, 0xCC
+";
+
+/// Debugger output that stays readable after the debugger took it.
+#[derive(Clone, Default)]
+struct Transcript(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Transcript {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `source` under the debugger, typing in `commands` one per line, until
+/// it quits or the commands run out. Return the machine and everything
+/// printed.
+fn debug(source: &str, variant: Variant, commands: &str) -> (Cpu, String) {
+    let mut cpu = TestMachine::new(&assemble(source).unwrap(), variant).unwrap().cpu;
+    let transcript = Transcript::default();
+    let mut debugger = Debugger::with_io(Box::new(Cursor::new(commands.trim_start().to_string())), Box::new(transcript.clone()));
+    for _ in 0..100 {
+        if debugger.run_frame(&mut cpu, 10).is_err() || debugger.quit_requested() {
+            break;
+        }
+    }
+    let output = String::from_utf8(transcript.0.borrow().clone()).unwrap();
+    (cpu, output)
+}
+
+/// Check the lines show up in the output in this order.
+fn assert_in_order(output: &str, expected: &[&str]) {
+    let mut rest = output;
+    for line in expected {
+        match rest.find(line) {
+            Some(pos)   => rest = &rest[pos + line.len()..],
+            None        => panic!("{:?} missing or out of order in:\n{}", line, output),
+        }
+    }
+}
+
+#[test]
+fn starts_paused_before_the_first_instruction() {
+    let (cpu, output) = debug(PROGRAM, Variant::Chip8, "quit\n");
+    assert!(output.starts_with("=> 200: 60 01"), "{}", output);
+    assert_eq!(cpu.chip8.registers.pc, 0x200);
+}
+
+#[test]
+fn breakpoints() {
+    let (cpu, output) = debug(PROGRAM, Variant::Chip8, "
+        break 20C
+        b 0x208
+        breakpoints
+        continue
+        regs
+        c
+        c
+        regs
+        delete 20C
+        breakpoints
+        delete 20C
+        d
+        breakpoints
+        quit
+    ");
+    assert_in_order(&output, &[
+        "breakpoint set at 20C",
+        "breakpoint set at 208",
+        "208 20C",
+        // first time through the subroutine
+        "breakpoint at 20C", "=> 20C: 71 01", "ADD V1, 0x01",
+        "V0 01  V1 02", "I  0210  PC 020C  SP 1", "stack: 206",
+        "breakpoint at 208", "=> 208: 12 04", "JP 0x204",
+        // second time through, both went up
+        "breakpoint at 20C", "V0 02  V1 03", "stack: 206",
+        "208",
+        "no breakpoint at 20C",
+        "no breakpoints",
+    ]);
+    assert_eq!(cpu.chip8.registers.pc, 0x20C);
+}
+
+#[test]
+fn step_next_and_finish() {
+    let (cpu, output) = debug(PROGRAM, Variant::Chip8, "
+        step
+        s 2
+        step
+
+        finish
+        regs
+        next
+        next
+        next
+        regs
+        finish
+        quit
+    ");
+    assert_in_order(&output, &[
+        "=> 200:",
+        "=> 202:",
+        // step 2 goes into the subroutine
+        "=> 20A:",
+        "=> 20C:",
+        // an empty line repeats the step
+        "=> 20E:",
+        "=> 206:", "V0 01  V1 03", "SP 0",
+        "=> 208:",
+        "=> 204:",
+        // next runs the whole call
+        "=> 206:", "V0 02  V1 04", "SP 0", "stack: empty",
+        "not inside a subroutine",
+    ]);
+    assert_eq!(cpu.chip8.registers.pc, 0x206);
+}
+
+#[test]
+fn next_steps_over_calls() {
+    let (cpu, output) = debug(PROGRAM, Variant::Chip8, "s 2\nn\nregs\nquit\n");
+    assert_in_order(&output, &["=> 204: 22 0A", "CALL 0x20A", "=> 206:", "V0 01  V1 03", "SP 0"]);
+    assert_eq!(cpu.chip8.registers.i, 0x210);
+}
+
+#[test]
+fn memory_and_registers() {
+    let (cpu, output) = debug(PROGRAM, Variant::Chip8, "
+        mem 210 3
+        x 0x20E 4
+        dis 204 2
+        l
+        set v5 7f
+        set I 300
+        set dt 20
+        set v5 100
+        set vg 1
+        poke 300 12 34
+        poke FFF 1 2
+        mem 300 2
+        regs
+        frob
+        mem
+        quit
+    ");
+    assert_in_order(&output, &[
+        "210: AA BB CC",
+        "20E: 00 EE AA BB",
+        "   204: 22 0A", "CALL 0x20A", "   206: 70 01", "ADD V0, 0x01",
+        "=> 200: 60 01",
+        "100 doesn't fit into a byte",
+        "unknown register: vg",
+        "1000 is outside of memory",
+        "300: 12 34",
+        "V4 00  V5 7F", "I  0300  PC 0200  SP 0  DT 20",
+        "unknown command: frob, try help",
+        "mem needs more arguments, see help",
+    ]);
+    assert_eq!(cpu.chip8.registers.v[5], 0x7F);
+    // poke wrote what fit before running out of memory
+    assert_eq!(cpu.chip8.memory.ram[0xFFF], 0x01);
+}
+
+#[test]
+fn errors_stop_at_the_prompt() {
+    let (cpu, output) = debug("RET", Variant::Chip8, "c\nregs\nquit\n");
+    assert_in_order(&output, &["error at 200: return with an empty stack", "=> 200: 00 EE", "PC 0200"]);
+    assert_eq!(cpu.chip8.registers.pc, 0x200);
+}
+
+/// Writes 0x7B as BCD to 300-302, loads 301-302 into V0-V1 and stores them
+/// at 304-305, then draws the two bytes at 301 as a sprite.
+const MEMORY_PROGRAM: &str = "
+        LD I, 0x300
+        LD V0, 0x7B
+        LD B, V0
+        LD I, 0x301
+        LD V1, [I]
+        LD I, 0x304
+        LD [I], V1
+        LD I, 0x301
+        DRW V0, V1, 2
+    end:
+        JP end
+";
+
+#[test]
+fn instructions_log_their_data_accesses() {
+    let mut machine = TestMachine::new(&assemble(MEMORY_PROGRAM).unwrap(), Variant::Chip8).unwrap();
+    let mut accesses = Vec::new();
+    while machine.pc() != 0x212 {
+        let pc = machine.pc();
+        machine.cpu.chip8.access_log = Some(Vec::new());
+        machine.cpu.step().unwrap();
+        let log = machine.cpu.chip8.access_log.take().unwrap();
+        accesses.extend(log.iter().map(|access| (pc, access.addr, access.write)));
+    }
+    // fetching the instructions themselves is no data access
+    assert_eq!(accesses, [
+        (0x204, 0x300, true), (0x204, 0x301, true), (0x204, 0x302, true),
+        (0x208, 0x301, false), (0x208, 0x302, false),
+        (0x20C, 0x304, true), (0x20C, 0x305, true),
+        (0x210, 0x301, false), (0x210, 0x302, false),
+    ]);
+    assert_eq!(machine.memory(0x300, 6), [1, 2, 3, 0, 2, 3]);
+}
+
+#[test]
+fn memory_watchpoints() {
+    let (cpu, output) = debug(MEMORY_PROGRAM, Variant::Chip8, "
+        rwatch 302
+        watch 305
+        awatch 300-301
+        watch 310-30F
+        watchpoints
+        c
+        c
+        c
+        c
+        quit
+    ");
+    assert_in_order(&output, &[
+        "watchpoint 1: read 302-302",
+        "watchpoint 2: write 305-305",
+        "watchpoint 3: access 300-301",
+        "empty range: 310-30f",
+        "1: read 302-302\n2: write 305-305\n3: access 300-301\n",
+        // stopped after the instruction that hit it
+        "watchpoint 3: write to 300 by 204", "=> 206:",
+        "=> 20A:",
+        "=> 20E:",
+        "=> 212:",
+    ]);
+    let hits: Vec<&str> = output.lines().filter_map(|line| line.split("(chip8) ").last()).filter(|line| line.contains(" by ")).collect();
+    assert_eq!(hits, [
+        "watchpoint 3: write to 300 by 204",
+        "watchpoint 1: read of 302 by 208",
+        "watchpoint 3: read of 301 by 208",
+        "watchpoint 2: write to 305 by 20C",
+        "watchpoint 1: read of 302 by 210",
+        "watchpoint 3: read of 301 by 210",
+    ]);
+    assert_eq!(cpu.chip8.registers.pc, 0x212);
+}
+
+#[test]
+fn register_and_timer_watchpoints() {
+    let (cpu, output) = debug("
+        LD V3, 5
+        LD V3, 5
+        LD I, 0x300
+        ADD V3, 1
+        LD DT, V3
+        LD ST, V3
+        LD V3, 0
+        LD DT, V3
+    end:
+        JP end
+    ", Variant::Chip8, "
+        watch v3 0
+        watch i
+        watch dt
+        watch st 7
+        watch v3
+        c
+        c
+        c
+        c
+        unwatch 5
+        unwatch 6
+        c
+        unwatch
+        watchpoints
+        quit
+    ");
+    let hits: Vec<&str> = output.lines().filter_map(|line| line.split("(chip8) ").last()).filter(|line| line.contains(" by ")).collect();
+    // LD V3, 5 twice only changes it once, ST is set to 6 and not 7
+    assert_eq!(hits, [
+        "watchpoint 5: V3 = 05, was 00 by 200",
+        "watchpoint 2: I = 0300, was 0000 by 204",
+        "watchpoint 5: V3 = 06, was 05 by 206",
+        "watchpoint 3: DT set to 06 by 208",
+        "watchpoint 1: V3 = 00, was 06 by 20C",
+    ]);
+    assert_in_order(&output, &["no watchpoint 6", "no watchpoints"]);
+    assert_eq!(cpu.chip8.registers.pc, 0x20E);
+}
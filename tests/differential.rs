@@ -0,0 +1,92 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/differential.rs file
+//
+
+use chip8::harness::lockstep;
+use chip8::rng::{Rng, RngKind};
+use chip8::{Quirks, Variant};
+
+type Preset = (&'static str, Variant, fn() -> Quirks);
+
+const PRESETS: [Preset; 5] = [
+    ("COSMAC VIP",      Variant::Chip8,     Quirks::cosmac_vip),
+    ("CHIP-48",         Variant::Chip8,     Quirks::chip48),
+    ("SUPER-CHIP 1.0",  Variant::SuperChip, Quirks::superchip_1_0),
+    ("SUPER-CHIP 1.1",  Variant::SuperChip, Quirks::superchip_1_1),
+    ("XO-CHIP",         Variant::XoChip,    Quirks::xo_chip),
+];
+
+/// Programs per preset and steps per program of the default run, kept
+/// short. The ignored `_long` tests run far more, deeper fuzzing is left
+/// to the cargo fuzz targets.
+const PROGRAMS: u64 = 6;
+const STEPS: usize = 500;
+const LONG_PROGRAMS: u64 = 200;
+const LONG_STEPS: usize = 2000;
+
+/// A program of random opcodes. Jumps, calls and skips land on the other
+/// random opcodes, which makes for plenty of branching.
+fn random_program(seed: u64, len: usize) -> Vec<u8> {
+    let mut rng = Rng::new(RngKind::Xorshift, seed);
+    (0..len).map(|_| rng.next_byte(&[])).collect()
+}
+
+/// A random program made of the opcodes that move data around, no jumps
+/// out of it, so the runs are long. Skips still branch.
+fn random_straight_program(seed: u64, len: usize) -> Vec<u8> {
+    let mut rng = Rng::new(RngKind::Xorshift, seed);
+    let mut rom = Vec::with_capacity(len);
+    while rom.len() < len {
+        let high = rng.next_byte(&[]);
+        let low = rng.next_byte(&[]);
+        let high = match high >> 4 {
+            // turn jumps and calls into loads so the program keeps going
+            0x1 | 0x2 | 0xB => 0x60 | (high & 0xF),
+            // keep I below 0x300 so the data instructions hit the program
+            0xA => 0xA0 | (high & 0x1),
+            _   => high,
+        };
+        rom.push(high);
+        rom.push(low);
+    }
+    rom
+}
+
+fn run(generate: fn(u64, usize) -> Vec<u8>, programs: u64, steps: usize) {
+    let mut failures = Vec::new();
+    for (preset, variant, quirks) in PRESETS {
+        for seed in 0..programs {
+            let rom = generate(seed, 0x400);
+            if let Err(divergence) = lockstep(&rom, variant, quirks(), steps, seed) {
+                failures.push(format!("{}, program {}: {}", preset, seed, divergence));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{} diverged, the first:\n{}", failures.len(), failures[0]);
+}
+
+#[test]
+fn random_programs() {
+    run(random_program, PROGRAMS, STEPS);
+}
+
+#[test]
+fn random_straight_programs() {
+    run(random_straight_program, PROGRAMS, STEPS);
+}
+
+#[test]
+#[ignore = "takes minutes, run with cargo test -- --ignored"]
+fn random_programs_long() {
+    run(random_program, LONG_PROGRAMS, LONG_STEPS);
+}
+
+#[test]
+#[ignore = "takes minutes, run with cargo test -- --ignored"]
+fn random_straight_programs_long() {
+    run(random_straight_program, LONG_PROGRAMS, LONG_STEPS);
+}
@@ -0,0 +1,147 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/disasm.rs file
+//
+
+use chip8::disasm::{disassemble, disassemble_program, DisasmLine};
+use chip8::Variant;
+
+/// Draws the sprite at 212 and calls 20C, then either loops back to 200
+/// or spins at 20A. 210 is no instruction on any variant and 20C is only
+/// one on SUPER-CHIP.
+const ROM: [u8; 23] = [
+    0xA2, 0x12, 0xD0, 0x15, 0x22, 0x0C, 0x30, 0x01, 0x12, 0x00, 0x12, 0x0A,
+    0x00, 0xFF, 0x00, 0xEE, 0x51, 0x21,
+    0xF0, 0x90, 0xF0, 0x90, 0xF0,
+];
+
+/// One line per label and per disassembled line, comments after a `;`.
+fn listing(lines: &[DisasmLine]) -> Vec<String> {
+    let mut listing = Vec::new();
+    for line in lines {
+        if let Some(label) = &line.label {
+            listing.push(format!("{}:", label));
+        }
+        match &line.comment {
+            Some(comment)   => listing.push(format!("{:03X} {} ; {}", line.addr, line.text, comment)),
+            None            => listing.push(format!("{:03X} {}", line.addr, line.text)),
+        }
+    }
+    listing
+}
+
+fn assert_listing(lines: &[DisasmLine], expected: &str) {
+    let expected: Vec<&str> = expected.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    assert_eq!(listing(lines), expected, "\n{}", listing(lines).join("\n"));
+}
+
+#[test]
+fn code_flow_places_labels() {
+    assert_listing(&disassemble_program(&ROM, 0x200, Variant::Chip8), "
+        L200:
+        200 LD I, sprite_212
+        202 DRW V0, V1, 5
+        204 CALL sub_20C
+        206 SE V0, 0x01
+        208 JP L200
+        L20A:
+        20A JP L20A
+        sub_20C:
+        20C SYS 0x0FF
+        20E RET
+        210 DB 0x51, 0x21
+        sprite_212:
+        212 DB 0b11110000 ; ####....
+        213 DB 0b10010000 ; #..#....
+        214 DB 0b11110000 ; ####....
+        215 DB 0b10010000 ; #..#....
+        216 DB 0b11110000 ; ####....
+    ");
+}
+
+#[test]
+fn superchip_opcodes_only_decode_on_superchip() {
+    let lines = disassemble_program(&ROM, 0x200, Variant::SuperChip);
+    assert_eq!(listing(&lines)[8..10], ["sub_20C:", "20C HIGH"]);
+}
+
+#[test]
+fn linear_disassembly_falls_back_to_data() {
+    // without following the code the sprite comes out as instructions
+    assert_listing(&disassemble(&ROM, 0x200, Variant::Chip8), "
+        200 LD I, 0x212
+        202 DRW V0, V1, 5
+        204 CALL 0x20C
+        206 SE V0, 0x01
+        208 JP 0x200
+        20A JP 0x20A
+        20C SYS 0x0FF
+        20E RET
+        210 DW 0x5121
+        212 DW 0xF090
+        214 DW 0xF090
+        216 DB 0xF0
+    ");
+}
+
+#[test]
+fn the_strongest_reason_names_a_label() {
+    // called, jumped to and loaded into I
+    let rom = [0x22, 0x06, 0xA2, 0x06, 0x12, 0x06, 0x00, 0xEE];
+    assert_listing(&disassemble_program(&rom, 0x200, Variant::Chip8), "
+        200 CALL sub_206
+        202 LD I, sub_206
+        204 JP sub_206
+        sub_206:
+        206 RET
+    ");
+    // jumped to and loaded into I
+    let rom = [0xA2, 0x02, 0x12, 0x02];
+    assert_listing(&disassemble_program(&rom, 0x200, Variant::Chip8), "
+        200 LD I, L202
+        L202:
+        202 JP L202
+    ");
+    // targets inside an instruction or outside of the ROM get no label
+    let rom = [0x12, 0x01, 0x22, 0x00, 0xA3, 0x00];
+    assert_listing(&disassemble_program(&rom, 0x200, Variant::Chip8), "
+        200 JP 0x201
+        202 DB 0x22, 0x00, 0xA3, 0x00
+    ");
+}
+
+#[test]
+fn long_loads_on_xochip() {
+    // a long load of the sprite, a skip over a long load, then a spin with
+    // a CLS nothing reaches
+    let rom = [
+        0xF0, 0x00, 0x02, 0x0E, 0x30, 0x00, 0xF0, 0x00, 0x03, 0x00, 0x12, 0x0A, 0x00, 0xE0,
+        0x3C, 0x42,
+    ];
+    assert_listing(&disassemble_program(&rom, 0x200, Variant::XoChip), "
+        200 LD I, LONG sprite_20E
+        204 SE V0, 0x00
+        206 LD I, LONG 0x0300
+        L20A:
+        20A JP L20A
+        20C DB 0x00, 0xE0
+        sprite_20E:
+        20E DB 0b00111100 ; ..####..
+        20F DB 0b01000010 ; .#....#.
+    ");
+    // everywhere else F000 is no instruction, and nothing after it is reached
+    assert_listing(&disassemble_program(&rom, 0x200, Variant::SuperChip), "
+        200 DB 0xF0, 0x00, 0x02, 0x0E, 0x30, 0x00, 0xF0, 0x00
+        208 DB 0x03, 0x00, 0x12, 0x0A, 0x00, 0xE0, 0x3C, 0x42
+    ");
+    // a long load cut off by the end of the ROM is data
+    let rom = [0x12, 0x02, 0xF0, 0x00, 0x02];
+    assert_listing(&disassemble_program(&rom, 0x200, Variant::XoChip), "
+        200 JP L202
+        L202:
+        202 DB 0xF0, 0x00, 0x02
+    ");
+}
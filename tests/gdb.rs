@@ -0,0 +1,264 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/gdb.rs file
+//
+
+use chip8::asm::assemble;
+use chip8::debugger::DebugFrontend;
+use chip8::gdb::GdbStub;
+use chip8::harness::TestMachine;
+use chip8::{Chip8Error, Variant};
+
+use std::cell::RefCell;
+use std::io::{Cursor, Write};
+use std::net::TcpStream;
+use std::rc::Rc;
+
+/// Counts V0 up in a loop at 204-206.
+const PROGRAM: &str = "
+        LD V0, 1
+        LD I, 0x300
+    loop:
+        ADD V0, 1
+        JP loop
+";
+
+/// What the stub sent, readable after the stub took it.
+#[derive(Clone, Default)]
+struct Wire(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Wire {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn packet(data: &str) -> String {
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    format!("${}#{:02x}", data, checksum)
+}
+
+/// Split what the stub sent into acks and packet data, checking the
+/// checksum of every packet.
+fn split_replies(bytes: &[u8]) -> Vec<String> {
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    let mut replies = Vec::new();
+    let mut rest = text.as_str();
+    while let Some(first) = rest.chars().next() {
+        match first {
+            '+' | '-' => {
+                replies.push(first.to_string());
+                rest = &rest[1..];
+            },
+            '$' => {
+                let end = rest.find('#').unwrap();
+                let data = &rest[1..end];
+                assert_eq!(packet(data), rest[..end + 3], "bad checksum");
+                replies.push(data.to_string());
+                rest = &rest[end + 3..];
+            },
+            _ => panic!("garbage in {:?}", text),
+        }
+    }
+    replies
+}
+
+/// Send `input` to a stub debugging `source`, run until the debugger is
+/// done or the machine fails, and return the machine, every ack and reply,
+/// and the error.
+fn session_raw(source: &str, variant: Variant, input: String) -> (TestMachine, Vec<String>, Option<Chip8Error>) {
+    let mut machine = TestMachine::new(&assemble(source).unwrap(), variant).unwrap();
+    let wire = Wire::default();
+    let mut stub = GdbStub::with_io(Box::new(Cursor::new(input.into_bytes())), Box::new(wire.clone()));
+    let mut error = None;
+    for _ in 0..100 {
+        if let Err(failed) = stub.run_frame(&mut machine.cpu, 10) {
+            error = Some(failed);
+            break;
+        }
+        if stub.quit_requested() || machine.stopped() {
+            break;
+        }
+    }
+    let replies = split_replies(&wire.0.borrow());
+    (machine, replies, error)
+}
+
+/// Like `session_raw` for well formed packets, every one of them acked.
+/// Return the replies without the acks.
+fn session(source: &str, variant: Variant, packets: &[&str]) -> (TestMachine, Vec<String>, Option<Chip8Error>) {
+    let (machine, replies, error) = session_raw(source, variant, packets.iter().map(|data| packet(data)).collect());
+    assert_eq!(replies.iter().filter(|reply| *reply == "+").count(), packets.len(), "{:?}", replies);
+    let replies = replies.into_iter().filter(|reply| reply != "+").collect();
+    (machine, replies, error)
+}
+
+#[test]
+fn checksums() {
+    let input = format!("+$?#00{}\x03{}{}", packet("?"), packet("p}\x10"), packet("k"));
+    let (_, replies, _) = session_raw(PROGRAM, Variant::Chip8, input);
+    // the broken packet is refused and the acks and interrupts in between
+    // skipped, escaped bytes count as they were sent
+    assert_eq!(replies, ["-", "+", "S05", "+", "00", "+"]);
+}
+
+#[test]
+fn registers() {
+    let (machine, replies, _) = session(PROGRAM, Variant::Chip8, &[
+        "?",
+        "g",
+        "p11",
+        "P0=2a",
+        "P10=3412",
+        "p0",
+        "p10",
+        "p15",
+        "P15=00",
+        "P0=2a2a",
+        "G00",
+        &format!("G{}{}", "07".repeat(16), "00030203010000"),
+        "g",
+        "k",
+    ]);
+    assert_eq!(replies, [
+        "S05".to_string(),
+        format!("{}{}", "00".repeat(16), "00000002000000"),
+        "0002".to_string(),
+        "OK".to_string(),
+        "OK".to_string(),
+        "2a".to_string(),
+        "3412".to_string(),
+        "E01".to_string(),
+        "E01".to_string(),
+        "E01".to_string(),
+        "E01".to_string(),
+        "OK".to_string(),
+        format!("{}{}", "07".repeat(16), "00030203010000"),
+    ]);
+    let registers = &machine.cpu.chip8.registers;
+    assert_eq!((registers.v[0xF], registers.i, registers.pc, registers.sp), (0x07, 0x300, 0x302, 1));
+}
+
+#[test]
+fn memory() {
+    let (machine, replies, _) = session(PROGRAM, Variant::Chip8, &[
+        "m200,4",
+        "M300,2:abcd",
+        "m300,3",
+        "mffe,4",
+        "m300",
+        "M300,2:ab",
+        "Mfff,2:abcd",
+        "k",
+    ]);
+    assert_eq!(replies, ["6001a300", "OK", "abcd00", "E01", "E01", "E01", "E01"]);
+    assert_eq!(machine.memory(0x300, 2), [0xAB, 0xCD]);
+    assert_eq!(machine.memory(0xFFF, 1), [0x00]);
+}
+
+#[test]
+fn breakpoints_continue_and_step() {
+    let (machine, replies, _) = session(PROGRAM, Variant::Chip8, &[
+        "Z0,206,2",
+        "c",
+        "p0",
+        "s",
+        "p11",
+        "c",
+        "p0",
+        "z0,206,2",
+        "Z1,206,2",
+        "Z2,300,1",
+        "s",
+        "s",
+        "p11",
+        "s200",
+        "p11",
+        "sxyz",
+        "k",
+    ]);
+    assert_eq!(replies, [
+        "OK",
+        // stopped at the breakpoint after 200-204 ran
+        "S05", "02",
+        "S05", "0402",
+        // stops at it again after going round once
+        "S05", "03",
+        "OK",
+        // no hardware breakpoints or watchpoints
+        "", "",
+        "S05", "S05", "0602",
+        // stepping from a given address
+        "S05", "0202",
+        "E01",
+    ]);
+    assert_eq!(machine.cpu.chip8.registers.i, 0x300);
+}
+
+#[test]
+fn errors_stop_the_machine() {
+    let (machine, replies, error) = session("CLS\nRET", Variant::Chip8, &["c", "?", "p11", "k"]);
+    assert_eq!(replies, ["S0b", "S0b", "0202"]);
+    assert!(matches!(error, Some(Chip8Error::StackUnderflow)), "{:?}", error);
+    assert_eq!(machine.pc(), 0x202);
+}
+
+#[test]
+fn exits_are_reported() {
+    let (machine, replies, _) = session("CLS\nEXIT", Variant::SuperChip, &["c"]);
+    assert_eq!(replies, ["W00"]);
+    assert!(machine.stopped());
+}
+
+#[test]
+fn queries() {
+    let (machine, replies, _) = session(PROGRAM, Variant::Chip8, &[
+        "qSupported:multiprocess+",
+        "qXfer:features:read:target.xml:0,10",
+        "qXfer:features:read:target.xml:10,1000",
+        "qXfer:features:read:target.xml:zz",
+        "qAttached",
+        "qC",
+        "qfThreadInfo",
+        "qsThreadInfo",
+        "Hg0",
+        "qOffsets",
+        "vMustReplyEmpty",
+        "D",
+    ]);
+    assert_eq!(replies.len(), 12);
+    assert_eq!(replies[0], "PacketSize=1000;qXfer:features:read+");
+    assert_eq!(replies[1], "m<?xml version=\"1");
+    assert!(replies[2].starts_with("l.0\"?>") && replies[2].ends_with("</target>\n"), "{}", replies[2]);
+    assert!(replies[2].contains(r#"<reg name="pc" bitsize="16" type="code_ptr"/>"#));
+    assert_eq!(replies[3..], ["E01", "1", "QC1", "m1", "l", "OK", "", "", "OK"]);
+    // detached, the machine runs on by itself
+    assert!(machine.cpu.chip8.registers.v[0] > 2);
+}
+
+#[test]
+fn listen_reports_both_addresses() {
+    let mut client = None;
+    let (mut stub, address) = GdbStub::listen(0, |listening| {
+        assert!(listening.ip().is_loopback() && listening.port() != 0, "{}", listening);
+        client = Some(std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(listening).unwrap();
+            let local = stream.local_addr().unwrap();
+            stream.write_all(packet("k").as_bytes()).unwrap();
+            local
+        }));
+    }).unwrap();
+    assert_eq!(address, client.unwrap().join().unwrap());
+
+    let mut machine = TestMachine::new(&assemble(PROGRAM).unwrap(), Variant::Chip8).unwrap();
+    stub.run_frame(&mut machine.cpu, 10).unwrap();
+    assert!(stub.quit_requested());
+}
@@ -0,0 +1,126 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/harness.rs file
+//
+
+use chip8::harness::TestMachine;
+use chip8::octo;
+use chip8::Variant;
+
+fn machine(source: &str, variant: Variant) -> TestMachine {
+    let rom = octo::compile(source, variant).unwrap();
+    TestMachine::new(&rom, variant).unwrap()
+}
+
+/// Waits for a key and draws its hex digit in the corner, over and over.
+const ECHO_KEY: &str = "
+: main
+    loop
+        v0 := key
+        clear
+        i := hex v0
+        sprite v1 v1 5
+    again
+";
+
+#[test]
+fn draws_the_pressed_key() {
+    let mut machine = machine(ECHO_KEY, Variant::Chip8);
+    machine.run_script("wait 5; tap A; wait 5").unwrap();
+    assert_eq!(machine.v(0), 0xA);
+    machine.assert_pixels(0, 0, "
+        ####.
+        #..#.
+        ####.
+        #..#.
+        #..#.
+        .....
+    ");
+    machine.assert_golden("tests/golden/echo-key-a.txt");
+}
+
+#[test]
+fn waits_without_a_key() {
+    let mut machine = machine(ECHO_KEY, Variant::Chip8);
+    machine.run_frames(30).unwrap();
+    assert_eq!(machine.pc(), 0x200);
+    assert!(machine.screen().chars().all(|c| c == '.' || c == '\n'));
+}
+
+#[test]
+fn bcd_goes_to_memory() {
+    let mut machine = machine("
+: main
+    v3 := 254
+    i := 0x300
+    bcd v3
+    loop again
+", Variant::Chip8);
+    machine.run_frames(1).unwrap();
+    assert_eq!(machine.i(), 0x300);
+    assert_eq!(machine.memory(0x300, 3), &[2, 5, 4]);
+}
+
+#[test]
+#[cfg(feature = "png")]
+fn stops_when_the_program_exits() {
+    let mut machine = machine("
+: main
+    hires
+    v0 := 60
+    v1 := 28
+    i := bighex v0
+    v0 := 0
+    sprite v0 v0 0
+    exit
+", Variant::SuperChip);
+    machine.run_frames(100).unwrap();
+    assert!(machine.stopped());
+    assert_eq!(machine.frames(), 1);
+    machine.assert_golden("tests/golden/schip-exit.png");
+}
+
+#[test]
+#[cfg(feature = "png")]
+fn xochip_planes_in_a_golden_image() {
+    let mut machine = machine("
+: main
+    plane 1
+    i := hex v0
+    sprite v0 v0 5
+    plane 2
+    v1 := 2
+    sprite v1 v1 5
+    plane 3
+    v2 := 40
+    sprite v2 v1 5
+    loop again
+", Variant::XoChip);
+    machine.run_frames(2).unwrap();
+    assert_eq!(machine.pixel(0, 0), 1);
+    assert_eq!(machine.pixel(3, 2), 3);
+    assert_eq!(machine.pixel(5, 6), 2);
+    machine.assert_golden("tests/golden/xochip-planes.png");
+}
+
+#[test]
+#[should_panic(expected = "pixels at (0, 0) don't match")]
+fn mismatch_panics() {
+    let mut machine = machine(ECHO_KEY, Variant::Chip8);
+    machine.run_script("tap 3; wait 2").unwrap();
+    machine.assert_pixels(0, 0, "
+        ####
+        #..#
+    ");
+}
+
+#[test]
+fn bad_scripts_are_rejected() {
+    let mut machine = machine(ECHO_KEY, Variant::Chip8);
+    assert!(machine.run_script("tap 10").is_err());
+    assert!(machine.run_script("wait soon").is_err());
+    assert!(machine.run_script("jump").is_err());
+}
@@ -0,0 +1,176 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/movie.rs file
+//
+
+use chip8::asm::assemble;
+use chip8::backend::{KeypadSource, NullAudio, NullDisplay};
+use chip8::keypad::{Keypad, VKeys};
+use chip8::movie::{Movie, MoviePlayer, MovieRecorder, MAX_MOVIE_FRAMES, MOVIE_HEADER};
+use chip8::rng::RngKind;
+use chip8::{Chip8Error, Cpu, Quirks, RomCartridge, Variant};
+
+const FRAMES: usize = 240;
+const SPEED: usize = 10;
+
+/// Draws a dot at a random spot, waits for a key, adds it up in V3 and
+/// draws its digit there. Whatever ends up on the screen depends on both
+/// the random numbers and the keys.
+fn rom() -> Vec<u8> {
+    assemble("
+        loop:
+            RND V0, 0x3F
+            RND V1, 0x1F
+            LD I, dot
+            DRW V0, V1, 1
+            LD V2, K
+            ADD V3, V2
+            LD F, V2
+            DRW V0, V1, 5
+            JP loop
+        dot: DB 0x80
+    ").unwrap()
+}
+
+/// A player pressing a different key every few frames.
+struct Player {
+    frame: usize,
+}
+
+impl KeypadSource for Player {
+    fn poll_keys(&mut self, keypad: &mut Keypad) {
+        keypad.release_all();
+        if self.frame % 12 < 4 {
+            keypad.set_key(VKeys::from_num((self.frame / 12 % 16) as u8).unwrap(), true);
+        }
+        self.frame += 1;
+    }
+}
+
+fn machine(keypad: Box<dyn KeypadSource>) -> Cpu {
+    let mut cpu = Cpu::with_backends(RomCartridge::from_bytes(&rom()).unwrap(), Box::new(NullDisplay), keypad, Box::new(NullAudio));
+    cpu.set_variant(Variant::SuperChip);
+    cpu.set_quirks(Quirks::superchip_1_0());
+    cpu.load_instructions().unwrap();
+    cpu
+}
+
+/// Record a run, return the movie and the state the machine ended in.
+fn record(seed: u64) -> (Movie, Vec<u8>) {
+    let recorder = MovieRecorder::new(Box::new(Player { frame: 0 }));
+    let recording = recorder.recording();
+    let mut cpu = machine(Box::new(recorder));
+    let mut movie = Movie::start(&mut cpu, seed, SPEED);
+    for _ in 0..FRAMES {
+        cpu.run_frame(SPEED).unwrap();
+    }
+    movie.frames = recording.borrow().clone();
+    (movie, cpu.save_state())
+}
+
+/// Play a movie back on a fresh machine, return the state it ended in.
+fn play(movie: &Movie) -> Vec<u8> {
+    let mut cpu = machine(Box::new(Movie::from_text(&movie.to_text()).map(|movie| MoviePlayer::new(&movie, None)).unwrap()));
+    movie.prepare(&mut cpu).unwrap();
+    let mut frames = 0;
+    while !cpu.chip8.keypad_source.quit_requested() {
+        cpu.run_frame(movie.instructions_per_frame).unwrap();
+        frames += 1;
+    }
+    assert_eq!(frames, FRAMES);
+    cpu.save_state()
+}
+
+#[test]
+fn playback_ends_where_the_recording_did() {
+    let (movie, recorded) = record(1234);
+    assert_eq!(movie.frames.len(), FRAMES);
+    assert_eq!(movie.seed, 1234);
+    assert!(play(&movie) == recorded);
+    // and again, nothing carries over between runs
+    assert!(play(&movie) == recorded);
+}
+
+#[test]
+fn the_seed_decides_the_random_numbers() {
+    let (first, first_state) = record(1);
+    let (second, second_state) = record(1);
+    assert_eq!(first, second);
+    assert!(first_state == second_state);
+
+    let (other, other_state) = record(2);
+    assert_eq!(other.frames, first.frames);
+    assert!(other_state != first_state);
+    assert!(play(&other) == other_state);
+}
+
+#[test]
+fn text_round_trip() {
+    let movie = Movie {
+        rom_hash:               0x9f2c6e0c1d3b5a47,
+        variant:                Variant::XoChip,
+        quirks:                 Quirks::chip48(),
+        rng:                    RngKind::FontWalk,
+        seed:                   u64::MAX,
+        instructions_per_frame: 1000,
+        frames:                 [vec![0; 120], vec![0x20; 4], vec![0x8001; 1], vec![0; 3]].concat(),
+    };
+    let text = movie.to_text();
+    let frames: Vec<&str> = text.lines().skip_while(|line| *line != "frames").skip(1).collect();
+    assert_eq!(frames, ["0000 120", "0020 4", "8001 1", "0000 3"]);
+    assert_eq!(text.lines().next(), Some(MOVIE_HEADER));
+    assert_eq!(Movie::from_text(&text).unwrap(), movie);
+
+    // an empty movie
+    let empty = Movie { frames: Vec::new(), ..movie };
+    assert_eq!(Movie::from_text(&empty.to_text()).unwrap(), empty);
+}
+
+fn movie_error(text: &str) -> String {
+    match Movie::from_text(text) {
+        Err(Chip8Error::InvalidMovie(error))    => error,
+        Err(error)                              => panic!("{:?}: {}", text, error),
+        Ok(movie)                               => panic!("{:?} read as {:?}", text, movie),
+    }
+}
+
+#[test]
+fn broken_movies_name_the_line() {
+    let header = format!("{}\nrom 0123456789abcdef\n", MOVIE_HEADER);
+    let cases = [
+        (format!("{}\nrom xyz\n", MOVIE_HEADER),                "line 2: invalid ROM hash"),
+        (format!("{}variant chip9\nframes\n", header),          "line 3: "),
+        (format!("{}quirks shift = sideways\n", header),        "line 3: "),
+        (format!("{}rng dice\n", header),                       "line 3: "),
+        (format!("{}seed -1\n", header),                        "line 3: invalid seed"),
+        (format!("{}\nspeed fast\n", header),                   "line 4: invalid speed"),
+        (format!("{}volume 11\n", header),                      "line 3: unknown setting: volume"),
+        (format!("{}frames\n0000 10\n0001\n", header),          "line 5: expected keys and a frame count"),
+        (format!("{}frames\n0000 10\n\nkey 3\n", header),       "line 6: invalid keys"),
+        (format!("{}frames\n0000 ten\n", header),               "line 4: invalid frame count"),
+        (format!("{}frames\n0000 {}\n", header, usize::MAX),    "line 4: frame count above"),
+        (format!("{}frames\n0000 {}\n0001 {}\n0000 1\n", header, MAX_MOVIE_FRAMES / 2, MAX_MOVIE_FRAMES / 2),
+                                                                "line 6: movie longer than"),
+    ];
+    for (text, message) in cases {
+        let error = movie_error(&text);
+        assert!(error.starts_with(message), "{:?}: {}", text, error);
+    }
+
+    assert!(movie_error("chip8-movie 2\nrom 0\nframes\n").contains("not a movie file"));
+    assert!(movie_error("").contains("not a movie file"));
+    assert!(movie_error(&format!("{}\nseed 1\nframes\n", MOVIE_HEADER)).contains("no ROM hash"));
+}
+
+#[test]
+fn movies_only_play_on_their_rom() {
+    let (movie, _) = record(1);
+    let mut cpu = Cpu::new(RomCartridge::from_bytes(&[0x12, 0x00]).unwrap());
+    match movie.prepare(&mut cpu) {
+        Err(Chip8Error::InvalidMovie(error))    => assert!(error.contains("different ROM"), "{}", error),
+        result                                  => panic!("{:?}", result.err()),
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/octo.rs file
+//
+
+use chip8::octo::compile;
+use chip8::Variant;
+
+/// Compile a program that starts with `: main`, so no jump to it comes
+/// first and the statements start at 0x200.
+fn main(body: &str, variant: Variant) -> Vec<u8> {
+    let source = format!(": main\n{}", body);
+    compile(&source, variant).unwrap_or_else(|error| panic!("{:?}: {}", body, error))
+}
+
+fn words(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+#[test]
+fn statements() {
+    let cases: &[(&str, &[u16])] = &[
+        ("clear return ;",                  &[0x00E0, 0x00EE, 0x00EE]),
+        ("v3 := 0x42 v3 += 5 v3 -= 1",      &[0x6342, 0x7305, 0x73FF]),
+        ("v1 := v2 v1 |= v2 v1 &= v2",      &[0x8120, 0x8121, 0x8122]),
+        ("v1 ^= v2 v1 += v2 v1 -= v2",      &[0x8123, 0x8124, 0x8125]),
+        ("v1 >>= v2 v1 =- v2 v1 <<= v2",    &[0x8126, 0x8127, 0x812E]),
+        ("v4 := random 0x0F",               &[0xC40F]),
+        ("v4 := delay v4 := key",           &[0xF407, 0xF40A]),
+        ("delay := v2 buzzer := v2",        &[0xF215, 0xF218]),
+        ("i := 0x300 i += v5 i := hex v6",  &[0xA300, 0xF51E, 0xF629]),
+        ("bcd v7 save v8 load v8",          &[0xF733, 0xF855, 0xF865]),
+        ("sprite v1 v2 5",                  &[0xD125]),
+        ("jump0 0x300 native 0x123",        &[0xB300, 0x0123]),
+        // a call by name, to a label further down
+        ("sub jump main : sub return",      &[0x2204, 0x1200, 0x00EE]),
+        ("# a comment\nclear # and another",&[0x00E0]),
+    ];
+    for (body, expected) in cases {
+        assert_eq!(main(body, Variant::Chip8), words(expected), "{}", body);
+    }
+}
+
+#[test]
+fn main_is_jumped_to_unless_it_comes_first() {
+    assert_eq!(compile("clear\n: main\nreturn\n", Variant::Chip8).unwrap(), words(&[0x1204, 0x00E0, 0x00EE]));
+    assert_eq!(compile(": main\nreturn\n", Variant::Chip8).unwrap(), words(&[0x00EE]));
+}
+
+#[test]
+fn conditions() {
+    let cases: &[(&str, &[u16])] = &[
+        // skip the statement when the condition doesn't hold
+        ("if v1 == 3 then v2 := 1",         &[0x4103, 0x6201]),
+        ("if v1 != 3 then v2 := 1",         &[0x3103, 0x6201]),
+        ("if v1 == v2 then clear",          &[0x9120, 0x00E0]),
+        ("if v1 != v2 then clear",          &[0x5120, 0x00E0]),
+        ("if v1 key then clear",            &[0xE1A1, 0x00E0]),
+        ("if v1 -key then clear",           &[0xE19E, 0x00E0]),
+        // comparisons go through vf
+        ("if v1 < 5 then clear",            &[0x6F05, 0x8F17, 0x4F00, 0x00E0]),
+        ("if v1 >= 5 then clear",           &[0x6F05, 0x8F17, 0x3F00, 0x00E0]),
+        ("if v1 > v2 then clear",           &[0x8F20, 0x8F15, 0x4F00, 0x00E0]),
+        ("if v1 <= v2 then clear",          &[0x8F20, 0x8F15, 0x3F00, 0x00E0]),
+        ("if v1 > 5 then clear",            &[0x6F05, 0x8F15, 0x4F00, 0x00E0]),
+    ];
+    for (body, expected) in cases {
+        assert_eq!(main(body, Variant::Chip8), words(expected), "{}", body);
+    }
+}
+
+#[test]
+fn blocks() {
+    let cases: &[(&str, &[u16])] = &[
+        ("if v0 == 1 begin v1 := 1 end clear",
+            &[0x3001, 0x1206, 0x6101, 0x00E0]),
+        ("if v0 == 1 begin v1 := 1 else v1 := 2 end clear",
+            &[0x3001, 0x1208, 0x6101, 0x120A, 0x6102, 0x00E0]),
+        ("loop v0 += 1 while v0 != 10 again clear",
+            &[0x7001, 0x400A, 0x1208, 0x1200, 0x00E0]),
+        // nested, the while leaves the inner loop only
+        ("loop loop while v0 key again if v1 == 2 begin clear end again",
+            &[0xE09E, 0x1206, 0x1200, 0x3102, 0x120C, 0x00E0, 0x1200]),
+    ];
+    for (body, expected) in cases {
+        assert_eq!(main(body, Variant::Chip8), words(expected), "{}", body);
+    }
+}
+
+#[test]
+fn directives() {
+    let cases: &[(&str, Vec<u8>)] = &[
+        // right to left without precedence: { 3 * 2 + 1 } is 9
+        (":const SPEED 3 :alias x v5 :calc HALF { SPEED * 2 + 1 } x := SPEED x += HALF :byte { HALF - 1 } :byte 255",
+            vec![0x65, 0x03, 0x75, 0x09, 0x08, 0xFF]),
+        ("0xF0 0b1001 -1 7",                vec![0xF0, 0x09, 0xFF, 0x07]),
+        (":macro twice reg { reg += 1 reg += 1 } twice v3 twice v4",
+            words(&[0x7301, 0x7301, 0x7401, 0x7401])),
+        (":unpack 0xA data : data",         vec![0x60, 0xA2, 0x61, 0x04]),
+        (":pointer data : data",            vec![0x02, 0x02]),
+        // the label is the second byte of the next instruction
+        (":next value v0 := 7 i := value",  words(&[0x6007, 0xA201])),
+        (":org 0x204 clear",                vec![0, 0, 0, 0, 0x00, 0xE0]),
+        (":breakpoint here :monitor v0 2 clear", words(&[0x00E0])),
+        ("0x12 :byte { @ 0x200 + 1 } i := { HERE }", vec![0x12, 0x13, 0xA2, 0x02]),
+        // parentheses group, otherwise { 1 << 3 | 1 } is 1 << 3
+        (":calc X { ( 1 + 2 ) * 4 } :calc Y { 1 << 3 | 1 } :byte X :byte Y :byte { 7 min 3 }",
+            vec![0x0C, 0x08, 0x03]),
+    ];
+    for (body, expected) in cases {
+        assert_eq!(main(body, Variant::Chip8), *expected, "{}", body);
+    }
+}
+
+#[test]
+fn superchip_and_xochip_statements() {
+    let superchip = "hires lores exit scroll-left scroll-right scroll-down 3 saveflags v2 loadflags v2 i := bighex v1 sprite v0 v0 0";
+    assert_eq!(main(superchip, Variant::SuperChip), words(&[
+        0x00FF, 0x00FE, 0x00FD, 0x00FC, 0x00FB, 0x00C3, 0xF275, 0xF285, 0xF130, 0xD000,
+    ]));
+    let xochip = "scroll-up 2 plane 3 audio pitch := v4 save v1 - v3 load v1 - v3 i := long data : data :unpack long data";
+    assert_eq!(main(xochip, Variant::XoChip), words(&[
+        0x00D2, 0xF301, 0xF002, 0xF43A, 0x5132, 0x5133, 0xF000, 0x0210, 0x6002, 0x6110,
+    ]));
+    // XO-CHIP has all of SUPER-CHIP
+    assert_eq!(main(superchip, Variant::XoChip), main(superchip, Variant::SuperChip));
+}
+
+#[test]
+fn errors_name_the_line() {
+    let cases = [
+        (": main\nclear\nsub\n",                        Variant::Chip8,     3,  "undefined label: sub"),
+        (": main\n  clear\n  ???\n",                    Variant::Chip8,     3,  "unknown statement: ???"),
+        (": main\n: a\n: a\n",                          Variant::Chip8,     3,  "label a defined twice"),
+        (": main\n: 1abc\n",                            Variant::Chip8,     2,  "invalid label name"),
+        (": main\nv0 := 256\n",                         Variant::Chip8,     2,  "doesn't fit into 8 bits"),
+        (": main\nsprite v0 v1 16\n",                   Variant::Chip8,     2,  "doesn't fit into 4 bits"),
+        (": main\nv0 *= 2\n",                           Variant::Chip8,     2,  "invalid operation"),
+        (": main\nv0 *= v1\n",                          Variant::Chip8,     2,  "unknown register operation"),
+        (": main\n\nhires\n",                           Variant::Chip8,     3,  "needs"),
+        (": main\nsprite v0 v1 0\n",                    Variant::Chip8,     2,  "needs"),
+        (": main\ni := long main\n",                    Variant::SuperChip, 2,  "needs"),
+        (": main\nsave v0 - v3\n",                      Variant::SuperChip, 2,  "needs"),
+        (": main\nif v0 == 1 begin\nclear\n",           Variant::Chip8,     3,  "begin without end"),
+        (": main\nloop\nclear\n",                       Variant::Chip8,     3,  "loop without again"),
+        (": main\nclear\nelse\n",                       Variant::Chip8,     3,  "else without begin"),
+        (": main\nend\n",                               Variant::Chip8,     2,  "end without begin"),
+        (": main\nagain\n",                             Variant::Chip8,     2,  "again without loop"),
+        (": main\nwhile v0 == 1\n",                     Variant::Chip8,     2,  "while outside of a loop"),
+        (": main\nif v0 == 1\nclear\n",                 Variant::Chip8,     2,  "if without then or begin"),
+        (": main\nif v0 =< 1 then clear\n",             Variant::Chip8,     2,  "unknown comparison"),
+        (": main\n:org 0x100\n",                        Variant::Chip8,     2,  "below 0x200"),
+        (": main\n:calc X { 1 + }\n",                   Variant::Chip8,     2,  "expression ends early"),
+        (": main\n:calc X { ( 1 + 2 }\n",               Variant::Chip8,     2,  "missing )"),
+        (": main\n:calc X { 1 foo 2 }\n",               Variant::Chip8,     2,  "unknown operator in expression: foo"),
+        (": main\n:calc X { nope }\n",                  Variant::Chip8,     2,  "unknown name in expression: nope"),
+        (": main\ni := { 5000 }\n",                     Variant::Chip8,     2,  "use i := long"),
+        // the line of the jump, not of the label
+        (": main\njump far\n:org 0x1000\n: far\n",      Variant::XoChip,    2,  "above 0xFFF"),
+        (": main\nclear\njump 0x1234\n",                Variant::XoChip,    3,  "outside 0x000-0xFFF"),
+        (": main\njump0 -2\n",                          Variant::Chip8,     2,  "outside 0x000-0xFFF"),
+        (": main\nclear\nv0 :=\n",                      Variant::Chip8,     3,  "unexpected end of file"),
+    ];
+    for (source, variant, line, message) in cases {
+        let error = match compile(source, variant) {
+            Ok(rom)     => panic!("{:?} compiled to {:02X?}", source, rom),
+            Err(error)  => error,
+        };
+        assert_eq!(error.line, line, "{:?}: {}", source, error);
+        assert!(error.message.contains(message), "{:?}: {}", source, error);
+    }
+}
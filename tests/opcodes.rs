@@ -0,0 +1,232 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/opcodes.rs file
+//
+
+use chip8::harness::{run_opcode, MachineState};
+use chip8::quirks::MemoryIncrement;
+use chip8::rng::{Rng, RngKind};
+use chip8::{Chip8Error, Quirks, Variant};
+
+type Preset = (&'static str, Variant, fn() -> Quirks);
+
+/// Every quirk preset with the variant it runs on.
+const PRESETS: [Preset; 5] = [
+    ("COSMAC VIP",      Variant::Chip8,     Quirks::cosmac_vip),
+    ("CHIP-48",         Variant::Chip8,     Quirks::chip48),
+    ("SUPER-CHIP 1.0",  Variant::SuperChip, Quirks::superchip_1_0),
+    ("SUPER-CHIP 1.1",  Variant::SuperChip, Quirks::superchip_1_1),
+    ("XO-CHIP",         Variant::XoChip,    Quirks::xo_chip),
+];
+
+struct Case {
+    name:   &'static str,
+    opcode: u16,
+    before: MachineState,
+    after:  MachineState,
+}
+
+fn case(name: &'static str, opcode: u16, before: MachineState, after: MachineState) -> Case {
+    Case { name, opcode, before, after }
+}
+
+fn state() -> MachineState {
+    MachineState::new()
+}
+
+/// The first byte of the generator `MachineState::setup` seeds with 0.
+fn seeded_byte() -> u8 {
+    Rng::new(RngKind::default(), 0).next_byte(&[])
+}
+
+/// The instructions of plain CHIP-8, with the results the quirks ask for.
+fn chip8_cases(quirks: &Quirks) -> Vec<Case> {
+    let s = state();
+    let logic_vf = if quirks.logic_resets_vf { 0 } else { 0x55 };
+    let mut deepest = [0x202; 16];
+    deepest[15] = 0x212;
+    let mut cases = vec![
+        case("00E0 clears the screen", 0x00E0, s.clone().lit(&[(0, 0), (63, 31)]), s.clone().pc(0x202)),
+        case("00EE returns to the address on the stack", 0x00EE, s.clone().pc(0x400).stack(&[0x2AA, 0x302]), s.clone().pc(0x302).stack(&[0x2AA])),
+        case("1nnn jumps", 0x1345, s.clone(), s.clone().pc(0x345)),
+        case("2nnn pushes the address after it", 0x2345, s.clone().pc(0x210), s.clone().pc(0x345).stack(&[0x212])),
+        case("2nnn fills the 16th stack slot", 0x2345, s.clone().pc(0x210).stack(&[0x202; 15]), s.clone().pc(0x345).stack(&deepest)),
+        case("3xkk skips if equal", 0x3112, s.clone().v(1, 0x12), s.clone().v(1, 0x12).pc(0x204)),
+        case("3xkk doesn't skip if different", 0x3112, s.clone().v(1, 0x13), s.clone().v(1, 0x13).pc(0x202)),
+        case("4xkk skips if different", 0x4112, s.clone().v(1, 0x13), s.clone().v(1, 0x13).pc(0x204)),
+        case("4xkk doesn't skip if equal", 0x4112, s.clone().v(1, 0x12), s.clone().v(1, 0x12).pc(0x202)),
+        case("5xy0 skips if equal", 0x5120, s.clone().v(1, 7).v(2, 7), s.clone().v(1, 7).v(2, 7).pc(0x204)),
+        case("5xy0 doesn't skip if different", 0x5120, s.clone().v(1, 7).v(2, 8), s.clone().v(1, 7).v(2, 8).pc(0x202)),
+        case("6xkk loads", 0x61AB, s.clone(), s.clone().v(1, 0xAB).pc(0x202)),
+        case("7xkk adds", 0x7105, s.clone().v(1, 10), s.clone().v(1, 15).pc(0x202)),
+        case("7xkk wraps around and leaves VF alone", 0x7102, s.clone().v(1, 0xFF).v(0xF, 0x55), s.clone().v(1, 1).v(0xF, 0x55).pc(0x202)),
+        case("8xy0 copies", 0x8120, s.clone().v(2, 9), s.clone().v(1, 9).v(2, 9).pc(0x202)),
+        case("8xy1 ors", 0x8121, s.clone().v(1, 0x0F).v(2, 0x30).v(0xF, 0x55), s.clone().v(1, 0x3F).v(2, 0x30).v(0xF, logic_vf).pc(0x202)),
+        case("8xy2 ands", 0x8122, s.clone().v(1, 0x3C).v(2, 0x0F).v(0xF, 0x55), s.clone().v(1, 0x0C).v(2, 0x0F).v(0xF, logic_vf).pc(0x202)),
+        case("8xy3 xors", 0x8123, s.clone().v(1, 0x3C).v(2, 0x0F).v(0xF, 0x55), s.clone().v(1, 0x33).v(2, 0x0F).v(0xF, logic_vf).pc(0x202)),
+        case("8xy4 adds without carry", 0x8124, s.clone().v(1, 10).v(2, 20).v(0xF, 0x55), s.clone().v(1, 30).v(2, 20).pc(0x202)),
+        case("8xy4 adds with carry", 0x8124, s.clone().v(1, 200).v(2, 100), s.clone().v(1, 44).v(2, 100).v(0xF, 1).pc(0x202)),
+        case("8xy4 into VF keeps the carry", 0x8F24, s.clone().v(0xF, 200).v(2, 100), s.clone().v(0xF, 1).v(2, 100).pc(0x202)),
+        case("8xy4 from VF adds its old value", 0x81F4, s.clone().v(1, 10).v(0xF, 20), s.clone().v(1, 30).pc(0x202)),
+        case("8xy5 subtracts without borrow", 0x8125, s.clone().v(1, 20).v(2, 10), s.clone().v(1, 10).v(2, 10).v(0xF, 1).pc(0x202)),
+        case("8xy5 subtracts with borrow", 0x8125, s.clone().v(1, 10).v(2, 20).v(0xF, 0x55), s.clone().v(1, 246).v(2, 20).pc(0x202)),
+        case("8xy5 of equal values doesn't borrow", 0x8125, s.clone().v(1, 10).v(2, 10), s.clone().v(2, 10).v(0xF, 1).pc(0x202)),
+        case("8xy5 into VF keeps the flag", 0x8F25, s.clone().v(0xF, 10).v(2, 20), s.clone().v(2, 20).pc(0x202)),
+        case("8xy7 subtracts the other way", 0x8127, s.clone().v(1, 10).v(2, 20), s.clone().v(1, 10).v(2, 20).v(0xF, 1).pc(0x202)),
+        case("8xy7 with borrow", 0x8127, s.clone().v(1, 20).v(2, 10).v(0xF, 0x55), s.clone().v(1, 246).v(2, 10).pc(0x202)),
+        case("8xy7 into VF keeps the flag", 0x8F27, s.clone().v(0xF, 10).v(2, 20), s.clone().v(0xF, 1).v(2, 20).pc(0x202)),
+        case("9xy0 skips if different", 0x9120, s.clone().v(1, 7).v(2, 8), s.clone().v(1, 7).v(2, 8).pc(0x204)),
+        case("9xy0 doesn't skip if equal", 0x9120, s.clone().v(1, 7).v(2, 7), s.clone().v(1, 7).v(2, 7).pc(0x202)),
+        case("Annn loads I", 0xA123, s.clone(), s.clone().i(0x123).pc(0x202)),
+        case("Cxkk masks the random byte", 0xC10F, s.clone().v(1, 0xFF), s.clone().v(1, seeded_byte() & 0x0F).pc(0x202)),
+        case("Dxyn draws a sprite", 0xD121, s.clone().i(0x300).mem(0x300, &[0xF0]).v(0xF, 0x55), s.clone().i(0x300).mem(0x300, &[0xF0]).lit(&[(0, 0), (1, 0), (2, 0), (3, 0)]).pc(0x202)),
+        case("Dxyn flips pixels and reports the collision", 0xD121, s.clone().i(0x300).mem(0x300, &[0xC0]).lit(&[(0, 0), (5, 5)]), s.clone().i(0x300).mem(0x300, &[0xC0]).lit(&[(1, 0), (5, 5)]).v(0xF, 1).pc(0x202)),
+        case("Dxyn wraps the start onto the screen", 0xD121, s.clone().i(0x300).mem(0x300, &[0x80]).v(1, 66).v(2, 33), s.clone().i(0x300).mem(0x300, &[0x80]).v(1, 66).v(2, 33).lit(&[(2, 1)]).pc(0x202)),
+        case("Dxyn reads VF as a coordinate before clearing it", 0xDF11, s.clone().i(0x300).mem(0x300, &[0x80]).v(0xF, 3),
+            s.clone().i(0x300).mem(0x300, &[0x80]).lit(&[(3, 0)]).pc(0x202)),
+        case("Ex9E skips if the key is down", 0xE19E, s.clone().v(1, 7).keys(&[7]), s.clone().v(1, 7).pc(0x204)),
+        case("Ex9E doesn't skip if the key is up", 0xE19E, s.clone().v(1, 7).keys(&[6]), s.clone().v(1, 7).pc(0x202)),
+        case("ExA1 skips if the key is up", 0xE1A1, s.clone().v(1, 7), s.clone().v(1, 7).pc(0x204)),
+        case("ExA1 doesn't skip if the key is down", 0xE1A1, s.clone().v(1, 7).keys(&[7]), s.clone().v(1, 7).pc(0x202)),
+        case("Ex9E only looks at the low nibble", 0xE19E, s.clone().v(1, 0xF7).keys(&[7]), s.clone().v(1, 0xF7).pc(0x204)),
+        case("ExA1 only looks at the low nibble", 0xE1A1, s.clone().v(1, 0x17).keys(&[7]), s.clone().v(1, 0x17).pc(0x202)),
+        case("Fx07 reads the delay timer", 0xF107, s.clone().dt(42), s.clone().dt(42).v(1, 42).pc(0x202)),
+        case("Fx0A waits while no key is down", 0xF10A, s.clone(), s.clone()),
+        case("Fx0A stores the key", 0xF10A, s.clone().keys(&[0xB]), s.clone().v(1, 0xB).pc(0x202)),
+        case("Fx15 sets the delay timer", 0xF115, s.clone().v(1, 42), s.clone().v(1, 42).dt(42).pc(0x202)),
+        case("Fx18 sets the sound timer", 0xF118, s.clone().v(1, 42), s.clone().v(1, 42).st(42).pc(0x202)),
+        case("Fx1E adds to I", 0xF11E, s.clone().v(1, 0x10).i(0x2F8), s.clone().v(1, 0x10).i(0x308).pc(0x202)),
+        case("Fx29 points I at the digit", 0xF129, s.clone().v(1, 0xA), s.clone().v(1, 0xA).i(50).pc(0x202)),
+        case("Fx29 only looks at the low nibble", 0xF129, s.clone().v(1, 0x3A), s.clone().v(1, 0x3A).i(50).pc(0x202)),
+        case("Fx33 stores the decimal digits", 0xF133, s.clone().v(1, 254).i(0x300), s.clone().v(1, 254).i(0x300).mem(0x300, &[2, 5, 4]).pc(0x202)),
+        case("Fx33 of a single digit", 0xF133, s.clone().v(1, 7).i(0x300), s.clone().v(1, 7).i(0x300).mem(0x300, &[0, 0, 7]).pc(0x202)),
+    ];
+
+    let (right, right_flag) = if quirks.shift_uses_vy { (0x02, 1) } else { (0x06, 0) };
+    cases.push(case("8xy6 shifts right", 0x8126, s.clone().v(1, 0x0C).v(2, 0x05), s.clone().v(1, right).v(2, 0x05).v(0xF, right_flag).pc(0x202)));
+    let (left, left_flag) = if quirks.shift_uses_vy { (0x80, 0) } else { (0x02, 1) };
+    cases.push(case("8xyE shifts left", 0x812E, s.clone().v(1, 0x81).v(2, 0x40), s.clone().v(1, left).v(2, 0x40).v(0xF, left_flag).pc(0x202)));
+    cases.push(case("8xy6 into VF keeps the flag", 0x8FF6, s.clone().v(0xF, 0x05), s.clone().v(0xF, 1).pc(0x202)));
+    cases.push(case("8xyE into VF keeps the flag", 0x8FFE, s.clone().v(0xF, 0x81), s.clone().v(0xF, 1).pc(0x202)));
+
+    let target = if quirks.jump_uses_vx { 0x315 } else { 0x312 };
+    cases.push(case("Bnnn jumps with an offset", 0xB310, s.clone().v(0, 2).v(3, 5), s.clone().v(0, 2).v(3, 5).pc(target)));
+
+    let clipped: &[(usize, usize)] = if quirks.clip_sprites { &[(62, 0), (63, 0)] } else { &[(62, 0), (63, 0), (0, 0), (1, 0)] };
+    cases.push(case("Dxyn clips or wraps at the edge", 0xD121, s.clone().i(0x300).mem(0x300, &[0xF0]).v(1, 62), s.clone().i(0x300).mem(0x300, &[0xF0]).v(1, 62).lit(clipped).pc(0x202)));
+
+    let i = match quirks.memory_increment {
+        MemoryIncrement::None       => 0x300,
+        MemoryIncrement::X          => 0x302,
+        MemoryIncrement::XPlusOne   => 0x303,
+    };
+    cases.push(case("Fx55 stores V0 to Vx", 0xF255,
+        s.clone().v(0, 1).v(1, 2).v(2, 3).v(3, 4).i(0x300).mem(0x300, &[0; 4]),
+        s.clone().v(0, 1).v(1, 2).v(2, 3).v(3, 4).i(i).mem(0x300, &[1, 2, 3, 0]).pc(0x202)));
+    cases.push(case("Fx65 loads V0 to Vx", 0xF265,
+        s.clone().v(3, 9).i(0x300).mem(0x300, &[1, 2, 3, 4]),
+        s.clone().v(0, 1).v(1, 2).v(2, 3).v(3, 9).i(i).mem(0x300, &[1, 2, 3, 4]).pc(0x202)));
+    cases
+}
+
+/// The SUPER-CHIP additions.
+fn superchip_cases() -> Vec<Case> {
+    let s = state();
+    let hires = s.clone().hires(true);
+    vec![
+        case("00Cn scrolls down", 0x00C2, s.clone().lit(&[(3, 4)]), s.clone().lit(&[(3, 6)]).pc(0x202)),
+        case("00FB scrolls right", 0x00FB, s.clone().lit(&[(3, 4)]), s.clone().lit(&[(7, 4)]).pc(0x202)),
+        case("00FC scrolls left", 0x00FC, s.clone().lit(&[(3, 4), (10, 4)]), s.clone().lit(&[(6, 4)]).pc(0x202)),
+        case("00FD exits", 0x00FD, s.clone(), s.clone().exited(true)),
+        case("00FE switches to low resolution", 0x00FE, hires.clone().lit(&[(100, 60)]), s.clone().pc(0x202)),
+        case("00FF switches to high resolution", 0x00FF, s.clone().lit(&[(1, 1)]), hires.clone().pc(0x202)),
+        case("Dxy0 draws a 16x16 sprite", 0xD120, hires.clone().i(0x300).mem(0x300, &[0x80, 0x01]).v(1, 10),
+            hires.clone().i(0x300).mem(0x300, &[0x80, 0x01]).v(1, 10).lit(&[(10, 0), (25, 0)]).pc(0x202)),
+        case("Fx30 points I at the big digit", 0xF130, s.clone().v(1, 3), s.clone().v(1, 3).i(0x50 + 30).pc(0x202)),
+        case("Fx75 saves the RPL flags", 0xF275, s.clone().v(0, 1).v(1, 2).v(2, 3).v(3, 4), s.clone().v(0, 1).v(1, 2).v(2, 3).v(3, 4).rpl(&[1, 2, 3]).pc(0x202)),
+        case("Fx85 restores the RPL flags", 0xF285, s.clone().rpl(&[1, 2, 3, 4]), s.clone().v(0, 1).v(1, 2).v(2, 3).rpl(&[1, 2, 3, 4]).pc(0x202)),
+    ]
+}
+
+/// The XO-CHIP additions.
+fn xochip_cases() -> Vec<Case> {
+    let s = state();
+    let long = s.clone().mem(0x202, &[0xF0, 0x00, 0x12, 0x34]);
+    vec![
+        case("00Dn scrolls up", 0x00D2, s.clone().lit(&[(3, 4)]), s.clone().lit(&[(3, 2)]).pc(0x202)),
+        case("5xy2 stores Vx to Vy", 0x5132, s.clone().v(1, 1).v(2, 2).v(3, 3).i(0x300).mem(0x300, &[0; 4]),
+            s.clone().v(1, 1).v(2, 2).v(3, 3).i(0x300).mem(0x300, &[1, 2, 3, 0]).pc(0x202)),
+        case("5xy2 stores backwards", 0x5312, s.clone().v(1, 1).v(2, 2).v(3, 3).i(0x300).mem(0x300, &[0; 3]),
+            s.clone().v(1, 1).v(2, 2).v(3, 3).i(0x300).mem(0x300, &[3, 2, 1]).pc(0x202)),
+        case("5xy3 loads Vx to Vy", 0x5133, s.clone().i(0x300).mem(0x300, &[1, 2, 3]),
+            s.clone().v(1, 1).v(2, 2).v(3, 3).i(0x300).mem(0x300, &[1, 2, 3]).pc(0x202)),
+        case("F000 nnnn loads a long address", 0xF000, s.clone().mem(0x202, &[0x12, 0x34]), s.clone().mem(0x202, &[0x12, 0x34]).i(0x1234).pc(0x204)),
+        case("skips jump over all of F000 nnnn", 0x3100, long.clone(), long.clone().pc(0x206)),
+        case("Fn01 selects the planes", 0xF301, s.clone(), s.clone().planes(3).pc(0x202)),
+        case("F002 loads the audio pattern", 0xF002, s.clone().i(0x300).mem(0x300, &[0xAA; 16]),
+            s.clone().i(0x300).mem(0x300, &[0xAA; 16]).pattern(&[0xAA; 16]).pc(0x202)),
+        case("Fx3A sets the pitch", 0xF13A, s.clone().v(1, 100), s.clone().v(1, 100).pitch(100).pc(0x202)),
+        case("Dxyn draws on the second plane", 0xD121, s.clone().planes(2).i(0x300).mem(0x300, &[0x80]),
+            s.clone().planes(2).i(0x300).mem(0x300, &[0x80]).pixels(&[(0, 0, 2)]).pc(0x202)),
+        case("Dxyn on both planes reads the data one after the other", 0xD121, s.clone().planes(3).i(0x300).mem(0x300, &[0x80, 0x40]),
+            s.clone().planes(3).i(0x300).mem(0x300, &[0x80, 0x40]).pixels(&[(0, 0, 1), (1, 0, 2)]).pc(0x202)),
+    ]
+}
+
+#[test]
+fn every_opcode_on_every_preset() {
+    let mut failures = Vec::new();
+    for (preset, variant, quirks) in PRESETS {
+        let quirks = quirks();
+        let mut cases = chip8_cases(&quirks);
+        if variant.has_superchip() {
+            cases.extend(superchip_cases());
+        }
+        if variant.has_xochip() {
+            cases.extend(xochip_cases());
+        }
+        for case in cases {
+            let result = run_opcode(variant, quirks, case.opcode, &case.before, &case.after);
+            match result.map(|actual| case.after.diff(&actual)) {
+                Ok(diff) if diff.is_empty() => (),
+                Ok(diff)    => failures.push(format!("{}: {:04X} {}\n    {}", preset, case.opcode, case.name, diff.join("\n    "))),
+                Err(error)  => failures.push(format!("{}: {:04X} {}\n    {}", preset, case.opcode, case.name, error)),
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{} failed:\n{}", failures.len(), failures.join("\n"));
+}
+
+#[test]
+fn cxkk_keeps_only_the_masked_bits() {
+    let seeded = seeded_byte();
+    // a byte with bits both inside and outside every mask, or the masks prove nothing
+    assert!(seeded & 0xF0 != 0 && seeded & 0x0F != 0, "seed 0 gives {:#04X}", seeded);
+    for mask in [0x0F, 0xF0, 0x81, 0x3C, 0xFF] {
+        let before = state().v(1, !(seeded & mask));
+        let actual = run_opcode(Variant::Chip8, Quirks::cosmac_vip(), 0xC100 | mask as u16, &before, &state()).unwrap();
+        assert_eq!(actual.v[1], seeded & mask, "mask {:#04X}", mask);
+        assert_eq!(actual.v[1] & !mask, 0, "mask {:#04X}", mask);
+    }
+}
+
+#[test]
+fn opcodes_that_fail() {
+    let s = state();
+    let full_stack = [0x202; 16];
+    let cases = [
+        ("00EE with an empty stack", 0x00EE, s.clone(), Chip8Error::StackUnderflow),
+        ("2nnn with a full stack", 0x2300, s.clone().stack(&full_stack), Chip8Error::StackOverflow),
+        ("Fx33 past the end of memory", 0xF133, s.clone().i(0xFFE), Chip8Error::MemoryOutOfBounds(0x1000)),
+        ("Fx55 past the end of memory", 0xF155, s.clone().i(0xFFF), Chip8Error::MemoryOutOfBounds(0x1000)),
+    ];
+    for (name, opcode, before, error) in cases {
+        match run_opcode(Variant::Chip8, Quirks::cosmac_vip(), opcode, &before, &before) {
+            Err(actual) => assert_eq!(actual.to_string(), error.to_string(), "{}", name),
+            Ok(_)       => panic!("{} didn't fail", name),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/rewind.rs file
+//
+
+use chip8::harness::TestMachine;
+use chip8::rewind::RewindBuffer;
+use chip8::rng::{Rng, RngKind};
+use chip8::Variant;
+
+/// Run `frames` frames, recording each one, and return the saved state of
+/// every recorded frame.
+fn record(machine: &mut TestMachine, buffer: &mut RewindBuffer, frames: usize) -> Vec<Vec<u8>> {
+    let mut states = Vec::new();
+    for _ in 0..frames {
+        // random code may stop with an error, the state is recorded all the same
+        let _ = machine.run_frames(1);
+        buffer.record(&machine.cpu);
+        states.push(machine.cpu.save_state());
+    }
+    states
+}
+
+/// Machines that change a lot between frames: the opcode test program,
+/// and random XO-CHIP code writing all over its 64K.
+fn machines() -> Vec<(String, TestMachine)> {
+    let mut machines = Vec::new();
+    for variant in [Variant::Chip8, Variant::SuperChip, Variant::XoChip] {
+        machines.push((format!("opcodes.8o on {}", variant), TestMachine::from_file("tests/programs/opcodes.8o", Some(variant)).unwrap()));
+    }
+    let mut rng = Rng::new(RngKind::Xorshift, 3);
+    let rom: Vec<u8> = (0..512).map(|_| rng.next_byte(&[])).collect();
+    let mut machine = TestMachine::new(&rom, Variant::XoChip).unwrap();
+    // keep the random code from exiting or jumping away too early
+    machine.set_speed(4);
+    machines.push(("random XO-CHIP code".to_string(), machine));
+    machines
+}
+
+#[test]
+fn rewinds_to_the_exact_earlier_state() {
+    for (name, mut machine) in machines() {
+        let mut buffer = RewindBuffer::new(100);
+        let states = record(&mut machine, &mut buffer, 40);
+        let recorded = buffer.len();
+        assert_eq!(recorded + 1, states.len(), "{}", name);
+        assert!(states[0] != states[states.len() - 1], "{} didn't run", name);
+        for back in 1..=recorded {
+            assert!(buffer.rewind(&mut machine.cpu).unwrap(), "{}", name);
+            assert!(machine.cpu.save_state() == states[states.len() - 1 - back], "{}: {} frames back", name, back);
+        }
+    }
+}
+
+#[test]
+fn recording_again_after_rewinding() {
+    let mut machine = TestMachine::from_file("tests/programs/opcodes.8o", Some(Variant::XoChip)).unwrap();
+    let mut buffer = RewindBuffer::new(100);
+    let states = record(&mut machine, &mut buffer, 20);
+    for _ in 0..5 {
+        buffer.rewind(&mut machine.cpu).unwrap();
+    }
+    // the machine goes on from frame 14, the frames after it are dropped
+    let replayed = record(&mut machine, &mut buffer, 4);
+    assert!(replayed[..] == states[15..19]);
+    assert_eq!(buffer.len(), 18);
+    for back in 1..=18 {
+        buffer.rewind(&mut machine.cpu).unwrap();
+        assert!(machine.cpu.save_state() == states[18 - back], "{} frames back", back);
+    }
+}
+
+#[test]
+fn resolution_changes_are_rewound() {
+    // HIGH, LOW, HIGH, ... one instruction a frame, so the state changes
+    // size every frame
+    let mut machine = TestMachine::new(&[0x00, 0xFF, 0x00, 0xFE, 0x12, 0x00], Variant::SuperChip).unwrap();
+    machine.set_speed(1);
+    let mut buffer = RewindBuffer::new(10);
+    let states = record(&mut machine, &mut buffer, 9);
+    assert!(states[0].len() != states[1].len());
+    for back in 1..=8 {
+        buffer.rewind(&mut machine.cpu).unwrap();
+        assert!(machine.cpu.save_state() == states[8 - back], "{} frames back", back);
+    }
+}
+
+#[test]
+fn oldest_frames_are_dropped_at_capacity() {
+    let mut machine = TestMachine::from_file("tests/programs/opcodes.8o", Some(Variant::Chip8)).unwrap();
+    let mut buffer = RewindBuffer::new(5);
+    let states = record(&mut machine, &mut buffer, 20);
+    assert_eq!(buffer.len(), 5);
+    for back in 1..=5 {
+        assert!(buffer.rewind(&mut machine.cpu).unwrap());
+        assert!(machine.cpu.save_state() == states[19 - back], "{} frames back", back);
+    }
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn rewinding_past_the_oldest_frame_does_nothing() {
+    let mut machine = TestMachine::from_file("tests/programs/opcodes.8o", Some(Variant::Chip8)).unwrap();
+    let mut buffer = RewindBuffer::new(3);
+    assert!(!buffer.rewind(&mut machine.cpu).unwrap());
+
+    let states = record(&mut machine, &mut buffer, 6);
+    while buffer.rewind(&mut machine.cpu).unwrap() {}
+    let oldest = machine.cpu.save_state();
+    assert!(oldest == states[2]);
+    assert!(!buffer.rewind(&mut machine.cpu).unwrap());
+    assert!(machine.cpu.save_state() == oldest);
+
+    // a single recorded frame has nothing before it either
+    buffer.clear();
+    record(&mut machine, &mut buffer, 1);
+    assert!(buffer.is_empty());
+    assert!(!buffer.rewind(&mut machine.cpu).unwrap());
+}
@@ -0,0 +1,61 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/scheduler.rs file
+//
+
+use chip8::backend::Hotkey;
+use chip8::harness::TestMachine;
+use chip8::scheduler::{HotkeyEvent, Scheduler};
+use chip8::{Chip8Error, Variant};
+
+fn machine() -> TestMachine {
+    TestMachine::new(&[0x12, 0x00], Variant::Chip8).unwrap()
+}
+
+#[test]
+fn speed_hotkeys_report_the_new_speed() {
+    let mut machine = machine();
+    let mut scheduler = Scheduler::new(10);
+    assert!(matches!(scheduler.handle_hotkey(&mut machine.cpu, Hotkey::SpeedUp), Some(HotkeyEvent::Speed(12))));
+    assert!(matches!(scheduler.handle_hotkey(&mut machine.cpu, Hotkey::SlowDown), Some(HotkeyEvent::Speed(10))));
+
+    scheduler.set_deterministic(true);
+    assert!(matches!(scheduler.handle_hotkey(&mut machine.cpu, Hotkey::SpeedUp), Some(HotkeyEvent::NotWhileDeterministic)));
+    assert_eq!(scheduler.instructions_per_frame, 10);
+}
+
+#[test]
+fn break_without_a_debugger() {
+    let mut machine = machine();
+    let mut scheduler = Scheduler::new(10);
+    assert!(matches!(scheduler.handle_hotkey(&mut machine.cpu, Hotkey::Break), Some(HotkeyEvent::DebuggerOff)));
+    assert!(scheduler.handle_hotkey(&mut machine.cpu, Hotkey::Rewind).is_none());
+}
+
+#[test]
+fn save_and_load_slots() {
+    let dir = std::env::temp_dir().join(format!("chip8-scheduler-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let rom = dir.join("game.ch8").display().to_string();
+    let mut machine = machine();
+    let mut scheduler = Scheduler::new(10);
+    // no slots until there is a ROM path to put them next to
+    assert!(scheduler.handle_hotkey(&mut machine.cpu, Hotkey::SaveState(1)).is_none());
+
+    scheduler.set_save_slots(&rom);
+    machine.cpu.chip8.registers.v[3] = 0x33;
+    assert!(matches!(scheduler.handle_hotkey(&mut machine.cpu, Hotkey::SaveState(1)), Some(HotkeyEvent::Saved(1))));
+    machine.cpu.chip8.registers.v[3] = 0x44;
+    assert!(matches!(scheduler.handle_hotkey(&mut machine.cpu, Hotkey::LoadState(1)), Some(HotkeyEvent::Loaded(1))));
+    assert_eq!(machine.v(3), 0x33);
+
+    machine.cpu.chip8.registers.v[3] = 0x44;
+    let failed = scheduler.handle_hotkey(&mut machine.cpu, Hotkey::LoadState(2));
+    assert!(matches!(failed, Some(HotkeyEvent::SlotFailed(2, Chip8Error::StateIo(_)))), "{:?}", failed);
+    assert_eq!(machine.v(3), 0x44);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
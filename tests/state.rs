@@ -0,0 +1,187 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/state.rs file
+//
+
+use chip8::harness::TestMachine;
+use chip8::rng::{Rng, RngKind};
+use chip8::state::STATE_VERSION;
+use chip8::{Chip8Error, Variant};
+
+/// Calls 0x210, which calls 0x214, which spins at 0x216 with two return
+/// addresses on the stack.
+///
+/// ```text
+/// 200: LD V0, 5 / LD V1, 3 / LD I, 0x220 / DRW V0, V1, 5 / CALL 0x210 / JP 0x20A
+/// 210: CALL 0x214 / RET
+/// 214: LD VA, 0x42 / JP 0x216
+/// 220: sprite
+/// ```
+const FIXTURE_ROM: [u8; 37] = [
+    0x60, 0x05, 0x61, 0x03, 0xA2, 0x20, 0xD0, 0x15, 0x22, 0x10, 0x12, 0x0A, 0x00, 0x00, 0x00, 0x00,
+    0x22, 0x14, 0x00, 0xEE, 0x6A, 0x42, 0x12, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xF0, 0x90, 0xF0, 0x90, 0xF0,
+];
+
+// Offsets into a state, see the layout in src/state.rs.
+const VERSION: usize = 4;
+const VARIANT: usize = 14;
+const SP: usize = 41;
+const EXITED: usize = 92;
+const PLANES: usize = 111;
+const PIXELS: usize = 112;
+
+fn fixture_machine() -> TestMachine {
+    TestMachine::new(&FIXTURE_ROM, Variant::Chip8).unwrap()
+}
+
+#[test]
+fn loads_into_a_fresh_machine() {
+    let mut running = fixture_machine();
+    running.cpu.chip8.rng = Rng::new(RngKind::Xorshift, 7);
+    running.run_frames(2).unwrap();
+    running.cpu.chip8.registers.dt = 0x30;
+    let mut machine = fixture_machine();
+    machine.cpu.load_state(&running.cpu.save_state()).unwrap();
+
+    let chip8 = &machine.cpu.chip8;
+    assert_eq!(chip8.registers.pc, 0x216);
+    assert_eq!(chip8.registers.sp, 2);
+    assert_eq!(chip8.stack.stack[..2], [0x20A, 0x212]);
+    assert_eq!(chip8.registers.v[0xA], 0x42);
+    assert_eq!(chip8.registers.i, 0x220);
+    assert_eq!(chip8.registers.dt, 0x30);
+    assert_eq!(chip8.rng, Rng::new(RngKind::Xorshift, 7));
+    machine.assert_pixels(5, 3, "
+        ####
+        #..#
+        ####
+        #..#
+        ####
+    ");
+
+    // return twice from where it spins
+    machine.cpu.chip8.memory.ram[0x216..0x218].copy_from_slice(&[0x00, 0xEE]);
+    machine.cpu.step().unwrap();
+    assert_eq!(machine.pc(), 0x212);
+    machine.cpu.step().unwrap();
+    assert_eq!(machine.pc(), 0x20A);
+    assert_eq!(machine.cpu.chip8.registers.sp, 0);
+}
+
+#[test]
+fn save_and_load_round_trip() {
+    for variant in [Variant::Chip8, Variant::SuperChip, Variant::XoChip] {
+        let mut machine = TestMachine::from_file("tests/programs/opcodes.8o", Some(variant)).unwrap();
+        machine.run_frames(3).unwrap();
+        let saved = machine.cpu.save_state();
+        assert_eq!(u16::from_le_bytes([saved[VERSION], saved[VERSION + 1]]), STATE_VERSION);
+        machine.run_frames(20).unwrap();
+        let later = machine.cpu.save_state();
+        let screen = machine.screen();
+
+        // loading brings back exactly what was saved, and running on from
+        // there ends up in the same place again
+        machine.cpu.load_state(&saved).unwrap();
+        assert!(machine.cpu.save_state() == saved, "{}: loaded state differs", variant);
+        machine.run_frames(20).unwrap();
+        assert!(machine.cpu.save_state() == later, "{}: run after loading differs", variant);
+        assert_eq!(machine.screen(), screen);
+    }
+}
+
+#[test]
+fn loads_states_with_a_full_stack() {
+    let mut machine = fixture_machine();
+    for _ in 0..16 {
+        machine.cpu.chip8.stack_push(0x300).unwrap();
+    }
+    let saved = machine.cpu.save_state();
+    let mut other = fixture_machine();
+    other.cpu.load_state(&saved).unwrap();
+    assert_eq!(other.cpu.chip8.registers.sp, 16);
+}
+
+/// Load a broken state and check it's refused without touching the machine.
+fn refused(bytes: &[u8]) -> Chip8Error {
+    let mut machine = fixture_machine();
+    machine.cpu.chip8.registers.v[3] = 0x33;
+    let before = machine.cpu.save_state();
+    let error = machine.cpu.load_state(bytes).unwrap_err();
+    assert!(machine.cpu.save_state() == before, "{} changed the machine", error);
+    error
+}
+
+fn current() -> Vec<u8> {
+    fixture_machine().cpu.save_state()
+}
+
+#[test]
+fn refuses_broken_states() {
+    let mut bad_magic = current();
+    bad_magic[0] = b'X';
+    let mut future = current();
+    future[VERSION..VERSION + 2].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+    let mut zero = current();
+    zero[VERSION..VERSION + 2].copy_from_slice(&0u16.to_le_bytes());
+    let mut variant = current();
+    variant[VARIANT] = 9;
+    let mut sp_past_the_end = current();
+    sp_past_the_end[SP] = 17;
+    let mut planes = current();
+    planes[PLANES] = 2;
+    let mut pixel = current();
+    pixel[PIXELS + 7] = 4;
+    let mut flag = current();
+    flag[EXITED] = 2;
+    let mut generator = current();
+    let kind = generator.len() - 9;
+    generator[kind] = 2;
+    let mut trailing = current();
+    trailing.push(0);
+    let mut truncated = current();
+    truncated.pop();
+    let mut memory_size = current();
+    // claim the XO-CHIP variant, the memory is still 4K
+    memory_size[VARIANT] = 2;
+
+    let cases: [(&str, Vec<u8>, &str); 13] = [
+        ("bad magic",                   bad_magic,              "not a save state"),
+        ("newer version",               future,                 "unsupported version"),
+        ("version 0",                   zero,                   "unsupported version"),
+        ("unknown variant",             variant,                "unknown variant"),
+        ("stack pointer past 16",       sp_past_the_end,        "stack pointer out of range"),
+        ("planes on CHIP-8",            planes,                 "planes"),
+        ("pixel value",                 pixel,                  "pixel"),
+        ("invalid flag",                flag,                   "invalid flag"),
+        ("unknown generator",           generator,              "unknown random number generator"),
+        ("trailing bytes",              trailing,               "trailing bytes"),
+        ("truncated",                   truncated,              "truncated"),
+        ("wrong memory size",           memory_size,            "bytes of memory"),
+        ("empty",                       Vec::new(),             "truncated"),
+    ];
+    for (name, bytes, message) in cases {
+        match refused(&bytes) {
+            Chip8Error::InvalidState(error) => assert!(error.contains(message), "{}: {}", name, error),
+            error => panic!("{}: {}", name, error),
+        }
+    }
+
+    let mut other_rom = TestMachine::new(&[0x12, 0x00], Variant::Chip8).unwrap();
+    assert!(matches!(other_rom.cpu.load_state(&current()), Err(Chip8Error::StateRomMismatch)));
+}
+
+#[test]
+fn refuses_xochip_planes_past_the_second() {
+    let mut machine = TestMachine::new(&FIXTURE_ROM, Variant::XoChip).unwrap();
+    machine.cpu.chip8.display.planes = 3;
+    let mut bytes = machine.cpu.save_state();
+    machine.cpu.load_state(&bytes).unwrap();
+    bytes[PLANES] = 4;
+    let error = machine.cpu.load_state(&bytes).unwrap_err();
+    assert!(matches!(error, Chip8Error::InvalidState(_)), "{}", error);
+    assert_eq!(machine.cpu.chip8.display.planes, 3);
+}
@@ -0,0 +1,124 @@
+// Copyright 2023 - https://github.com/arongeo
+//
+// Chip-8 Interpreter written in Rust
+// https://github.com/arongeo/chip8
+// 
+// tests/trace.rs file
+//
+
+use chip8::asm::assemble;
+use chip8::harness::{TestMachine, BLESS_VAR};
+use chip8::trace::Tracer;
+use chip8::Variant;
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Goes through a subroutine, a long load and a word that's no instruction,
+/// then fails returning from the top level.
+const PROGRAM: &str = "
+        LD V0, 0x12
+        LD V1, 3
+        CALL sub
+        LD I, LONG data
+        DW 0xE000
+        RET
+    sub:
+        ADD V0, V1
+        LD DT, V0
+        RET
+    data:
+        DB 0xFF
+";
+
+const GOLDEN: &str = "tests/golden/trace.txt";
+
+/// What the tracer wrote, readable after the tracer took it.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+/// Run the program on XO-CHIP with a tracer set up by `setup` until it
+/// fails. Return what was written while it ran and after the tracer was
+/// dropped.
+fn trace(setup: impl FnOnce(&mut Tracer)) -> (String, String) {
+    let output = Output::default();
+    let mut tracer = Tracer::new(Box::new(output.clone()));
+    setup(&mut tracer);
+    let mut machine = TestMachine::new(&assemble(PROGRAM).unwrap(), Variant::XoChip).unwrap();
+    machine.cpu.set_tracer(tracer);
+    while machine.cpu.step().is_ok() {}
+    let running = output.text();
+    machine.cpu.tracer = None;
+    (running, output.text())
+}
+
+#[test]
+fn trace_matches_the_golden_file() {
+    let (running, finished) = trace(|_| ());
+    assert_eq!(running, finished);
+    if std::env::var_os(BLESS_VAR).is_some() {
+        std::fs::write(GOLDEN, &finished).unwrap();
+    }
+    let expected = std::fs::read_to_string(GOLDEN).unwrap();
+    assert_eq!(finished, expected, "trace doesn't match {}, run with {}=1 to update it", GOLDEN, BLESS_VAR);
+}
+
+fn lines(text: &str) -> Vec<String> {
+    text.lines().map(str::to_string).collect()
+}
+
+/// Every line of the unfiltered trace.
+fn full_trace() -> Vec<String> {
+    lines(&trace(|_| ()).1)
+}
+
+#[test]
+fn ranges_filter_by_address() {
+    let (_, finished) = trace(|tracer| {
+        tracer.add_range(0x20E, 0x210);
+        tracer.add_range(0x204, 0x204);
+    });
+    let expected: Vec<String> = full_trace().into_iter().filter(|line| ["0204", "020E", "0210"].contains(&&line[..4])).collect();
+    assert_eq!(expected.len(), 3);
+    assert_eq!(lines(&finished), expected);
+}
+
+#[test]
+fn ring_keeps_the_last_lines_until_finished() {
+    let (running, finished) = trace(|tracer| tracer.set_ring(3));
+    assert_eq!(running, "");
+    let full = full_trace();
+    assert_eq!(lines(&finished), full[full.len() - 3..]);
+
+    // a ring bigger than the trace keeps all of it
+    let (_, finished) = trace(|tracer| tracer.set_ring(100));
+    assert_eq!(lines(&finished), full);
+}
+
+#[test]
+fn ring_of_0_writes_every_line() {
+    let (running, finished) = trace(|tracer| {
+        tracer.set_ring(3);
+        tracer.set_ring(0);
+    });
+    assert_eq!(lines(&running), full_trace());
+    assert_eq!(running, finished);
+}